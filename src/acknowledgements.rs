@@ -0,0 +1,20 @@
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use crate::expiring_keys::ExpiringKeys;
+
+/// Cameras that were acknowledged, and until when their alerts are muted
+static MUTED_CAMERAS: Lazy<ExpiringKeys<String>> = Lazy::new(ExpiringKeys::default);
+
+/// Marks a camera's current event as handled, muting its alerts for a while
+///
+/// # Arguments
+/// * `camera` - Name of the camera that was acknowledged
+/// * `mute_for` - How long to mute the camera's alerts
+pub fn acknowledge(camera: &str, mute_for: Duration) {
+    MUTED_CAMERAS.insert(camera.to_string(), Some(Instant::now() + mute_for));
+}
+
+/// Returns true if alerts for the camera are muted by a recent acknowledgement
+pub fn is_muted(camera: &str) -> bool {
+    MUTED_CAMERAS.contains(camera)
+}
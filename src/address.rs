@@ -0,0 +1,130 @@
+use std::error::Error;
+use std::net::Ipv6Addr;
+use tiny_http::Server;
+
+/// Formats a host so it can be joined with a port, bracketing bare IPv6 literals
+///
+/// rumqttc connects to `"{host}:{port}"`, which only resolves when an IPv6
+/// address is written as `[2001:db8::1]`. Hostnames, IPv4 addresses and already
+/// bracketed IPv6 addresses are returned unchanged.
+///
+/// # Arguments
+/// * `host` - Hostname or IP address, optionally bracketed
+///
+/// # Returns
+/// * Host ready to be used in front of `:port`
+pub fn mqtt_host(host: &str) -> String {
+    let host = host.trim();
+    if host.parse::<Ipv6Addr>().is_ok() {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
+    }
+}
+
+/// Normalizes the Blue Iris base URL so paths can be appended to it
+///
+/// Adds `http://` when no scheme is given, brackets a bare IPv6 host and drops
+/// trailing slashes. Both `http://[2001:db8::1]:81` and `2001:db8::1` are
+/// accepted; a bare IPv6 host can't carry a port since the port would be
+/// indistinguishable from the address.
+///
+/// # Arguments
+/// * `endpoint` - Configured `alert_endpoint`
+///
+/// # Returns
+/// * Base URL without a trailing slash, ie: `http://[2001:db8::1]:81`
+pub fn normalize_endpoint(endpoint: &str) -> String {
+    let endpoint = endpoint.trim().trim_end_matches('/');
+    let (scheme, rest) = match endpoint.split_once("://") {
+        Some((scheme, rest)) => (scheme, rest),
+        None => ("http", endpoint),
+    };
+
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, ""),
+    };
+
+    format!("{}://{}{}", scheme, mqtt_host(authority), path)
+}
+
+/// Joins a path onto the Blue Iris base URL
+///
+/// # Arguments
+/// * `endpoint` - Configured `alert_endpoint`
+/// * `path` - Path and query starting with `/`, ie: `/alerts/@123?fulljpeg`
+///
+/// # Returns
+/// * Full URL to the resource
+pub fn endpoint_url(endpoint: &str, path: &str) -> String {
+    format!("{}{}", normalize_endpoint(endpoint), path)
+}
+
+/// Binds an HTTP server to all interfaces on the given port
+///
+/// Listens on `::` so IPv6-only hosts are reachable (dual-stack systems accept
+/// IPv4 on the same socket), falling back to `0.0.0.0` where IPv6 is disabled.
+///
+/// # Arguments
+/// * `port` - Port to listen on
+///
+/// # Returns
+/// * `Err` if the port can't be bound on either address
+pub fn bind_server(port: u16) -> Result<Server, Box<dyn Error + Send + Sync>> {
+    Server::http(("::", port)).or_else(|_| Server::http(("0.0.0.0", port)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mqtt_host_brackets_bare_ipv6() {
+        assert_eq!(mqtt_host("2001:db8::1"), "[2001:db8::1]");
+        assert_eq!(mqtt_host("::1"), "[::1]");
+        assert_eq!(mqtt_host(" fe80::1 "), "[fe80::1]");
+    }
+
+    #[test]
+    fn mqtt_host_keeps_other_hosts() {
+        assert_eq!(mqtt_host("[2001:db8::1]"), "[2001:db8::1]");
+        assert_eq!(mqtt_host("127.0.0.1"), "127.0.0.1");
+        assert_eq!(mqtt_host("broker.local"), "broker.local");
+    }
+
+    #[test]
+    fn mqtt_host_resolves_with_port() {
+        use std::net::ToSocketAddrs;
+
+        let addr = format!("{}:{}", mqtt_host("::1"), 1883).to_socket_addrs().unwrap().next().unwrap();
+        assert!(addr.is_ipv6());
+        assert_eq!(addr.port(), 1883);
+    }
+
+    #[test]
+    fn endpoint_url_with_bracketed_ipv6() {
+        assert_eq!(endpoint_url("http://[2001:db8::1]:81", "/alerts/@123?fulljpeg"),
+                   "http://[2001:db8::1]:81/alerts/@123?fulljpeg");
+        assert_eq!(endpoint_url("https://[2001:db8::1]/", "/ui3.htm"),
+                   "https://[2001:db8::1]/ui3.htm");
+    }
+
+    #[test]
+    fn endpoint_url_with_bare_ipv6() {
+        assert_eq!(endpoint_url("http://2001:db8::1", "/ui3.htm"), "http://[2001:db8::1]/ui3.htm");
+        assert_eq!(endpoint_url("2001:db8::1", "/ui3.htm"), "http://[2001:db8::1]/ui3.htm");
+    }
+
+    #[test]
+    fn endpoint_url_with_ipv4_and_hostname() {
+        assert_eq!(endpoint_url("http://127.0.0.1:81", "/ui3.htm"), "http://127.0.0.1:81/ui3.htm");
+        assert_eq!(endpoint_url("https://nvr.example.com/bi/", "/ui3.htm"), "https://nvr.example.com/bi/ui3.htm");
+    }
+
+    #[test]
+    fn normalized_ipv6_endpoint_parses() {
+        let url = url::Url::parse(&normalize_endpoint("2001:db8::1")).unwrap();
+        assert_eq!(url.host_str(), Some("[2001:db8::1]"));
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+use rand::Rng;
+use crate::bvr_chirp_config::BackoffConfig;
+
+/// Largest jitter used, a jitter of 1.0 or more could make a delay negative
+const MAX_JITTER: f64 = 0.99;
+
+/// Exponential backoff with jitter, shared by everything that waits before trying again
+///
+/// Each delay is the previous one times `multiplier`, capped at `max_ms`, and randomized
+/// by ±`jitter` so multiple instances don't retry in lockstep.
+pub struct Backoff {
+    cfg: BackoffConfig,
+    current: Duration,
+}
+
+impl Backoff {
+    /// Starts a backoff at its initial delay
+    ///
+    /// `validate` only runs with `--validate`, so a `multiplier` below 1.0 is raised to 1.0
+    /// and `jitter` is kept from 0.0 up to `MAX_JITTER`, either could otherwise make a delay negative.
+    ///
+    /// # Arguments
+    /// * `cfg` - The `[backoff]` settings
+    pub fn new(cfg: &BackoffConfig) -> Self {
+        let mut cfg = cfg.clone();
+        // `max` and `min` also replace NaN
+        cfg.multiplier = cfg.multiplier.max(1.0);
+        cfg.jitter = cfg.jitter.max(0.0).min(MAX_JITTER);
+        Backoff {
+            current: Duration::from_millis(cfg.initial_ms),
+            cfg,
+        }
+    }
+
+    /// Returns how long to wait before the next attempt, growing the delay for the one after
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.jittered(self.current);
+        // A huge multiplier overflows a Duration, which only means the delay is capped
+        self.current = Duration::try_from_secs_f64(self.current.as_secs_f64() * self.cfg.multiplier)
+            .map_or(self.max(), |next| next.min(self.max()));
+        delay
+    }
+
+    /// Starts over at the initial delay, ie: once a connection succeeds
+    pub fn reset(&mut self) {
+        self.current = Duration::from_millis(self.cfg.initial_ms);
+    }
+
+    fn max(&self) -> Duration {
+        Duration::from_millis(self.cfg.max_ms)
+    }
+
+    /// Randomizes a delay by ±`jitter`, a fraction of the delay
+    fn jittered(&self, delay: Duration) -> Duration {
+        if self.cfg.jitter == 0.0 {
+            return delay;
+        }
+        delay.mul_f64(rand::thread_rng().gen_range(1.0 - self.cfg.jitter..=1.0 + self.cfg.jitter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_settings(multiplier: f64, jitter: f64) -> Backoff {
+        Backoff::new(&BackoffConfig { initial_ms: 1000, max_ms: 8000, multiplier, jitter, ..BackoffConfig::default() })
+    }
+
+    #[test]
+    fn delays_grow_up_to_the_max() {
+        let mut backoff = with_settings(2.0, 0.0);
+        let delays: Vec<u128> = (0..5).map(|_| backoff.next_delay().as_millis()).collect();
+        assert_eq!(delays, [1000, 2000, 4000, 8000, 8000]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay().as_millis(), 1000);
+    }
+
+    #[test]
+    fn invalid_multiplier_and_jitter_are_clamped() {
+        let mut backoff = with_settings(-2.0, 1.5);
+        for _ in 0..20 {
+            assert!(backoff.next_delay() <= Duration::from_millis(1990));
+        }
+
+        let mut backoff = with_settings(f64::NAN, f64::NAN);
+        assert_eq!(backoff.next_delay().as_millis(), 1000);
+        assert_eq!(backoff.next_delay().as_millis(), 1000);
+    }
+
+    #[test]
+    fn huge_multiplier_caps_the_delay() {
+        let mut backoff = with_settings(f64::INFINITY, 0.0);
+        assert_eq!(backoff.next_delay().as_millis(), 1000);
+        assert_eq!(backoff.next_delay().as_millis(), 8000);
+    }
+}
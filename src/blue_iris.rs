@@ -0,0 +1,139 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use md5::{Digest, Md5};
+use reqwest::blocking::Client;
+use serde_json::{json, Value};
+use crate::address;
+use crate::bvr_chirp_config::BvrChirpConfig;
+use crate::bvr_chirp_message::BvrChirpMessage;
+use log::{info, warn};
+
+/// How often to ask Blue Iris whether the recording is there yet
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Seconds a single request to the JSON API may take
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+/// Checks through the Blue Iris JSON API that an alert's recording can be opened
+///
+/// Logs in with the `blue_iris_api` credentials and keeps the session for later
+/// alerts, logging in again when Blue Iris forgets it.
+pub struct RecordingVerifier {
+    client: Client,
+    json_url: String,
+    username: String,
+    password: String,
+    session: Mutex<Option<String>>,
+}
+
+impl RecordingVerifier {
+    /// Creates a verifier for the Blue Iris server at `alert_endpoint`
+    ///
+    /// # Returns
+    /// * `Err` if the HTTP client can't be built
+    pub fn new(cfg: &BvrChirpConfig) -> reqwest::Result<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()?;
+
+        Ok(Self {
+            client,
+            json_url: address::endpoint_url(&cfg.alert_endpoint, "/json"),
+            username: cfg.blue_iris_api.username.clone(),
+            password: cfg.blue_iris_api.password.clone(),
+            session: Mutex::new(None),
+        })
+    }
+
+    /// Waits until Blue Iris lists the alert's recording, so its "View Alert" link works
+    ///
+    /// # Arguments
+    /// * `bvr_msg` - The alert, with the camera and `db_id` to look for
+    /// * `timeout` - How long to keep asking before giving up
+    ///
+    /// # Returns
+    /// * `true` once the recording is listed
+    /// * `false` if it still isn't after `timeout`, the alert should be sent anyway
+    pub fn wait_for_recording(&self, bvr_msg: &BvrChirpMessage, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.has_recording(&bvr_msg.camera_name, &bvr_msg.db_id) {
+                Ok(true) => return true,
+                Ok(false) => {}
+                Err(e) => warn!("BLUEIRIS: Failed to look up recording {}: {}", bvr_msg.db_id, e),
+            }
+
+            if Instant::now() + POLL_INTERVAL > deadline {
+                return false;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Returns true if the camera's alert list has the record ID
+    fn has_recording(&self, camera: &str, db_id: &str) -> Result<bool, String> {
+        let response = self.command(json!({ "cmd": "alertlist", "camera": camera }))?;
+        let wanted = db_id.trim_start_matches('@');
+
+        Ok(response["data"].as_array()
+            .map(|alerts| alerts.iter().any(|alert| {
+                ["path", "clip"].iter().any(|field| {
+                    alert[field].as_str().is_some_and(|id| id.trim_start_matches('@').split('.').next() == Some(wanted))
+                })
+            }))
+            .unwrap_or(false))
+    }
+
+    /// Sends a command with the current session, logging in again once if it was rejected
+    fn command(&self, mut command: Value) -> Result<Value, String> {
+        for attempt in 0..2 {
+            let session = self.session(attempt > 0)?;
+            command["session"] = json!(session);
+
+            let response = self.post(&command)?;
+            if response["result"] == "success" {
+                return Ok(response);
+            }
+        }
+        Err(format!("Blue Iris rejected the '{}' command", command["cmd"].as_str().unwrap_or_default()))
+    }
+
+    /// The session to send commands with, logging in when there's none or `renew` is set
+    fn session(&self, renew: bool) -> Result<String, String> {
+        let mut session = self.session.lock().map_err(|_| "Session is unavailable".to_string())?;
+        if renew || session.is_none() {
+            *session = Some(self.login()?);
+        }
+        Ok(session.clone().unwrap_or_default())
+    }
+
+    /// Logs in with the challenge-response of the JSON API
+    ///
+    /// The first login request returns a session, the second one answers with the
+    /// MD5 of "username:session:password".
+    fn login(&self) -> Result<String, String> {
+        let challenge = self.post(&json!({ "cmd": "login" }))?;
+        let session = challenge["session"].as_str()
+            .ok_or_else(|| "Blue Iris didn't return a login session".to_string())?
+            .to_string();
+
+        let digest = Md5::digest(format!("{}:{}:{}", self.username, session, self.password).as_bytes());
+        let response = self.post(&json!({ "cmd": "login", "session": session, "response": hex::encode(digest) }))?;
+        if response["result"] != "success" {
+            return Err("Blue Iris login failed, check blue_iris_api".to_string());
+        }
+
+        info!("BLUEIRIS: Logged in to the JSON API");
+        Ok(session)
+    }
+
+    /// Posts a command to the JSON API and parses the response
+    fn post(&self, command: &Value) -> Result<Value, String> {
+        self.client
+            .post(&self.json_url)
+            .json(command)
+            .send()
+            .and_then(|response| response.json::<Value>())
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::bvr_chirp_message::BvrChirpMessage;
+use crate::image_processing;
+use log::info;
+
+/// Frames of one event collected so far
+struct Burst {
+    /// When the window for this event closes
+    deadline: Instant,
+    /// Sharpest frame seen so far
+    best: BvrChirpMessage,
+    best_score: f64,
+    frames: usize,
+}
+
+/// Starts a thread that buffers frames of the same event and forwards only the sharpest one
+///
+/// Frames belong to the same event when they share a camera and `db_id`. The
+/// first frame of an event opens a window of `window`, once it closes the frame
+/// with the highest sharpness score is passed on.
+///
+/// # Arguments
+/// * `window` - How long to collect frames of an event before sending
+/// * `verbose` - Log each selected frame
+/// * `next` - Where the selected frames go, the messaging clients or multi-camera grouping
+///
+/// # Returns
+/// * Sender to pass every received frame to
+pub fn spawn(window: Duration, verbose: bool, next: Box<dyn Fn(BvrChirpMessage) + Send>) -> Sender<BvrChirpMessage> {
+    let (tx, rx) = mpsc::channel::<BvrChirpMessage>();
+
+    thread::spawn(move || {
+        let mut bursts: HashMap<(String, String), Burst> = HashMap::new();
+
+        loop {
+            let timeout = bursts.values()
+                .map(|burst| burst.deadline.saturating_duration_since(Instant::now()))
+                .min()
+                .unwrap_or(window);
+
+            match rx.recv_timeout(timeout) {
+                Ok(message) => add_frame(&mut bursts, message, window),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    for (_, burst) in bursts.drain() {
+                        send_best(burst, verbose, &next);
+                    }
+                    break;
+                }
+            }
+
+            let now = Instant::now();
+            let expired: Vec<(String, String)> = bursts.iter()
+                .filter(|(_, burst)| burst.deadline <= now)
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in expired {
+                if let Some(burst) = bursts.remove(&key) {
+                    send_best(burst, verbose, &next);
+                }
+            }
+        }
+    });
+
+    tx
+}
+
+/// Adds a frame to its event's burst, keeping it if it's the sharpest so far
+fn add_frame(bursts: &mut HashMap<(String, String), Burst>, message: BvrChirpMessage, window: Duration) {
+    let score = image_processing::sharpness(&message.image).unwrap_or(0.0);
+    let key = (message.camera_name.clone(), message.db_id.clone());
+
+    match bursts.get_mut(&key) {
+        Some(burst) => {
+            burst.frames += 1;
+            if score > burst.best_score {
+                burst.best = message;
+                burst.best_score = score;
+            }
+        }
+        None => {
+            bursts.insert(key, Burst {
+                deadline: Instant::now() + window,
+                best: message,
+                best_score: score,
+                frames: 1,
+            });
+        }
+    }
+}
+
+/// Passes the sharpest frame of a burst on
+fn send_best(burst: Burst, verbose: bool, next: &dyn Fn(BvrChirpMessage)) {
+    if verbose {
+        info!("BURST: Sending sharpest of {} frame(s) from {} camera (score {:.1})",
+                 burst.frames, burst.best.camera_name, burst.best_score);
+    }
+    next(burst.best);
+}
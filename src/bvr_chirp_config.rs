@@ -1,91 +1,2166 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::path::{Path, PathBuf};
+use chrono::{NaiveDateTime, NaiveTime};
 use confy::ConfyError;
-use serde::{Deserialize, Serialize};
+use hmac::{Hmac, Mac};
+use rumqttc::v5::mqttbytes::valid_filter;
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::Sha256;
+use url::Url;
+use crate::{address, image_processing};
+use crate::bvr_chirp_message::{BvrChirpMessage, DEFAULT_DETECTION_SEPARATORS};
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct BvrChirpConfig {
     pub alert_endpoint: String,
+    #[serde(default)]
+    pub instance_label: String,
+    #[serde(default)]
+    pub instance_label_position: LabelPosition,
+    #[serde(default)]
+    pub snapshot_server_port: u16,
+    #[serde(default)]
+    pub snapshot_server_url: String,
+    #[serde(default)]
+    pub snapshot_server_secret: String,
+    #[serde(default)]
+    pub live_url_template: String,
+    #[serde(default)]
+    pub include_qr: bool,
+    #[serde(default)]
+    pub include_motion_delta: bool,
+    #[serde(default)]
+    pub attach_metadata_json: bool,
+    #[serde(default)]
+    pub overlay_text: bool,
+    #[serde(default)]
+    pub overlay_position: OverlayPosition,
+    #[serde(default = "default_overlay_color")]
+    pub overlay_color: String,
+    #[serde(default)]
+    pub breaker_threshold: u32,
+    #[serde(default = "default_breaker_cooldown_secs")]
+    pub breaker_cooldown_secs: u64,
+    #[serde(default)]
+    pub fix_image_orientation: bool,
+    #[serde(default)]
+    pub skip_blank_frames: bool,
+    #[serde(default = "default_blank_frame_brightness")]
+    pub blank_frame_brightness: f64,
+    #[serde(default = "default_blank_frame_contrast")]
+    pub blank_frame_contrast: f64,
+    #[serde(default)]
+    pub blank_frame_action: ImageErrorAction,
+    #[serde(default)]
+    pub small_image_action: ImageErrorAction,
+    #[serde(default)]
+    pub best_frame_window_ms: u64,
+    #[serde(default)]
+    pub multi_cam_window_ms: u64,
+    #[serde(default)]
+    pub verify_recording: bool,
+    #[serde(default = "default_verify_recording_timeout_secs")]
+    pub verify_recording_timeout_secs: u64,
+    #[serde(default)]
+    pub alert_send_delay_ms: u64,
+    #[serde(default)]
+    pub max_alert_age_secs: u64,
+    #[serde(default = "default_upload_filename_template")]
+    pub upload_filename_template: String,
+    #[serde(default)]
+    pub max_upload_bytes: usize,
+    #[serde(default = "default_min_upload_quality")]
+    pub min_upload_quality: u8,
+    #[serde(default)]
+    pub upload_format: UploadFormat,
+    #[serde(default)]
+    pub fast_preview: bool,
+    #[serde(default)]
+    pub dead_letter_path: String,
+    #[serde(default)]
+    pub interaction_server_port: u16,
+    #[serde(default = "default_ack_mute_secs")]
+    pub ack_mute_secs: u64,
+    #[serde(default)]
+    pub web_ui_port: u16,
+    #[serde(default)]
+    pub service_priority: Vec<String>,
+    #[serde(default)]
+    pub ordered_delivery: bool,
+    #[serde(default)]
+    pub shared_runtime: bool,
+    #[serde(default)]
+    pub heartbeat_interval_secs: u64,
+    #[serde(default)]
+    pub heartbeat_service: String,
+    #[serde(default)]
+    pub daily_summary_time: String,
+    #[serde(default)]
+    pub daily_summary_service: String,
+    #[serde(default)]
+    pub log_file: String,
+    #[serde(default = "default_log_max_size")]
+    pub log_max_size: u64,
+    #[serde(default = "default_log_max_files")]
+    pub log_max_files: usize,
+    #[serde(default)]
+    pub detection_emoji: BTreeMap<String, String>,
+    #[serde(default)]
+    pub alert_templates: BTreeMap<String, AlertTemplate>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub templates: BTreeMap<String, String>,
+    #[serde(default)]
+    pub camera_locations: BTreeMap<String, String>,
+    #[serde(default)]
+    pub camera_confidence_thresholds: BTreeMap<String, f64>,
+    #[serde(default)]
+    pub alert_endpoint_auth: AlertEndpointAuth,
+    #[serde(default)]
+    pub blue_iris_api: BlueIrisApiConfig,
+    #[serde(default)]
+    pub targets: BTreeMap<String, TargetConfig>,
+    #[serde(default)]
+    pub debug_target: String,
+    #[serde(default)]
+    pub discord_mode: DiscordMode,
+    #[serde(default)]
+    pub no_services_action: NoServicesAction,
+    #[serde(default)]
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+    #[serde(default)]
+    pub failure_escalation: Vec<FailureEscalation>,
+    #[serde(default)]
+    pub backoff: BackoffConfig,
     pub mqtt_config: MqttConfig,
     pub matrix_config: MatrixConfig,
     pub discord_config: DiscordConfig,
     pub slack_config: SlackConfig,
+    #[serde(default)]
+    pub mastodon_config: MastodonConfig,
+    #[serde(default)]
+    pub chat_webhook_config: ChatWebhookConfig,
+    #[serde(default)]
+    pub apprise_config: AppriseConfig,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+/// Wording of an alert, selected by the detections through `templates`
+///
+/// Both texts support the `<CAMERA_NAME>`, `<DETECTIONS>`, `<COUNT>`, `<TIME>`
+/// and `<DAILY_COUNT>` placeholders.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AlertTemplate {
+    pub title: String,
+    /// Line shown below the title, left out when empty
+    #[serde(default)]
+    pub description: String,
+}
+
+/// Where an alert with a named `target` is sent, per service
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct TargetConfig {
+    /// Discord channel, replaces the raw channel ID otherwise expected in `target`
+    #[serde(default)]
+    pub discord_channel_id: String,
+    /// Matrix rooms, replacing the configured `room_id` for this target
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub matrix_room_id: Vec<String>,
+    /// Services tried one after another, the next only when the previous didn't deliver the alert
+    #[serde(default)]
+    pub primary_services: Vec<String>,
+}
+
+/// Time during which a camera is worked on and its alerts are suppressed
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct MaintenanceWindow {
+    pub camera: String,
+    /// Local time the window opens, ie: "2024-06-01 09:00"
+    pub start: String,
+    /// Local time the window closes
+    pub end: String,
+}
+
+impl MaintenanceWindow {
+    /// Returns true if the time is within the window, false if the window can't be parsed
+    pub fn contains(&self, time: NaiveDateTime) -> bool {
+        match (parse_local_time(&self.start), parse_local_time(&self.end)) {
+            (Some(start), Some(end)) => start <= time && time < end,
+            _ => false,
+        }
+    }
+}
+
+/// Notice sent once to another service when a service keeps failing
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FailureEscalation {
+    /// Service whose failures are watched
+    pub service: String,
+    /// Consecutive failures before the notice is sent
+    #[serde(default = "default_escalation_after_failures")]
+    pub after_failures: u32,
+    /// Service the notice goes to, ie: apprise for an SMS or email
+    pub notify_service: String,
+}
+
+/// Parses a local time in the "2024-06-01 09:00" format used by `maintenance_windows`
+fn parse_local_time(time: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(time.trim(), MAINTENANCE_TIME_FORMAT).ok()
+}
+
+/// Format of the start and end of `maintenance_windows`
+const MAINTENANCE_TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// Neutral camera name `validate` fills into `live_url_template` to check that it makes a URL
+const VALIDATION_CAMERA_NAME: &str = "camera";
+
+/// Credentials added to the "View Alert" links so Blue Iris opens them without a login prompt
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct AlertEndpointAuth {
+    /// Query parameter the token is passed in, ie: "session"
+    #[serde(default)]
+    pub token_param: String,
+    #[serde(default)]
+    pub token: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+}
+
+/// Login for the Blue Iris JSON API, used by `verify_recording`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct BlueIrisApiConfig {
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+}
+
+/// How long to wait before trying again, for MQTT reconnects, Matrix room lookups and alerts
+/// a messaging service failed to send
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BackoffConfig {
+    /// Wait before the first retry
+    #[serde(default = "default_backoff_initial_ms")]
+    pub initial_ms: u64,
+    /// Longest wait between retries
+    #[serde(default = "default_backoff_max_ms")]
+    pub max_ms: u64,
+    /// Factor the wait grows by after each retry
+    #[serde(default = "default_backoff_multiplier")]
+    pub multiplier: f64,
+    /// Fraction each wait is randomized by, ie: 0.2 for ±20%
+    #[serde(default = "default_backoff_jitter")]
+    pub jitter: f64,
+    /// Times a messaging service tries an alert again after a temporary failure
+    #[serde(default = "default_backoff_alert_retries")]
+    pub alert_retries: u32,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        BackoffConfig {
+            initial_ms: default_backoff_initial_ms(),
+            max_ms: default_backoff_max_ms(),
+            multiplier: default_backoff_multiplier(),
+            jitter: default_backoff_jitter(),
+            alert_retries: default_backoff_alert_retries(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct MqttConfig {
     pub host: String,
     pub port: u16,
     pub max_packet_size: u32,
+    #[serde(default = "default_mqtt_channel_cap")]
+    pub mqtt_channel_cap: usize,
     pub topic: String,
     pub device_id : String,
     pub username: String,
     pub password: String,
+    #[serde(default = "default_detection_separators")]
+    pub detection_separators: String,
+    #[serde(default)]
+    pub suppress_empty_detections: bool,
+    #[serde(default)]
+    pub verbose_mqtt_logs: bool,
+    #[serde(default)]
+    pub payload_encoding: PayloadEncoding,
+    #[serde(default)]
+    pub base64_variant: Base64Variant,
+    #[serde(default)]
+    pub binary_image_topic: String,
+    #[serde(default)]
+    pub skip_retained: bool,
+    #[serde(default)]
+    pub startup_grace_secs: u64,
+    #[serde(default)]
+    pub randomize_client_id: bool,
+    #[serde(default)]
+    pub on_image_error: ImageErrorAction,
+    #[serde(default)]
+    pub arm_state_topic: String,
+    #[serde(default)]
+    pub disarmed_detections: Vec<String>,
+    #[serde(default)]
+    pub maintenance_topic: String,
+    #[serde(default)]
+    pub max_queued_alerts: usize,
+    #[serde(default)]
+    pub queue_full_policy: QueueFullPolicy,
+    #[serde(default)]
+    pub payload_hmac_secret: String,
+    #[serde(default)]
+    pub decode_html_entities: bool,
+    #[serde(default)]
+    pub camera_cooldown_secs: u64,
+    #[serde(default)]
+    pub cooldown_break_on_new_type: bool,
+    #[serde(default)]
+    pub passthrough_mode: bool,
+    #[serde(default)]
+    pub passthrough_service: String,
+    #[serde(default)]
+    pub topic_routing: String,
+    #[serde(default)]
+    pub dedup_by_db_id: bool,
+    #[serde(default = "default_dedup_window_secs")]
+    pub dedup_window_secs: u64,
+    #[serde(default)]
+    pub credential_url: String,
+    #[serde(default)]
+    pub credential_token: String,
+}
+
+/// How the JSON in an MQTT message is encoded
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadEncoding {
+    /// Raw UTF-8 JSON
+    #[default]
+    Plain,
+    /// Gzip compressed JSON, optionally base64 encoded
+    Gzip,
+}
+
+/// Alphabet the base64 image in an MQTT message is encoded with
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Base64Variant {
+    /// Standard alphabet with `+` and `/`
+    #[default]
+    Standard,
+    /// URL-safe alphabet with `-` and `_`
+    UrlSafe,
+    /// Try the standard alphabet, then the URL-safe one
+    Auto,
+}
+
+/// Where `instance_label` is shown on alerts
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelPosition {
+    /// Only in the footer, next to the bot name, on services that have one
+    #[default]
+    Footer,
+    /// In front of the title on every service, ie: "[Home] Detection on Driveway camera"
+    Prefix,
+    /// After the title on every service, ie: "Detection on Driveway camera [Home]"
+    Suffix,
+}
+
+/// Corner of the snapshot the camera name and time are drawn in with `overlay_text`
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlayPosition {
+    #[default]
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// How the Discord client connects
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DiscordMode {
+    /// A full serenity client, which can connect to the gateway for slash commands
+    #[default]
+    Gateway,
+    /// Only the REST API, for sending alerts with less memory and no websocket
+    Rest,
+}
+
+/// Format alert images are re-encoded in before they're uploaded
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadFormat {
+    /// Uploaded as received, Blue Iris sends JPEG
+    #[default]
+    Jpeg,
+    /// Lossy WebP
+    Webp,
+    /// PNG
+    Png,
+}
+
+impl UploadFormat {
+    /// Format the image crate encodes it as
+    pub fn image_format(self) -> image::ImageFormat {
+        match self {
+            UploadFormat::Jpeg => image::ImageFormat::Jpeg,
+            UploadFormat::Webp => image::ImageFormat::WebP,
+            UploadFormat::Png => image::ImageFormat::Png,
+        }
+    }
+
+    /// File name extension of the format, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            UploadFormat::Jpeg => "jpg",
+            UploadFormat::Webp => "webp",
+            UploadFormat::Png => "png",
+        }
+    }
+}
+
+/// What to do at startup when no messaging service is enabled
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum NoServicesAction {
+    /// Exit with an error
+    #[default]
+    Exit,
+    /// Log a warning and keep running, ie: for the snapshot server or web UI alone
+    Warn,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+/// What to do with an alert whose image can't be decoded, or is blank with `skip_blank_frames`
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageErrorAction {
+    /// Drop the alert entirely
+    #[default]
+    Drop,
+    /// Send the alert without an image
+    TextOnly,
+}
+
+/// What to do with a new alert when a service already has `max_queued_alerts` waiting
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueFullPolicy {
+    /// Stop reading MQTT messages until the service catches up
+    #[default]
+    Block,
+    /// Discard the oldest waiting alert to make room for the new one
+    DropOldest,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct MatrixConfig {
     pub enabled: bool,
     pub username: String,
     pub password: String,
     pub homeserver_url: String,
-    pub room_id: String,
+    #[serde(deserialize_with = "string_or_list")]
+    pub room_id: Vec<String>,
     pub bot_name: String,
+    #[serde(default)]
+    pub enable_reactions: bool,
+    #[serde(default = "default_reaction_mute_secs")]
+    pub reaction_mute_secs: u64,
+    #[serde(default)]
+    pub format: MatrixFormat,
+    #[serde(default = "default_room_lookup_retries")]
+    pub room_lookup_retries: u32,
+    #[serde(default)]
+    pub min_image_width: u32,
+    #[serde(default)]
+    pub min_image_height: u32,
+    #[serde(default)]
+    pub image_only: bool,
+    #[serde(default)]
+    pub plain_ascii: bool,
+    #[serde(default = "default_matrix_max_field_length")]
+    pub max_field_length: usize,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+/// Which representation of the alert is sent to Matrix
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum MatrixFormat {
+    /// Plain text only, for bridges that mangle HTML
+    Plain,
+    /// HTML with a plain text fallback
+    #[default]
+    Html,
+    /// Markdown, converted to HTML with a plain text fallback
+    Markdown,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct DiscordConfig {
     pub enabled: bool,
     pub token: String,
     pub channel_id: String,
     pub bot_name: String,
+    #[serde(default)]
+    pub use_image_url: bool,
+    #[serde(default)]
+    pub forum_mode: bool,
+    #[serde(default = "default_embed_fields")]
+    pub embed_fields: Vec<String>,
+    #[serde(default)]
+    pub public_key: String,
+    #[serde(default)]
+    pub enable_commands: bool,
+    #[serde(default)]
+    pub warm_up: bool,
+    #[serde(default)]
+    pub webhook_url: String,
+    #[serde(default)]
+    pub avatar_url: String,
+    #[serde(default)]
+    pub camera_avatars: BTreeMap<String, String>,
+    #[serde(default)]
+    pub pin_active_events: bool,
+    #[serde(default = "default_active_event_secs")]
+    pub active_event_secs: u64,
+    #[serde(default)]
+    pub min_image_width: u32,
+    #[serde(default)]
+    pub min_image_height: u32,
+    #[serde(default)]
+    pub image_only: bool,
+    #[serde(default = "default_discord_max_field_length")]
+    pub max_field_length: usize,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct SlackConfig {
     pub enabled: bool,
     pub token: String,
     pub channel_id: String,
     pub bot_name: String,
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    #[serde(default)]
+    pub signing_secret: String,
+    #[serde(default = "default_button_text")]
+    pub button_text: String,
+    #[serde(default)]
+    pub button_emoji: bool,
+    #[serde(default)]
+    pub min_image_width: u32,
+    #[serde(default)]
+    pub min_image_height: u32,
+    #[serde(default)]
+    pub image_only: bool,
+    #[serde(default)]
+    pub plain_ascii: bool,
+    #[serde(default = "default_slack_max_field_length")]
+    pub max_field_length: usize,
+    #[serde(default)]
+    pub destinations: Vec<SlackDestination>,
+}
+
+/// A further Slack channel, possibly in another workspace, that alerts are sent to
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SlackDestination {
+    pub token: String,
+    pub channel_id: String,
+    /// Name shown in the footer, defaults to `bot_name` of slack_config
+    #[serde(default)]
+    pub bot_name: String,
+    /// Cameras whose alerts are sent to this channel, empty for every camera
+    #[serde(default)]
+    pub cameras: Vec<String>,
+}
+
+impl SlackDestination {
+    /// Returns true if alerts from the camera are sent to this channel
+    pub fn accepts(&self, camera: &str) -> bool {
+        self.cameras.is_empty() || self.cameras.iter().any(|name| name.eq_ignore_ascii_case(camera))
+    }
+}
+
+impl SlackConfig {
+    /// Every channel alerts are sent to
+    ///
+    /// The `token` and `channel_id` of slack_config come first and receive every
+    /// alert, unless the token is left empty to only use `destinations`.
+    /// Destinations without a `bot_name` use the one of slack_config.
+    pub fn all_destinations(&self) -> Vec<SlackDestination> {
+        let mut destinations = Vec::new();
+        if !self.token.is_empty() {
+            destinations.push(SlackDestination {
+                token: self.token.clone(),
+                channel_id: self.channel_id.clone(),
+                bot_name: self.bot_name.clone(),
+                cameras: Vec::new(),
+            });
+        }
+
+        for destination in &self.destinations {
+            let mut destination = destination.clone();
+            if destination.bot_name.is_empty() {
+                destination.bot_name = self.bot_name.clone();
+            }
+            destinations.push(destination);
+        }
+        destinations
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MastodonConfig {
+    pub enabled: bool,
+    pub instance_url: String,
+    pub access_token: String,
+    #[serde(default = "default_mastodon_visibility")]
+    pub visibility: String,
+    #[serde(default)]
+    pub min_image_width: u32,
+    #[serde(default)]
+    pub min_image_height: u32,
+    #[serde(default)]
+    pub image_only: bool,
+}
+
+/// Visibilities a Mastodon status can be posted with
+pub const MASTODON_VISIBILITIES: &[&str] = &["public", "unlisted", "private", "direct"];
+
+impl Default for MastodonConfig {
+    fn default() -> Self {
+        MastodonConfig {
+            enabled: false,
+            instance_url: "https://mastodon.social".to_string(),
+            access_token: "<access_token>".to_string(),
+            visibility: default_mastodon_visibility(),
+            min_image_width: 0,
+            min_image_height: 0,
+            image_only: false,
+        }
+    }
+}
+
+/// Self-hosted chat platforms alerts can be posted to through an incoming webhook
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatPlatform {
+    /// Synology Chat, images are only shown from a URL
+    #[default]
+    Synology,
+    /// Rocket.Chat
+    #[serde(rename = "rocketchat")]
+    RocketChat,
+    /// Mattermost
+    Mattermost,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChatWebhookConfig {
+    pub enabled: bool,
+    #[serde(default)]
+    pub platform: ChatPlatform,
+    pub webhook_url: String,
+    #[serde(default = "default_chat_webhook_bot_name")]
+    pub bot_name: String,
+    #[serde(default)]
+    pub min_image_width: u32,
+    #[serde(default)]
+    pub min_image_height: u32,
+    #[serde(default)]
+    pub image_only: bool,
+}
+
+impl Default for ChatWebhookConfig {
+    fn default() -> Self {
+        ChatWebhookConfig {
+            enabled: false,
+            platform: ChatPlatform::Synology,
+            webhook_url: "<webhook_url>".to_string(),
+            bot_name: default_chat_webhook_bot_name(),
+            min_image_width: 0,
+            min_image_height: 0,
+            image_only: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AppriseConfig {
+    pub enabled: bool,
+    pub server_url: String,
+    /// Key of a configuration saved on the Apprise server, used instead of `urls`
+    #[serde(default)]
+    pub config_key: String,
+    /// Apprise URLs to notify when there's no `config_key`, ie: "tgram://bottoken/ChatID"
+    #[serde(default)]
+    pub urls: Vec<String>,
+    /// Only notify the services of the saved configuration with this tag
+    #[serde(default)]
+    pub tag: String,
+    #[serde(default)]
+    pub min_image_width: u32,
+    #[serde(default)]
+    pub min_image_height: u32,
+    #[serde(default)]
+    pub image_only: bool,
+}
+
+impl Default for AppriseConfig {
+    fn default() -> Self {
+        AppriseConfig {
+            enabled: false,
+            server_url: "http://127.0.0.1:8000".to_string(),
+            config_key: "".to_string(),
+            urls: Vec::new(),
+            tag: "".to_string(),
+            min_image_width: 0,
+            min_image_height: 0,
+            image_only: false,
+        }
+    }
 }
 
 impl Default for BvrChirpConfig {
     fn default() -> Self {
         BvrChirpConfig {
             alert_endpoint: "http://127.0.0.1:81".to_string(),
+            instance_label: "".to_string(),
+            instance_label_position: LabelPosition::Footer,
+            snapshot_server_port: 0,
+            snapshot_server_url: "".to_string(),
+            snapshot_server_secret: "".to_string(),
+            live_url_template: "".to_string(),
+            include_qr: false,
+            include_motion_delta: false,
+            attach_metadata_json: false,
+            overlay_text: false,
+            overlay_position: OverlayPosition::TopLeft,
+            overlay_color: default_overlay_color(),
+            breaker_threshold: 0,
+            breaker_cooldown_secs: default_breaker_cooldown_secs(),
+            fix_image_orientation: false,
+            skip_blank_frames: false,
+            blank_frame_brightness: default_blank_frame_brightness(),
+            blank_frame_contrast: default_blank_frame_contrast(),
+            blank_frame_action: ImageErrorAction::Drop,
+            small_image_action: ImageErrorAction::Drop,
+            best_frame_window_ms: 0,
+            multi_cam_window_ms: 0,
+            verify_recording: false,
+            verify_recording_timeout_secs: default_verify_recording_timeout_secs(),
+            alert_send_delay_ms: 0,
+            max_alert_age_secs: 0,
+            upload_filename_template: default_upload_filename_template(),
+            max_upload_bytes: 0,
+            min_upload_quality: default_min_upload_quality(),
+            upload_format: UploadFormat::Jpeg,
+            fast_preview: false,
+            dead_letter_path: "".to_string(),
+            interaction_server_port: 0,
+            ack_mute_secs: default_ack_mute_secs(),
+            web_ui_port: 0,
+            service_priority: Vec::new(),
+            ordered_delivery: false,
+            shared_runtime: false,
+            heartbeat_interval_secs: 0,
+            heartbeat_service: "".to_string(),
+            daily_summary_time: "".to_string(),
+            daily_summary_service: "".to_string(),
+            log_file: "".to_string(),
+            log_max_size: default_log_max_size(),
+            log_max_files: default_log_max_files(),
+            detection_emoji: BTreeMap::new(),
+            alert_templates: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            templates: BTreeMap::new(),
+            camera_locations: BTreeMap::new(),
+            camera_confidence_thresholds: BTreeMap::new(),
+            alert_endpoint_auth: AlertEndpointAuth::default(),
+            blue_iris_api: BlueIrisApiConfig::default(),
+            targets: BTreeMap::new(),
+            debug_target: "".to_string(),
+            discord_mode: DiscordMode::Gateway,
+            no_services_action: NoServicesAction::Exit,
+            maintenance_windows: Vec::new(),
+            failure_escalation: Vec::new(),
+            backoff: BackoffConfig::default(),
             mqtt_config: MqttConfig {
                 host: "127.0.0.1".to_string(),
                 port: 1884,
                 max_packet_size: 2048000,
+                mqtt_channel_cap: default_mqtt_channel_cap(),
                 topic: "my_topic/#".to_string(),
                 device_id: "Bvr Chirp".to_string(),
                 username: "".to_string(),
                 password: "".to_string(),
+                detection_separators: default_detection_separators(),
+                suppress_empty_detections: false,
+                verbose_mqtt_logs: false,
+                payload_encoding: PayloadEncoding::Plain,
+                base64_variant: Base64Variant::Standard,
+                binary_image_topic: "".to_string(),
+                skip_retained: false,
+                startup_grace_secs: 0,
+                randomize_client_id: false,
+                on_image_error: ImageErrorAction::Drop,
+                arm_state_topic: "".to_string(),
+                disarmed_detections: Vec::new(),
+                maintenance_topic: "".to_string(),
+                max_queued_alerts: 0,
+                queue_full_policy: QueueFullPolicy::Block,
+                payload_hmac_secret: "".to_string(),
+                decode_html_entities: false,
+                camera_cooldown_secs: 0,
+                cooldown_break_on_new_type: false,
+                passthrough_mode: false,
+                passthrough_service: "".to_string(),
+                topic_routing: "".to_string(),
+                dedup_by_db_id: false,
+                dedup_window_secs: default_dedup_window_secs(),
+                credential_url: "".to_string(),
+                credential_token: "".to_string(),
             },
             matrix_config: MatrixConfig {
                 enabled: false,
                 username: "username".to_string(),
                 password: "password".to_string(),
                 homeserver_url: "https://matrix.org".to_string(),
-                room_id: "<room_id>".to_string(),
+                room_id: vec!["<room_id>".to_string()],
                 bot_name: "Bvr Chirp Bot".to_string(),
+                enable_reactions: false,
+                reaction_mute_secs: default_reaction_mute_secs(),
+                format: MatrixFormat::Html,
+                room_lookup_retries: default_room_lookup_retries(),
+                min_image_width: 0,
+                min_image_height: 0,
+                image_only: false,
+                plain_ascii: false,
+                max_field_length: default_matrix_max_field_length(),
             },
             discord_config: DiscordConfig {
                 enabled: false,
                 token: "<token>".to_string(),
                 channel_id: "<channel_id>".to_string(),
                 bot_name: "Bvr Chirp Bot".to_string(),
+                use_image_url: false,
+                forum_mode: false,
+                embed_fields: default_embed_fields(),
+                public_key: "".to_string(),
+                enable_commands: false,
+                warm_up: false,
+                webhook_url: "".to_string(),
+                avatar_url: "".to_string(),
+                camera_avatars: BTreeMap::new(),
+                pin_active_events: false,
+                active_event_secs: default_active_event_secs(),
+                min_image_width: 0,
+                min_image_height: 0,
+                image_only: false,
+                max_field_length: default_discord_max_field_length(),
             },
             slack_config: SlackConfig {
                 enabled: false,
                 token: "<api_key>".to_string(),
                 channel_id: "<channel_id>".to_string(),
                 bot_name: "Bvr Chirp Bot".to_string(),
+                connect_timeout_secs: default_connect_timeout_secs(),
+                request_timeout_secs: default_request_timeout_secs(),
+                signing_secret: "".to_string(),
+                button_text: default_button_text(),
+                button_emoji: false,
+                min_image_width: 0,
+                min_image_height: 0,
+                image_only: false,
+                plain_ascii: false,
+                max_field_length: default_slack_max_field_length(),
+                destinations: Vec::new(),
             },
+            mastodon_config: MastodonConfig::default(),
+            chat_webhook_config: ChatWebhookConfig::default(),
+            apprise_config: AppriseConfig::default(),
         }
     }
 }
 
+/// Replaces the placeholders of an alert template with the alert's values
+///
+/// # Arguments
+/// * `text` - Template text
+/// * `bvr_msg` - BvrChirpMessage containing alert details
+/// * `image_link` - Full URL of the hosted snapshot, empty if the alert has none
+fn render_alert_text(text: &str, bvr_msg: &BvrChirpMessage, image_link: &str) -> String {
+    text.replace("<CAMERA_NAME>", &bvr_msg.camera_name)
+        .replace("<IMAGE_URL>", image_link)
+        .replace("<DETECTIONS>", &bvr_msg.detections)
+        .replace("<COUNT>", &bvr_msg.count_summary())
+        .replace("<TIME>", &bvr_msg.time)
+        .replace("<DAILY_COUNT>", &bvr_msg.daily_count.map(|count| count.to_string()).unwrap_or_default())
+}
+
+/// Path and query opening a recording in Blue Iris UI3
+fn recording_path(db_id: &str, camera_name: &str) -> String {
+    format!("/ui3.htm?rec={}&cam={}&m=1", db_id, camera_name)
+}
+
+/// Percent-encodes a value for use in a URL query
+fn query_encode(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+/// Parses "lat,lng" coordinates, ie: "45.4215,-75.6972"
+fn parse_coordinates(location: &str) -> Option<(f64, f64)> {
+    let (lat, lng) = location.split_once(',')?;
+    let lat: f64 = lat.trim().parse().ok()?;
+    let lng: f64 = lng.trim().parse().ok()?;
+
+    if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lng) {
+        Some((lat, lng))
+    } else {
+        None
+    }
+}
+
+/// Replaces characters that don't belong in a file name
+fn filename_safe(value: &str) -> String {
+    value.trim().chars()
+        .map(|c| match c {
+            ' ' => '_',
+            ':' => '-',
+            c if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '@' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Deserializes either a single string or a list of strings into a list
+///
+/// Lets options that used to take one value (ie: `room_id = "!abc"`) also accept
+/// several (ie: `room_id = ["!abc", "!def"]`) without breaking existing configs.
+fn string_or_list<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        String(String),
+        List(Vec<String>),
+    }
+
+    Ok(match StringOrList::deserialize(deserializer)? {
+        StringOrList::String(value) => vec![value],
+        StringOrList::List(values) => values,
+    })
+}
+
+/// Characters that separate individual detections in the detections string
+fn default_detection_separators() -> String {
+    DEFAULT_DETECTION_SEPARATORS.to_string()
+}
+
+/// Name uploaded images get when no template is configured
+fn default_upload_filename_template() -> String {
+    "<CAMERA_NAME>.jpg".to_string()
+}
+
+fn default_min_upload_quality() -> u8 {
+    40
+}
+
+fn default_blank_frame_brightness() -> f64 {
+    20.0
+}
+
+fn default_blank_frame_contrast() -> f64 {
+    5.0
+}
+
+fn default_log_max_size() -> u64 {
+    10_000_000
+}
+
+fn default_log_max_files() -> usize {
+    5
+}
+
+/// Visibility of Mastodon statuses when none is configured
+fn default_mastodon_visibility() -> String {
+    "public".to_string()
+}
+
+/// Seconds a failing service is skipped once its circuit breaker opens
+fn default_breaker_cooldown_secs() -> u64 {
+    300
+}
+
+/// Seconds a camera and detections combination is suppressed after a ❌ reaction in Matrix
+fn default_reaction_mute_secs() -> u64 {
+    3600
+}
+
+fn default_room_lookup_retries() -> u32 {
+    4
+}
+
+/// Seconds to wait for Blue Iris to list an alert's recording before sending it anyway
+fn default_verify_recording_timeout_secs() -> u64 {
+    30
+}
+
+/// Seconds an alert ID is remembered for `dedup_by_db_id`
+fn default_dedup_window_secs() -> u64 {
+    300
+}
+
+/// Requests (subscribes, acknowledgements) the MQTT client can queue for the broker connection
+fn default_mqtt_channel_cap() -> usize {
+    10
+}
+
+/// Characters a value in a Matrix message is truncated to
+fn default_matrix_max_field_length() -> usize {
+    4000
+}
+
+/// Characters a Discord embed field is truncated to
+fn default_discord_max_field_length() -> usize {
+    DISCORD_MAX_FIELD_LENGTH
+}
+
+/// Characters a value in a Slack message is truncated to
+fn default_slack_max_field_length() -> usize {
+    SLACK_MAX_FIELD_LENGTH
+}
+
+/// Failures in a row before a failing service is escalated
+fn default_escalation_after_failures() -> u32 {
+    5
+}
+
+/// Color of the text burned into snapshots with `overlay_text`
+fn default_overlay_color() -> String {
+    "#FFFFFF".to_string()
+}
+
+/// Seconds without an alert after which a channel's event is over and its alert unpinned
+fn default_active_event_secs() -> u64 {
+    300
+}
+
+/// Name the chat webhook posts alerts as
+fn default_chat_webhook_bot_name() -> String {
+    "Bvr Chirp Bot".to_string()
+}
+
+/// Milliseconds to wait before the first retry
+fn default_backoff_initial_ms() -> u64 {
+    1000
+}
+
+/// Longest wait between retries in milliseconds
+fn default_backoff_max_ms() -> u64 {
+    60000
+}
+
+/// Factor the wait between retries grows by
+fn default_backoff_multiplier() -> f64 {
+    2.0
+}
+
+/// Fraction the waits between retries are randomized by
+fn default_backoff_jitter() -> f64 {
+    0.2
+}
+
+/// Times a failed alert is tried again
+fn default_backoff_alert_retries() -> u32 {
+    2
+}
+
+/// Seconds a camera's alerts are muted after someone acknowledges it
+fn default_ack_mute_secs() -> u64 {
+    600
+}
+
+/// Seconds to wait for a connection to an HTTP API to be established
+fn default_connect_timeout_secs() -> u64 {
+    10
+}
+
+/// Seconds to wait for an HTTP API request to complete, including uploads
+fn default_request_timeout_secs() -> u64 {
+    60
+}
+
+/// Text of the Slack button linking to the alert
+fn default_button_text() -> String {
+    "View Alert".to_string()
+}
+
+/// Field keys the Discord embed knows how to render
+pub const DISCORD_EMBED_FIELDS: &[&str] = &["camera", "detections", "time", "db_id", "daily_count", "details"];
+
+/// Texts shown in alerts that `labels` can replace, with their English default
+///
+/// The formats support the same placeholders as alert templates, and
+/// `multi_camera_title_format` also `<CAMERA_NAMES>`.
+pub const LABEL_DEFAULTS: &[(&str, &str)] = &[
+    ("title_format", "Detection on <CAMERA_NAME> camera"),
+    ("multi_camera_title_format", "Event seen on <CAMERA_NAMES>"),
+    ("detections_label", "Detections"),
+    ("time_label", "Time"),
+    ("today_label", "Today"),
+    ("daily_count_format", "Alert #<DAILY_COUNT>"),
+    ("daily_count_summary_format", "Alert #<DAILY_COUNT> today for <CAMERA_NAME>"),
+    ("camera_label", "Camera"),
+    ("alert_id_label", "Alert ID"),
+    ("details_label", "Details"),
+    ("map_label", "Map"),
+    ("live_label", "Live"),
+    ("full_image_label", "Full Image"),
+    ("acknowledge_label", "Acknowledge"),
+];
+
+/// Payload targets that mark an alert as a test alert when `debug_target` is set
+pub const DEBUG_TARGETS: &[&str] = &["debug", "test"];
+
+/// Names of the messaging services, as used in settings that refer to one
+pub const SERVICES: &[&str] = &["discord", "matrix", "slack", "mastodon", "chat_webhook", "apprise"];
+
+/// Color of the `overlay_text` when `overlay_color` isn't a valid color, white
+pub const OVERLAY_DEFAULT_COLOR: [u8; 3] = [255, 255, 255];
+
+/// Longest Discord embed field value, Discord rejects longer ones
+pub const DISCORD_MAX_FIELD_LENGTH: usize = 1024;
+/// Longest value in a Slack alert, so the title and description together fit in a 3000 character section
+pub const SLACK_MAX_FIELD_LENGTH: usize = 1500;
+
+/// Embed fields shown by Discord when none are configured
+fn default_embed_fields() -> Vec<String> {
+    vec!["detections".to_string(), "time".to_string(), "daily_count".to_string(), "details".to_string()]
+}
+
+impl BvrChirpConfig {
+    /// Builds a URL on the Blue Iris server, handling IPv6 hosts in `alert_endpoint`
+    ///
+    /// # Arguments
+    /// * `path` - Path and query starting with `/`
+    pub fn alert_url(&self, path: &str) -> String {
+        address::endpoint_url(&self.alert_endpoint, path)
+    }
+
+    /// Full URL of the snapshot hosted by the NVR, from the payload's `image_url`
+    ///
+    /// Relative paths (starting with `/`) are served from the alert endpoint.
+    ///
+    /// # Arguments
+    /// * `bvr_msg` - BvrChirpMessage with the `image_url` from its payload
+    ///
+    /// # Returns
+    /// * `None` if the payload had no `image_url`
+    pub fn image_link(&self, bvr_msg: &BvrChirpMessage) -> Option<String> {
+        let image_url = bvr_msg.image_url.as_deref().filter(|image_url| !image_url.is_empty())?;
+        if image_url.starts_with('/') {
+            Some(self.alert_url(image_url))
+        } else {
+            Some(image_url.to_owned())
+        }
+    }
+
+    /// URL opening an alert's recording in Blue Iris UI3
+    ///
+    /// Includes the `alert_endpoint_auth` token and/or username and password when
+    /// configured, so the link works without a separate login.
+    ///
+    /// # Arguments
+    /// * `bvr_msg` - BvrChirpMessage with the alert ID and camera name
+    pub fn alert_link(&self, bvr_msg: &BvrChirpMessage) -> String {
+        self.recording_link(&bvr_msg.db_id, &bvr_msg.camera_name)
+    }
+
+    /// URL opening a recording in Blue Iris UI3, see `alert_link`
+    ///
+    /// # Arguments
+    /// * `db_id` - Blue Iris record ID of the alert
+    /// * `camera_name` - Name of the camera
+    pub fn recording_link(&self, db_id: &str, camera_name: &str) -> String {
+        self.alert_url(&format!("{}{}", recording_path(db_id, camera_name), self.endpoint_credentials()))
+    }
+
+    /// The `alert_endpoint_auth` token and credentials as query parameters, each starting with `&`
+    fn endpoint_credentials(&self) -> String {
+        let auth = &self.alert_endpoint_auth;
+        let mut query = String::new();
+        if !auth.token_param.is_empty() && !auth.token.is_empty() {
+            query.push_str(&format!("&{}={}", query_encode(&auth.token_param), query_encode(&auth.token)));
+        }
+        if !auth.username.is_empty() {
+            query.push_str(&format!("&user={}&pw={}", query_encode(&auth.username), query_encode(&auth.password)));
+        }
+        query
+    }
+
+    /// URL the snapshot server fetches an alert's full size JPEG from, with the `alert_endpoint_auth`
+    /// credentials for a Blue Iris server that requires a login
+    ///
+    /// # Arguments
+    /// * `db_id` - Blue Iris record ID of the alert
+    pub fn full_jpeg_url(&self, db_id: &str) -> String {
+        self.alert_url(&format!("/alerts/{}?fulljpeg{}", db_id, self.endpoint_credentials()))
+    }
+
+    /// URL opening a recording in Blue Iris UI3 without the `alert_endpoint_auth` credentials
+    ///
+    /// Used on pages that have no login, where embedded credentials would be readable by anyone.
+    ///
+    /// # Arguments
+    /// * `db_id` - Blue Iris record ID of the alert
+    /// * `camera_name` - Name of the camera
+    pub fn public_recording_link(&self, db_id: &str, camera_name: &str) -> String {
+        self.alert_url(&recording_path(db_id, camera_name))
+    }
+
+    /// Base URL that alert links use to reach the snapshot server
+    ///
+    /// Returns `None` when the snapshot server is disabled (port 0). Falls back to
+    /// localhost when no public URL is configured.
+    pub fn snapshot_base_url(&self) -> Option<String> {
+        if self.snapshot_server_port == 0 {
+            return None;
+        }
+
+        if self.snapshot_server_url.is_empty() {
+            Some(format!("http://127.0.0.1:{}", self.snapshot_server_port))
+        } else {
+            Some(self.snapshot_server_url.trim_end_matches('/').to_string())
+        }
+    }
+
+    /// Link to an alert's full resolution frame on the snapshot server
+    ///
+    /// The link carries a signature of the record ID, so only links from an alert open a
+    /// frame and guessing other record IDs doesn't.
+    ///
+    /// # Arguments
+    /// * `db_id` - Blue Iris record ID of the alert
+    ///
+    /// # Returns
+    /// * `None` when the snapshot server is disabled or wouldn't serve the record ID
+    pub fn snapshot_link(&self, db_id: &str) -> Option<String> {
+        if !is_valid_db_id(db_id) {
+            return None;
+        }
+        self.snapshot_base_url()
+            .map(|base_url| format!("{}/snapshot/{}?sig={}", base_url, db_id, self.snapshot_signature(db_id)))
+    }
+
+    /// Hex encoded HMAC-SHA256 of a record ID, keyed with `snapshot_server_secret`
+    pub fn snapshot_signature(&self, db_id: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.snapshot_server_secret.as_bytes())
+            .expect("HMAC takes a key of any size");
+        mac.update(db_id.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Emoji prefix for an alert title, picked by the detections it contains
+    ///
+    /// Each `detection_emoji` keyword found in the detections (case-insensitive)
+    /// contributes its emoji, in the order the detections are listed.
+    ///
+    /// # Arguments
+    /// * `bvr_msg` - Alert to pick the emoji for
+    ///
+    /// # Returns
+    /// * String like "🚶 🚗 " ready to put in front of the title, empty when nothing matches
+    pub fn title_prefix(&self, bvr_msg: &BvrChirpMessage) -> String {
+        let detections = bvr_msg.detections.to_lowercase();
+        let mut matches: Vec<(usize, &str)> = self.detection_emoji.iter()
+            .filter(|(keyword, _)| !keyword.is_empty())
+            .filter_map(|(keyword, emoji)| detections.find(&keyword.to_lowercase()).map(|pos| (pos, emoji.as_str())))
+            .collect();
+        matches.sort_by_key(|(pos, _)| *pos);
+
+        let mut emoji: Vec<&str> = Vec::new();
+        for (_, candidate) in matches {
+            if !emoji.contains(&candidate) {
+                emoji.push(candidate);
+            }
+        }
+
+        emoji.iter().map(|emoji| format!("{} ", emoji)).collect()
+    }
+
+    /// Discord channel ID an alert's `target` refers to
+    ///
+    /// # Arguments
+    /// * `target` - The `target` of the alert, a name from `targets` or a raw channel ID
+    ///
+    /// # Returns
+    /// * The named target's `discord_channel_id`, or `target` itself when it isn't a known name
+    pub fn discord_channel_for<'a>(&'a self, target: &'a str) -> &'a str {
+        match self.targets.get(target) {
+            Some(named) if !named.discord_channel_id.is_empty() => &named.discord_channel_id,
+            _ => target,
+        }
+    }
+
+    /// Returns true if an alert is a test alert to route to `debug_target`
+    ///
+    /// Test alerts carry `"debug": true` in the payload, or a `target` of "debug", "test"
+    /// or the `debug_target` itself. Always false when `debug_target` isn't set.
+    ///
+    /// # Arguments
+    /// * `target` - The payload's `target`
+    /// * `debug_flag` - The payload's `debug` flag
+    pub fn is_debug_alert(&self, target: &str, debug_flag: bool) -> bool {
+        !self.debug_target.is_empty()
+            && (debug_flag || DEBUG_TARGETS.iter().any(|name| target.eq_ignore_ascii_case(name)) || target == self.debug_target)
+    }
+
+    /// Returns true if a service can send test alerts to `debug_target` instead of its usual channels
+    ///
+    /// Only Discord (without a webhook) and Matrix can be sent to a target, the other
+    /// services leave test alerts out.
+    ///
+    /// # Arguments
+    /// * `service` - Name of the service, matched case-insensitively
+    pub fn has_debug_destination(&self, service: &str) -> bool {
+        let named = self.targets.get(&self.debug_target);
+        match service.to_lowercase().as_str() {
+            "discord" => self.discord_config.webhook_url.is_empty() && self.discord_channel_for(&self.debug_target).parse::<u64>().is_ok(),
+            "matrix" => named.is_some_and(|target| !target.matrix_room_id.is_empty()),
+            _ => false,
+        }
+    }
+
+    /// Avatar shown on an alert posted through the Discord webhook
+    ///
+    /// # Arguments
+    /// * `camera` - Name of the camera, matched case-insensitively against `camera_avatars`
+    ///
+    /// # Returns
+    /// * The camera's avatar URL, falling back to `avatar_url`
+    /// * `None` when neither is set, the webhook's own avatar is used
+    pub fn discord_avatar_for(&self, camera: &str) -> Option<&str> {
+        let discord = &self.discord_config;
+        discord.camera_avatars.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(camera))
+            .map(|(_, avatar_url)| avatar_url.as_str())
+            .or(Some(discord.avatar_url.as_str()))
+            .filter(|avatar_url| !avatar_url.is_empty())
+    }
+
+    /// Title of an alert, with the emoji prefix and the wording of its template
+    ///
+    /// # Arguments
+    /// * `bvr_msg` - Alert to build the title for
+    ///
+    /// # Returns
+    /// * The template's title, or the `title_format` label ("Detection on <camera> camera")
+    ///   when no template matches, with `instance_label` in front or after it when configured
+    /// * The `multi_camera_title_format` label ("Event seen on <camera>, <camera>") for an
+    ///   alert collapsed from several cameras
+    pub fn alert_title(&self, bvr_msg: &BvrChirpMessage) -> String {
+        let image_link = self.image_link(bvr_msg).unwrap_or_default();
+        let title = match self.alert_template(bvr_msg) {
+            _ if !bvr_msg.related.is_empty() => render_alert_text(
+                &self.label("multi_camera_title_format").replace("<CAMERA_NAMES>", &bvr_msg.camera_names().join(", ")),
+                bvr_msg, &image_link),
+            Some(template) => render_alert_text(&template.title, bvr_msg, &image_link),
+            None => self.render_label("title_format", bvr_msg),
+        };
+        let title = format!("{}{}", self.title_prefix(bvr_msg), title);
+
+        match self.instance_label_position {
+            LabelPosition::Footer => title,
+            _ if self.instance_label.is_empty() => title,
+            LabelPosition::Prefix => format!("[{}] {}", self.instance_label, title),
+            LabelPosition::Suffix => format!("{} [{}]", title, self.instance_label),
+        }
+    }
+
+    /// Text shown in alerts for a `LABEL_DEFAULTS` key, from `labels` or the English default
+    ///
+    /// # Arguments
+    /// * `key` - Label key, ie: "time_label"
+    ///
+    /// # Returns
+    /// * The configured text, the default when it isn't configured, or empty for an unknown key
+    pub fn label(&self, key: &str) -> String {
+        self.labels.get(key)
+            .map(String::as_str)
+            .or_else(|| LABEL_DEFAULTS.iter().find(|(name, _)| *name == key).map(|(_, text)| *text))
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Label with the alert template placeholders filled in, for the `*_format` labels
+    ///
+    /// # Arguments
+    /// * `key` - Label key, ie: "daily_count_format"
+    /// * `bvr_msg` - Alert the placeholders are taken from
+    pub fn render_label(&self, key: &str, bvr_msg: &BvrChirpMessage) -> String {
+        render_alert_text(&self.label(key), bvr_msg, &self.image_link(bvr_msg).unwrap_or_default())
+    }
+
+    /// Describes how active the camera has been today, from the `daily_count_summary_format` label
+    ///
+    /// # Returns
+    /// * Text like "Alert #7 today for Driveway", empty when the alert carries no daily count
+    pub fn daily_count_summary(&self, bvr_msg: &BvrChirpMessage) -> String {
+        match bvr_msg.daily_count {
+            Some(_) => self.render_label("daily_count_summary_format", bvr_msg),
+            None => String::new(),
+        }
+    }
+
+    /// Line shown below the title of an alert, from its template
+    ///
+    /// # Returns
+    /// * The template's description, empty when no template matches or it has none
+    pub fn alert_description(&self, bvr_msg: &BvrChirpMessage) -> String {
+        self.alert_template(bvr_msg)
+            .map(|template| render_alert_text(&template.description, bvr_msg, &self.image_link(bvr_msg).unwrap_or_default()))
+            .unwrap_or_default()
+    }
+
+    /// Template selected by the first `templates` keyword found in the detections (case-insensitive)
+    fn alert_template(&self, bvr_msg: &BvrChirpMessage) -> Option<&AlertTemplate> {
+        let detections = bvr_msg.detections.to_lowercase();
+        self.templates.iter()
+            .filter(|(keyword, _)| !keyword.is_empty())
+            .filter_map(|(keyword, name)| detections.find(&keyword.to_lowercase()).map(|pos| (pos, name)))
+            .min_by_key(|(pos, _)| *pos)
+            .and_then(|(_, name)| self.alert_templates.get(name))
+    }
+
+    /// File name for an alert's uploaded image, rendered from `upload_filename_template`
+    ///
+    /// Supports the `<CAMERA_NAME>`, `<TIME>` and `<DB_ID>` placeholders. Characters
+    /// that don't belong in a file name are replaced, so a time of
+    /// "2024-06-01 14:03:22" becomes "2024-06-01_14-03-22".
+    ///
+    /// # Arguments
+    /// * `bvr_msg` - Alert the image belongs to
+    /// * `image` - The image as it's uploaded, ie: from `upload_image` or `preview_image`
+    ///
+    /// # Returns
+    /// * File name like "Driveway_2024-06-01_14-03-22.jpg", with the extension of the image's
+    ///   format when it isn't JPEG. A conversion to `upload_format` doesn't always keep its result.
+    pub fn upload_filename(&self, bvr_msg: &BvrChirpMessage, image: &[u8]) -> String {
+        let filename = self.render_upload_filename(bvr_msg);
+        let extension = match image::guess_format(image) {
+            Ok(image::ImageFormat::WebP) => UploadFormat::Webp.extension(),
+            Ok(image::ImageFormat::Png) => UploadFormat::Png.extension(),
+            _ => return filename,
+        };
+
+        let stem = filename.rsplit_once('.').map_or(filename.as_str(), |(stem, _)| stem);
+        format!("{}.{}", stem, extension)
+    }
+
+    /// `upload_filename_template` with the alert's values filled in, see `upload_filename`
+    fn render_upload_filename(&self, bvr_msg: &BvrChirpMessage) -> String {
+        self.upload_filename_template
+            .replace("<CAMERA_NAME>", &filename_safe(&bvr_msg.camera_name))
+            .replace("<TIME>", &filename_safe(&bvr_msg.time))
+            .replace("<DB_ID>", &filename_safe(&bvr_msg.db_id))
+    }
+
+    /// File name for an alert's audio clip, the image's file name with the clip's extension
+    ///
+    /// # Arguments
+    /// * `bvr_msg` - Alert the clip belongs to
+    /// * `extension` - Extension of the clip's format, ie: "ogg"
+    ///
+    /// # Returns
+    /// * File name like "Driveway_2024-06-01_14-03-22.ogg"
+    pub fn audio_filename(&self, bvr_msg: &BvrChirpMessage, extension: &str) -> String {
+        let filename = self.render_upload_filename(bvr_msg);
+        let stem = filename.rsplit_once('.').map_or(filename.as_str(), |(stem, _)| stem);
+        format!("{}.{}", stem, extension)
+    }
+
+    /// File name for an alert's metadata attached with `attach_metadata_json`, the image's
+    /// file name with a "json" extension
+    ///
+    /// # Arguments
+    /// * `bvr_msg` - Alert the metadata belongs to
+    ///
+    /// # Returns
+    /// * File name like "Driveway_2024-06-01_14-03-22.json"
+    pub fn metadata_filename(&self, bvr_msg: &BvrChirpMessage) -> String {
+        self.audio_filename(bvr_msg, "json")
+    }
+
+    /// Metadata of an alert attached as a file with `attach_metadata_json`
+    ///
+    /// # Arguments
+    /// * `bvr_msg` - Alert to describe
+    ///
+    /// # Returns
+    /// * `Some((file_name, json))` with the pretty printed metadata
+    /// * `None` if `attach_metadata_json` is off
+    pub fn metadata_attachment(&self, bvr_msg: &BvrChirpMessage) -> Option<(String, Vec<u8>)> {
+        if !self.attach_metadata_json {
+            return None;
+        }
+        let json = serde_json::to_vec_pretty(&bvr_msg.metadata_json()).unwrap_or_default();
+        Some((self.metadata_filename(bvr_msg), json))
+    }
+
+    /// Alert image as it should be uploaded, converted to `upload_format` and no larger than `max_upload_bytes`
+    ///
+    /// When the converted image is over the cap, the image is first re-encoded at a lower JPEG quality
+    /// until it fits, then converted. Converting only keeps an image that comes out smaller, so the
+    /// result fits whenever the JPEG does.
+    ///
+    /// # Arguments
+    /// * `image` - Encoded alert image
+    pub fn upload_image<'a>(&self, image: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.upload_format == UploadFormat::Jpeg {
+            return image_processing::fit_to_size(image, self.max_upload_bytes, self.min_upload_quality);
+        }
+
+        let converted = image_processing::convert(image, self.upload_format.image_format());
+        if self.max_upload_bytes == 0 || converted.len() <= self.max_upload_bytes {
+            return converted;
+        }
+
+        match image_processing::fit_to_size(image, self.max_upload_bytes, self.min_upload_quality) {
+            Cow::Owned(fitted) => Cow::Owned(image_processing::convert(&fitted, self.upload_format.image_format()).into_owned()),
+            // The image couldn't be made smaller, the conversion is as close to the cap as it gets
+            Cow::Borrowed(_) => converted,
+        }
+    }
+
+    /// Returns true if the alert image is a dark or featureless frame with `skip_blank_frames` enabled
+    ///
+    /// A frame is blank when its average brightness is below `blank_frame_brightness`
+    /// or its pixels barely vary (standard deviation below `blank_frame_contrast`).
+    ///
+    /// # Arguments
+    /// * `image` - Encoded alert image
+    pub fn is_blank_frame(&self, image: &[u8]) -> bool {
+        if !self.skip_blank_frames || image.is_empty() {
+            return false;
+        }
+
+        match image_processing::brightness(image) {
+            Some((mean, std_dev)) => mean < self.blank_frame_brightness || std_dev < self.blank_frame_contrast,
+            None => false,
+        }
+    }
+
+    /// Smallest image a service accepts, from its `min_image_width` and `min_image_height`
+    ///
+    /// # Arguments
+    /// * `service` - Name of the service, matched case-insensitively
+    ///
+    /// # Returns
+    /// * `(width, height)` in pixels, 0 where there's no minimum
+    pub fn min_image_size(&self, service: &str) -> (u32, u32) {
+        match service.to_lowercase().as_str() {
+            "discord" => (self.discord_config.min_image_width, self.discord_config.min_image_height),
+            "matrix" => (self.matrix_config.min_image_width, self.matrix_config.min_image_height),
+            "slack" => (self.slack_config.min_image_width, self.slack_config.min_image_height),
+            "mastodon" => (self.mastodon_config.min_image_width, self.mastodon_config.min_image_height),
+            "chat_webhook" => (self.chat_webhook_config.min_image_width, self.chat_webhook_config.min_image_height),
+            "apprise" => (self.apprise_config.min_image_width, self.apprise_config.min_image_height),
+            _ => (0, 0),
+        }
+    }
+
+    /// Returns true if a service is set to post only the image of alerts, with `image_only`
+    ///
+    /// # Arguments
+    /// * `service` - Name of the service, matched case-insensitively
+    pub fn image_only(&self, service: &str) -> bool {
+        match service.to_lowercase().as_str() {
+            "discord" => self.discord_config.image_only,
+            "matrix" => self.matrix_config.image_only,
+            "slack" => self.slack_config.image_only,
+            "mastodon" => self.mastodon_config.image_only,
+            "chat_webhook" => self.chat_webhook_config.image_only,
+            "apprise" => self.apprise_config.image_only,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the alert image is narrower or shorter than the service's minimum
+    ///
+    /// Images whose size can't be read aren't considered too small.
+    ///
+    /// # Arguments
+    /// * `service` - Name of the service the alert is sent to
+    /// * `image` - Encoded alert image
+    pub fn is_image_too_small(&self, service: &str, image: &[u8]) -> bool {
+        let (min_width, min_height) = self.min_image_size(service);
+        if (min_width == 0 && min_height == 0) || image.is_empty() {
+            return false;
+        }
+
+        match image_processing::dimensions(image) {
+            Some((width, height)) => width < min_width || height < min_height,
+            None => false,
+        }
+    }
+
+    /// Small version of the alert image to send first when `fast_preview` is enabled
+    ///
+    /// # Arguments
+    /// * `bvr_msg` - BvrChirpMessage with the alert image
+    ///
+    /// # Returns
+    /// * `Some(preview)` to send before the full image
+    /// * `None` if previews are disabled, the alert has no image or it can't be scaled down
+    pub fn preview_image(&self, bvr_msg: &BvrChirpMessage) -> Option<Vec<u8>> {
+        if !self.fast_preview || !bvr_msg.has_image() {
+            return None;
+        }
+        image_processing::thumbnail(&bvr_msg.image)
+    }
+
+    /// OpenStreetMap link to a camera's configured location
+    ///
+    /// # Arguments
+    /// * `camera` - Name of the camera
+    ///
+    /// # Returns
+    /// * `Some(url)` if `camera_locations` has valid "lat,lng" coordinates for the camera
+    /// * `None` otherwise
+    pub fn map_url(&self, camera: &str) -> Option<String> {
+        let (lat, lng) = parse_coordinates(self.camera_locations.get(camera)?)?;
+        Some(format!("https://www.openstreetmap.org/?mlat={lat}&mlon={lng}#map=18/{lat}/{lng}"))
+    }
+
+    /// Link to a camera's live view, from `live_url_template`
+    ///
+    /// `<CAMERA_NAME>` is replaced by the camera name and `<ALERT_ENDPOINT>` by the Blue Iris
+    /// base URL. Relative paths (starting with `/`) are served from the alert endpoint.
+    ///
+    /// # Arguments
+    /// * `camera` - Name of the camera
+    ///
+    /// # Returns
+    /// * `None` if `live_url_template` isn't set
+    pub fn live_url(&self, camera: &str) -> Option<String> {
+        let template = self.live_url_template.trim();
+        if template.is_empty() {
+            return None;
+        }
+
+        let url = template
+            .replace("<CAMERA_NAME>", &query_encode(camera))
+            .replace("<ALERT_ENDPOINT>", &address::normalize_endpoint(&self.alert_endpoint));
+        if url.starts_with('/') {
+            Some(self.alert_url(&url))
+        } else {
+            Some(url)
+        }
+    }
+
+    /// Color of the `overlay_text`, from `overlay_color`
+    ///
+    /// # Returns
+    /// * RGB color, `None` if `overlay_color` isn't a "#RRGGBB" hex color
+    pub fn overlay_color(&self) -> Option<[u8; 3]> {
+        let hex = self.overlay_color.trim().strip_prefix('#')?;
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        Some([channel(0)?, channel(2)?, channel(4)?])
+    }
+
+    /// Footer text identifying which bot and instance sent an alert
+    ///
+    /// # Arguments
+    /// * `bot_name` - Name of the bot for the service the alert is sent to
+    ///
+    /// # Returns
+    /// * String like "Bvr Chirp Bot • Cabin", or just the bot name without an instance label
+    pub fn footer_text(&self, bot_name: &str) -> String {
+        if self.instance_label.is_empty() {
+            bot_name.to_string()
+        } else {
+            format!("{} • {}", bot_name, self.instance_label)
+        }
+    }
+
+    /// Returns true if Slack alerts should carry an "Acknowledge" button
+    pub fn slack_acknowledge_enabled(&self) -> bool {
+        self.interaction_server_port != 0 && !self.slack_config.signing_secret.is_empty()
+    }
+
+    /// Returns true if Discord alerts should carry an "Acknowledge" button
+    pub fn discord_acknowledge_enabled(&self) -> bool {
+        self.interaction_server_port != 0 && !self.discord_config.public_key.is_empty()
+    }
+
+    /// Position of a service in `service_priority`, services not listed come last
+    ///
+    /// # Arguments
+    /// * `service` - Name of the service, matched case-insensitively
+    pub fn service_rank(&self, service: &str) -> usize {
+        self.service_priority.iter()
+            .position(|name| name.eq_ignore_ascii_case(service))
+            .unwrap_or(self.service_priority.len())
+    }
+
+    /// Local time of day the daily summary is sent at, from `daily_summary_time` (ie: "07:30")
+    ///
+    /// # Returns
+    /// * `None` when the summary is disabled or the time can't be parsed
+    pub fn daily_summary_local_time(&self) -> Option<NaiveTime> {
+        NaiveTime::parse_from_str(self.daily_summary_time.trim(), "%H:%M").ok()
+    }
+
+    /// Whether a messaging service is enabled
+    ///
+    /// # Arguments
+    /// * `service` - Name of the service, matched case-insensitively
+    ///
+    /// # Returns
+    /// * `None` if `service` isn't one of `SERVICES`
+    pub fn service_enabled(&self, service: &str) -> Option<bool> {
+        match service.to_lowercase().as_str() {
+            "discord" => Some(self.discord_config.enabled),
+            "matrix" => Some(self.matrix_config.enabled),
+            "slack" => Some(self.slack_config.enabled),
+            "mastodon" => Some(self.mastodon_config.enabled),
+            "chat_webhook" => Some(self.chat_webhook_config.enabled),
+            "apprise" => Some(self.apprise_config.enabled),
+            _ => None,
+        }
+    }
+
+    /// Checks that a setting names one of the messaging services and that it's enabled
+    ///
+    /// # Arguments
+    /// * `setting` - Name of the setting, used in the problem description
+    /// * `service` - The configured service name, matched case-insensitively
+    /// * `problems` - Problems found so far, added to
+    fn validate_service(&self, setting: &str, service: &str, problems: &mut Vec<String>) {
+        match self.service_enabled(service) {
+            Some(true) => {}
+            Some(false) => problems.push(format!("{} '{}' is not enabled", setting, service)),
+            None => problems.push(format!("{} must be one of: {}, got '{}'", setting, SERVICES.join(", "), service)),
+        }
+    }
+
+    /// Checks the configuration for problems without connecting to anything
+    ///
+    /// # Returns
+    /// * Vec with a description of each problem found, empty if the config is valid
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if Url::parse(&address::normalize_endpoint(&self.alert_endpoint)).is_err() {
+            problems.push(format!("alert_endpoint is not a valid URL: '{}'", self.alert_endpoint));
+        }
+
+        if self.no_services_action == NoServicesAction::Exit
+            && !SERVICES.iter().any(|service| self.service_enabled(service) == Some(true)) {
+            problems.push(format!("No messaging service is enabled, enable at least one of: {}", SERVICES.join(", ")));
+        }
+
+        if self.snapshot_server_port != 0 && !self.snapshot_server_url.is_empty()
+            && Url::parse(&self.snapshot_server_url).is_err() {
+            problems.push(format!("snapshot_server_url is not a valid URL: '{}'", self.snapshot_server_url));
+        }
+
+        if !(1..=100).contains(&self.min_upload_quality) {
+            problems.push(format!("min_upload_quality must be between 1 and 100, got {}", self.min_upload_quality));
+        }
+
+        if self.skip_blank_frames {
+            if !(0.0..=255.0).contains(&self.blank_frame_brightness) {
+                problems.push(format!("blank_frame_brightness must be between 0 and 255, got {}", self.blank_frame_brightness));
+            }
+            if !(0.0..=255.0).contains(&self.blank_frame_contrast) {
+                problems.push(format!("blank_frame_contrast must be between 0 and 255, got {}", self.blank_frame_contrast));
+            }
+        }
+
+        for service in &self.service_priority {
+            if !SERVICES.contains(&service.to_lowercase().as_str()) {
+                problems.push(format!("service_priority has unknown service '{}', expected one of: {}", service, SERVICES.join(", ")));
+            }
+        }
+
+        if self.slack_config.enabled && self.slack_config.button_text.trim().is_empty() {
+            problems.push("button_text under slack_config can't be empty".to_string());
+        }
+
+        if self.heartbeat_interval_secs != 0 {
+            self.validate_service("heartbeat_service", &self.heartbeat_service, &mut problems);
+        }
+
+        if !self.daily_summary_time.is_empty() {
+            if self.daily_summary_local_time().is_none() {
+                problems.push(format!("daily_summary_time must be a local time like \"07:30\", got '{}'", self.daily_summary_time));
+            }
+            self.validate_service("daily_summary_service", &self.daily_summary_service, &mut problems);
+        }
+
+        if !self.log_file.is_empty() {
+            if self.log_max_size == 0 {
+                problems.push("log_max_size must not be 0".to_string());
+            }
+            if self.log_max_files == 0 {
+                problems.push("log_max_files must not be 0".to_string());
+            }
+        }
+
+        if !self.debug_target.is_empty() && !self.targets.contains_key(&self.debug_target)
+            && self.debug_target.parse::<u64>().is_err() {
+            problems.push(format!("debug_target '{}' is neither a name in targets nor a Discord channel ID", self.debug_target));
+        }
+
+        for (name, target) in &self.targets {
+            if target.discord_channel_id.is_empty() && target.matrix_room_id.is_empty() {
+                problems.push(format!("targets.{} has neither a discord_channel_id nor a matrix_room_id", name));
+            }
+            if !target.discord_channel_id.is_empty() && target.discord_channel_id.parse::<u64>().is_err() {
+                problems.push(format!("targets.{}.discord_channel_id is not a channel ID: '{}'", name, target.discord_channel_id));
+            }
+            for service in &target.primary_services {
+                self.validate_service(&format!("targets.{}.primary_services", name), service, &mut problems);
+            }
+        }
+
+        for (index, window) in self.maintenance_windows.iter().enumerate() {
+            check_value(&mut problems, &format!("maintenance_windows[{}].camera", index), &window.camera);
+            match (parse_local_time(&window.start), parse_local_time(&window.end)) {
+                (Some(start), Some(end)) if start >= end => {
+                    problems.push(format!("maintenance_windows[{}] ends before it starts", index));
+                }
+                (Some(_), Some(_)) => {}
+                _ => problems.push(format!("maintenance_windows[{}] start and end must look like '2024-06-01 09:00'", index)),
+            }
+        }
+
+        for (index, escalation) in self.failure_escalation.iter().enumerate() {
+            self.validate_service(&format!("failure_escalation[{}].service", index), &escalation.service, &mut problems);
+            self.validate_service(&format!("failure_escalation[{}].notify_service", index), &escalation.notify_service, &mut problems);
+            if escalation.service.eq_ignore_ascii_case(&escalation.notify_service) {
+                problems.push(format!("failure_escalation[{}] must notify another service than the failing one", index));
+            }
+            if escalation.after_failures == 0 {
+                problems.push(format!("failure_escalation[{}].after_failures must not be 0", index));
+            }
+        }
+
+        for (keyword, name) in &self.templates {
+            if !self.alert_templates.contains_key(name) {
+                problems.push(format!("templates.{} refers to '{}', which isn't defined in alert_templates", keyword, name));
+            }
+        }
+
+        for (camera, location) in &self.camera_locations {
+            if parse_coordinates(location).is_none() {
+                problems.push(format!("camera_locations.{} must be \"lat,lng\" coordinates: '{}'", camera, location));
+            }
+        }
+
+        for (camera, threshold) in &self.camera_confidence_thresholds {
+            if !(0.0..=100.0).contains(threshold) {
+                problems.push(format!("camera_confidence_thresholds.{} must be a percentage between 0 and 100: {}", camera, threshold));
+            }
+        }
+
+        if let Some(live_url) = self.live_url(VALIDATION_CAMERA_NAME) {
+            if Url::parse(&live_url).is_err() {
+                problems.push(format!("live_url_template doesn't make a valid URL: '{}'", self.live_url_template));
+            }
+        }
+
+        if self.overlay_text && self.overlay_color().is_none() {
+            problems.push(format!("overlay_color must be a hex color like \"#FFFFFF\", got '{}'", self.overlay_color));
+        }
+
+        let mqtt = &self.mqtt_config;
+        if self.interaction_server_port != 0 && self.interaction_server_port == self.snapshot_server_port {
+            problems.push("interaction_server_port and snapshot_server_port must be different".to_string());
+        }
+        if self.web_ui_port != 0
+            && (self.web_ui_port == self.snapshot_server_port || self.web_ui_port == self.interaction_server_port) {
+            problems.push("web_ui_port must be different from snapshot_server_port and interaction_server_port".to_string());
+        }
+
+        if mqtt.host.is_empty() {
+            problems.push("mqtt_config.host is empty".to_string());
+        }
+        if mqtt.port == 0 {
+            problems.push("mqtt_config.port must not be 0".to_string());
+        }
+        if mqtt.topic.is_empty() {
+            problems.push("mqtt_config.topic is empty".to_string());
+        } else {
+            check_topic_filter(&mut problems, "mqtt_config.topic", &mqtt.topic);
+        }
+        if !mqtt.binary_image_topic.is_empty() {
+            check_topic_filter(&mut problems, "mqtt_config.binary_image_topic", &mqtt.binary_image_topic);
+        }
+        if !mqtt.arm_state_topic.is_empty() {
+            check_topic_filter(&mut problems, "mqtt_config.arm_state_topic", &mqtt.arm_state_topic);
+        }
+        if !mqtt.maintenance_topic.is_empty() {
+            check_topic_filter(&mut problems, "mqtt_config.maintenance_topic", &mqtt.maintenance_topic);
+        }
+        if mqtt.max_packet_size == 0 {
+            problems.push("mqtt_config.max_packet_size must not be 0".to_string());
+        }
+        if mqtt.mqtt_channel_cap == 0 {
+            problems.push("mqtt_config.mqtt_channel_cap must not be 0".to_string());
+        }
+        if mqtt.passthrough_mode {
+            self.validate_service("mqtt_config.passthrough_service", &mqtt.passthrough_service, &mut problems);
+        }
+        if mqtt.dedup_by_db_id {
+            if mqtt.dedup_window_secs == 0 {
+                problems.push("mqtt_config.dedup_window_secs must not be 0".to_string());
+            }
+            // Burst capture relies on getting several frames with the same db_id
+            if self.best_frame_window_ms > 0 {
+                problems.push("mqtt_config.dedup_by_db_id can't be used with best_frame_window_ms, it would drop every frame but the first".to_string());
+            }
+        }
+        if !mqtt.credential_url.is_empty() && Url::parse(&mqtt.credential_url).is_err() {
+            problems.push(format!("mqtt_config.credential_url is not a valid URL: '{}'", mqtt.credential_url));
+        }
+        if !mqtt.topic_routing.is_empty() {
+            let segments: Vec<&str> = mqtt.topic_routing.split('/').collect();
+            if !segments.iter().any(|segment| *segment == "<SERVICE>" || *segment == "<TARGET>") {
+                problems.push(format!("mqtt_config.topic_routing has neither a <SERVICE> nor a <TARGET> segment: '{}'", mqtt.topic_routing));
+            }
+            if segments.iter().filter(|segment| **segment == "<SERVICE>").count() > 1
+                || segments.iter().filter(|segment| **segment == "<TARGET>").count() > 1 {
+                problems.push(format!("mqtt_config.topic_routing has a placeholder more than once: '{}'", mqtt.topic_routing));
+            }
+            if segments.contains(&"#") {
+                problems.push(format!("mqtt_config.topic_routing can't contain '#', use one '+' per segment: '{}'", mqtt.topic_routing));
+            }
+        }
+
+        let matrix = &self.matrix_config;
+        if matrix.enabled {
+            check_value(&mut problems, "matrix_config.username", &matrix.username);
+            check_value(&mut problems, "matrix_config.password", &matrix.password);
+            if Url::parse(&matrix.homeserver_url).is_err() {
+                problems.push(format!("matrix_config.homeserver_url is not a valid URL: '{}'", matrix.homeserver_url));
+            }
+            if matrix.room_id.is_empty() {
+                problems.push("matrix_config.room_id is empty".to_string());
+            }
+            for room_id in &matrix.room_id {
+                if !room_id.starts_with('!') {
+                    problems.push(format!("matrix_config.room_id must be a room ID starting with '!': '{}'", room_id));
+                }
+            }
+            if matrix.max_field_length == 0 {
+                problems.push("matrix_config.max_field_length must not be 0".to_string());
+            }
+        }
+
+        let discord = &self.discord_config;
+        if discord.enabled {
+            if !(1..=DISCORD_MAX_FIELD_LENGTH).contains(&discord.max_field_length) {
+                problems.push(format!("discord_config.max_field_length must be between 1 and {}: {}",
+                                      DISCORD_MAX_FIELD_LENGTH, discord.max_field_length));
+            }
+            // A webhook is authenticated by its URL, the bot token is only needed for commands
+            if discord.webhook_url.is_empty() || discord.enable_commands {
+                check_value(&mut problems, "discord_config.token", &discord.token);
+            }
+            if !discord.webhook_url.is_empty() {
+                if Url::parse(&discord.webhook_url).is_err() {
+                    problems.push(format!("discord_config.webhook_url is not a valid URL: '{}'", discord.webhook_url));
+                }
+                if discord.forum_mode {
+                    problems.push("discord_config.forum_mode can't be used with webhook_url".to_string());
+                }
+            }
+            // Pins need the bot, and a forum post per alert has nothing to pin it above
+            if discord.pin_active_events {
+                if !discord.webhook_url.is_empty() || discord.forum_mode {
+                    problems.push("discord_config.pin_active_events can't be used with webhook_url or forum_mode".to_string());
+                }
+                if discord.active_event_secs == 0 {
+                    problems.push("discord_config.active_event_secs must be greater than 0".to_string());
+                }
+            }
+            // Slash commands are registered and received over the gateway
+            if self.discord_mode == DiscordMode::Rest && discord.enable_commands {
+                problems.push("discord_config.enable_commands needs discord_mode \"gateway\"".to_string());
+            }
+            for (name, avatar_url) in std::iter::once(("avatar_url", &discord.avatar_url))
+                .chain(discord.camera_avatars.iter().map(|(camera, avatar_url)| (camera.as_str(), avatar_url))) {
+                if !avatar_url.is_empty() && Url::parse(avatar_url).is_err() {
+                    problems.push(format!("discord_config avatar for '{}' is not a valid URL: '{}'", name, avatar_url));
+                }
+            }
+            for key in &discord.embed_fields {
+                if !DISCORD_EMBED_FIELDS.contains(&key.to_lowercase().as_str()) {
+                    problems.push(format!("discord_config.embed_fields has unknown field '{}', expected one of: {}",
+                                          key, DISCORD_EMBED_FIELDS.join(", ")));
+                }
+            }
+        }
+
+        let backoff = &self.backoff;
+        if backoff.initial_ms == 0 {
+            problems.push("backoff.initial_ms must not be 0".to_string());
+        }
+        if backoff.max_ms < backoff.initial_ms {
+            problems.push(format!("backoff.max_ms ({}) must not be below backoff.initial_ms ({})", backoff.max_ms, backoff.initial_ms));
+        }
+        if backoff.multiplier.is_nan() || backoff.multiplier < 1.0 {
+            problems.push(format!("backoff.multiplier must be at least 1.0, got {}", backoff.multiplier));
+        }
+        if !(0.0..1.0).contains(&backoff.jitter) {
+            problems.push(format!("backoff.jitter must be from 0.0 up to (but not including) 1.0, got {}", backoff.jitter));
+        }
+
+        for key in self.labels.keys() {
+            if !LABEL_DEFAULTS.iter().any(|(name, _)| name == key) {
+                problems.push(format!("labels has unknown key '{}', expected one of: {}", key,
+                                      LABEL_DEFAULTS.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ")));
+            }
+        }
+
+        let auth = &self.alert_endpoint_auth;
+        if auth.token_param.is_empty() != auth.token.is_empty() {
+            problems.push("alert_endpoint_auth needs both token_param and token, or neither".to_string());
+        }
+        if auth.username.is_empty() && !auth.password.is_empty() {
+            problems.push("alert_endpoint_auth.password is set without a username".to_string());
+        }
+
+        if self.verify_recording {
+            check_value(&mut problems, "blue_iris_api.username", &self.blue_iris_api.username);
+            if self.verify_recording_timeout_secs == 0 {
+                problems.push("verify_recording_timeout_secs must not be 0".to_string());
+            }
+        }
+
+        let slack = &self.slack_config;
+        if slack.enabled {
+            if !(1..=SLACK_MAX_FIELD_LENGTH).contains(&slack.max_field_length) {
+                problems.push(format!("slack_config.max_field_length must be between 1 and {}: {}",
+                                      SLACK_MAX_FIELD_LENGTH, slack.max_field_length));
+            }
+            // The main token may be left empty when every alert goes to the extra destinations
+            if !slack.token.is_empty() || slack.destinations.is_empty() {
+                check_value(&mut problems, "slack_config.token", &slack.token);
+                check_value(&mut problems, "slack_config.channel_id", &slack.channel_id);
+            }
+            for (index, destination) in slack.destinations.iter().enumerate() {
+                check_value(&mut problems, &format!("slack_config.destinations[{}].token", index), &destination.token);
+                check_value(&mut problems, &format!("slack_config.destinations[{}].channel_id", index), &destination.channel_id);
+            }
+        }
+
+        let mastodon = &self.mastodon_config;
+        if mastodon.enabled {
+            if Url::parse(&mastodon.instance_url).is_err() {
+                problems.push(format!("mastodon_config.instance_url is not a valid URL: '{}'", mastodon.instance_url));
+            }
+            check_value(&mut problems, "mastodon_config.access_token", &mastodon.access_token);
+            if !MASTODON_VISIBILITIES.contains(&mastodon.visibility.as_str()) {
+                problems.push(format!("mastodon_config.visibility must be one of: {}, got '{}'",
+                                      MASTODON_VISIBILITIES.join(", "), mastodon.visibility));
+            }
+        }
+
+        let chat_webhook = &self.chat_webhook_config;
+        if chat_webhook.enabled {
+            check_value(&mut problems, "chat_webhook_config.webhook_url", &chat_webhook.webhook_url);
+            let url = chat_webhook.webhook_url.trim();
+            if !url.is_empty() && !url.starts_with('<') && Url::parse(url).is_err() {
+                problems.push(format!("chat_webhook_config.webhook_url is not a valid URL: '{}'", chat_webhook.webhook_url));
+            }
+        }
+
+        let apprise = &self.apprise_config;
+        if apprise.enabled {
+            if Url::parse(&apprise.server_url).is_err() {
+                problems.push(format!("apprise_config.server_url is not a valid URL: '{}'", apprise.server_url));
+            }
+            // The server either notifies the services of a saved configuration or the URLs sent with each alert
+            match (apprise.config_key.is_empty(), apprise.urls.is_empty()) {
+                (true, true) => problems.push("apprise_config needs a config_key or urls".to_string()),
+                (false, false) => problems.push("apprise_config can't have both a config_key and urls".to_string()),
+                _ => {}
+            }
+            if !apprise.config_key.is_empty() {
+                check_value(&mut problems, "apprise_config.config_key", &apprise.config_key);
+            }
+            if !apprise.tag.is_empty() && apprise.config_key.is_empty() {
+                problems.push("apprise_config.tag only applies with a config_key".to_string());
+            }
+        }
+
+        problems
+    }
+}
+
+/// Records a problem if a required value is empty or still a `<placeholder>`
+fn check_value(problems: &mut Vec<String>, name: &str, value: &str) {
+    if value.trim().is_empty() {
+        problems.push(format!("{} is empty", name));
+    } else if value.starts_with('<') && value.ends_with('>') {
+        problems.push(format!("{} is still the placeholder '{}'", name, value));
+    }
+}
+
+/// Adds a problem when a topic isn't a filter the broker accepts, e.g. `a/#/b`
+fn check_topic_filter(problems: &mut Vec<String>, name: &str, topic: &str) {
+    if !valid_filter(topic) {
+        problems.push(format!("{} is not a valid MQTT topic filter: '{}'", name, topic));
+    }
+}
+
+/// Loads and validates a config file without printing anything
+///
+/// Unlike `load_config` a missing file is reported as a problem rather than
+/// falling back to the defaults.
+///
+/// # Arguments
+/// * `config_path` - Path to the config file
+///
+/// # Returns
+/// * Vec with a description of each problem found, empty if the config is valid
+pub fn validate_config(config_path: &str) -> Vec<String> {
+    if !Path::new(config_path).exists() {
+        return vec![format!("Config file does not exist: {}", config_path)];
+    }
+
+    match confy::load_path::<BvrChirpConfig>(PathBuf::from(config_path)) {
+        Ok(cfg) => cfg.validate(),
+        Err(e) => vec![format!("Failed to parse config file {}: {}", config_path, e)],
+    }
+}
+
+/// Returns true if the record ID looks like a Blue Iris record ID, the only IDs the
+/// snapshot server serves, so a request can't traverse other paths
+pub fn is_valid_db_id(db_id: &str) -> bool {
+    !db_id.is_empty() && db_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '@')
+}
+
 pub fn load_config(config_path: String) -> Result<BvrChirpConfig, Box<dyn Error>> {
     // Check if the file exists
     if !Path::new(&config_path).exists() {
@@ -109,4 +2184,112 @@ pub fn load_config(config_path: String) -> Result<BvrChirpConfig, Box<dyn Error>
             Err(Box::new(e))
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fully populated config with every service enabled
+    const FULL_CONFIG_FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/full_config.toml");
+
+    #[test]
+    fn default_config_round_trips_through_toml() {
+        let path = std::env::temp_dir().join(format!("bvr_chirp_round_trip_{}.toml", std::process::id()));
+        let default = BvrChirpConfig::default();
+
+        confy::store_path(&path, &default).expect("default config serializes");
+        let loaded: BvrChirpConfig = confy::load_path(&path).expect("default config deserializes");
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(loaded, default);
+    }
+
+    #[test]
+    fn full_config_fixture_loads() {
+        let cfg = load_config(FULL_CONFIG_FIXTURE.to_string()).expect("fixture loads");
+
+        assert_eq!(cfg.alert_url("/ui3.htm"), "http://[2001:db8::1]:81/ui3.htm");
+        assert_eq!(cfg.instance_label, "Cabin");
+        assert_eq!(cfg.best_frame_window_ms, 1500);
+        assert_eq!(cfg.detection_emoji.get("person").map(String::as_str), Some("🚶"));
+        assert_eq!(cfg.label("time_label"), "Heure");
+        assert_eq!(cfg.label("map_label"), "Map");
+        assert_eq!(cfg.live_url("Front Door").as_deref(), Some("http://[2001:db8::1]:81/ui3.htm?cam=Front+Door&m=1"));
+        assert_eq!(cfg.overlay_color(), Some([255, 215, 0]));
+        assert_eq!(cfg.mqtt_config.payload_encoding, PayloadEncoding::Gzip);
+        assert_eq!(cfg.matrix_config.room_id, vec!["!front:example.org", "!back:example.org"]);
+        assert_eq!(cfg.discord_config.embed_fields.len(), DISCORD_EMBED_FIELDS.len());
+        assert!(cfg.discord_config.forum_mode);
+        assert_eq!(cfg.slack_config.request_timeout_secs, 30);
+        assert!(cfg.slack_acknowledge_enabled());
+        assert!(cfg.discord_acknowledge_enabled());
+    }
+
+    #[test]
+    fn public_recording_link_leaves_out_credentials() {
+        let cfg = load_config(FULL_CONFIG_FIXTURE.to_string()).expect("fixture loads");
+
+        assert_eq!(cfg.recording_link("A1", "Front"),
+                   "http://[2001:db8::1]:81/ui3.htm?rec=A1&cam=Front&m=1&session=0123456789abcdef&user=viewer&pw=viewer-password");
+        assert_eq!(cfg.public_recording_link("A1", "Front"), "http://[2001:db8::1]:81/ui3.htm?rec=A1&cam=Front&m=1");
+    }
+
+    #[test]
+    fn snapshot_links_are_signed_and_fetch_with_credentials() {
+        let cfg = load_config(FULL_CONFIG_FIXTURE.to_string()).expect("fixture loads");
+
+        assert_eq!(cfg.full_jpeg_url("A1"),
+                   "http://[2001:db8::1]:81/alerts/A1?fulljpeg&session=0123456789abcdef&user=viewer&pw=viewer-password");
+        assert_eq!(cfg.snapshot_link("A1"),
+                   Some(format!("http://192.168.1.50:8090/snapshot/A1?sig={}", cfg.snapshot_signature("A1"))));
+        assert_ne!(cfg.snapshot_signature("A1"), cfg.snapshot_signature("A2"));
+    }
+
+    #[test]
+    fn snapshot_link_only_for_ids_the_server_serves() {
+        let cfg = load_config(FULL_CONFIG_FIXTURE.to_string()).expect("fixture loads");
+
+        assert!(cfg.snapshot_link("@123").is_some());
+        for db_id in ["", "A 1", "../A1", "A1?x=1", "A1#"] {
+            assert_eq!(cfg.snapshot_link(db_id), None, "{:?}", db_id);
+        }
+    }
+
+    #[test]
+    fn upload_filename_takes_the_extension_of_the_uploaded_image() {
+        let cfg = BvrChirpConfig {
+            upload_filename_template: "<CAMERA_NAME>.jpg".to_string(),
+            upload_format: UploadFormat::Webp,
+            ..BvrChirpConfig::default()
+        };
+        let bvr_msg = BvrChirpMessage::new(String::new(), "Front Door".to_string(), String::new(),
+                                           "A1".to_string(), String::new(), Vec::new());
+
+        assert_eq!(cfg.upload_filename(&bvr_msg, b"\xFF\xD8\xFF\xE0"), "Front_Door.jpg");
+        assert_eq!(cfg.upload_filename(&bvr_msg, b"RIFF\0\0\0\0WEBPVP8 "), "Front_Door.webp");
+        assert_eq!(cfg.upload_filename(&bvr_msg, b"\x89PNG\r\n\x1a\n"), "Front_Door.png");
+        assert_eq!(cfg.upload_filename(&bvr_msg, &[]), "Front_Door.jpg");
+    }
+
+    #[test]
+    fn invalid_topic_filters_are_reported() {
+        let mut cfg = BvrChirpConfig::default();
+        cfg.mqtt_config.binary_image_topic = "a/#/b".to_string();
+
+        assert!(cfg.validate().contains(
+            &"mqtt_config.binary_image_topic is not a valid MQTT topic filter: 'a/#/b'".to_string()));
+    }
+
+    #[test]
+    fn full_config_fixture_is_valid() {
+        let cfg = load_config(FULL_CONFIG_FIXTURE.to_string()).expect("fixture loads");
+        assert_eq!(cfg.validate(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn sample_config_loads() {
+        let sample = concat!(env!("CARGO_MANIFEST_DIR"), "/bvr_chirp.cfg.sample");
+        assert!(confy::load_path::<BvrChirpConfig>(sample).is_ok());
+    }
+}
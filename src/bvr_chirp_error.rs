@@ -0,0 +1,65 @@
+use reqwest::blocking::Response;
+use thiserror::Error;
+
+/// Errors returned by the messaging clients
+///
+/// The variant describes the kind of failure so callers can decide whether an
+/// operation is worth retrying (ie: a timeout) or not (ie: a bad token).
+#[derive(Error, Debug)]
+pub enum BvrChirpError {
+    /// The service rejected the credentials or the bot lacks permissions
+    #[error("authentication failed: {0}")]
+    Auth(String),
+
+    /// A temporary failure, such as a timeout or the service being unavailable
+    #[error("transient failure: {0}")]
+    Transient(String),
+
+    /// The image or file could not be uploaded
+    #[error("upload failed: {0}")]
+    Upload(String),
+
+    /// The message could not be sent
+    #[error("send failed: {0}")]
+    Send(String),
+
+    /// The configuration or message contents are invalid for the service
+    #[error("invalid configuration: {0}")]
+    Config(String),
+}
+
+impl BvrChirpError {
+    /// Returns true if the operation that produced this error may succeed when tried again
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, BvrChirpError::Transient(_) | BvrChirpError::Upload(_) | BvrChirpError::Send(_))
+    }
+}
+
+impl From<reqwest::Error> for BvrChirpError {
+    fn from(err: reqwest::Error) -> Self {
+        match err.status().map(|status| status.as_u16()) {
+            Some(401) | Some(403) => BvrChirpError::Auth(err.to_string()),
+            Some(429) => BvrChirpError::Transient(err.to_string()),
+            Some(status) if status >= 500 => BvrChirpError::Transient(err.to_string()),
+            _ if err.is_timeout() => BvrChirpError::Transient(err.to_string()),
+            _ => BvrChirpError::Send(err.to_string()),
+        }
+    }
+}
+
+/// Classifies a failed response by its HTTP status, passing successful ones through
+pub fn check_status(response: Response, other: fn(String) -> BvrChirpError) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let error = format!("Request failed with status {}: {}", status, response.text().unwrap_or_default());
+    match status.as_u16() {
+        401 | 403 => Err(BvrChirpError::Auth(error)),
+        429 | 500..=599 => Err(BvrChirpError::Transient(error)),
+        _ => Err(other(error)),
+    }
+}
+
+pub type Result<T> = std::result::Result<T, BvrChirpError>;
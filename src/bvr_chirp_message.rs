@@ -1,11 +1,74 @@
+use std::time::{Duration, Instant};
+use base64::Engine;
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::prelude::BASE64_STANDARD;
+use crossbeam_channel::Sender;
+use serde_json::{json, Value};
+use crate::bvr_chirp_config::Base64Variant;
+
+/// Separators used to split the detections string when none are configured
+pub const DEFAULT_DETECTION_SEPARATORS: &str = ",;";
+
+/// URL-safe base64, with or without the trailing `=` padding
+const BASE64_URL_SAFE: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
 #[derive(Clone)]
 pub struct BvrChirpMessage {
     pub target: String,
     pub camera_name: String,
+    /// Detections as received, used for display
     pub detections: String,
+    /// Individual detections parsed from `detections`, used for filtering and routing
+    pub detection_list: Vec<String>,
     pub db_id: String,
     pub time: String,
+    /// JPEG image of the alert, empty when it couldn't be decoded and the alert is sent as text only
     pub image: Vec<u8>,
+    pub image_url: Option<String>,
+    /// Short audio clip sent with the alert, ie: a doorbell press or glass breaking
+    pub audio: Option<Vec<u8>>,
+    /// Number of objects detected, when the source reports it
+    pub object_count: Option<u64>,
+    /// Number of objects detected per type (ie: person: 2, car: 1), when the source reports it
+    pub object_counts: Vec<(String, u64)>,
+    /// Individual objects with their confidence, when the source provides a breakdown
+    pub detections_detail: Vec<DetectionDetail>,
+    /// Confidence of the detection as a percentage, when the source reports it
+    pub confidence: Option<f64>,
+    /// When the alert was received from MQTT
+    pub received_at: Instant,
+    /// Number of this alert among the camera's alerts today, set when it's passed to the clients
+    pub daily_count: Option<u32>,
+    /// Text of a status message, like a heartbeat, sent as is instead of an alert
+    pub system_text: Option<String>,
+    /// Signalled once the service is done with the alert, with whether it delivered it, when
+    /// services get it one after another
+    pub handled: Option<Sender<bool>>,
+    /// Services of the alert's target that get it one at a time until one delivers it, see `primary_services`
+    pub primary_services: Vec<String>,
+    /// Number of services that report the alert to `dead_letter`, when it isn't passed to every enabled service
+    pub expected_reports: Option<usize>,
+    /// Another primary service gets the alert when this one doesn't deliver it, so this service's
+    /// failure isn't the final outcome of the alert
+    pub fallback_follows: bool,
+    /// Only service the alert goes to, named by its MQTT topic with `topic_routing`
+    pub service: Option<String>,
+    /// Alerts of the same event from other cameras, collapsed into this one by `multi_cam_window_ms`
+    pub related: Vec<BvrChirpMessage>,
+    /// Test alert routed to `debug_target`, kept away from the services without a sandbox
+    pub debug: bool,
+}
+
+/// One detected object from the `detections_detail` breakdown
+#[derive(Clone)]
+pub struct DetectionDetail {
+    pub label: String,
+    /// Confidence as a percentage
+    pub confidence: Option<f64>,
 }
 
 impl BvrChirpMessage {
@@ -17,13 +80,534 @@ impl BvrChirpMessage {
         time: String,
         image: Vec<u8>
     ) -> BvrChirpMessage {
+        let detection_list = parse_detections(&detections, DEFAULT_DETECTION_SEPARATORS);
+
         BvrChirpMessage {
             target,
             camera_name,
             detections,
+            detection_list,
             db_id,
             time,
-            image
+            image,
+            image_url: None,
+            audio: None,
+            object_count: None,
+            object_counts: Vec::new(),
+            detections_detail: Vec::new(),
+            confidence: None,
+            received_at: Instant::now(),
+            daily_count: None,
+            system_text: None,
+            handled: None,
+            primary_services: Vec::new(),
+            expected_reports: None,
+            fallback_follows: false,
+            service: None,
+            related: Vec::new(),
+            debug: false,
+        }
+    }
+
+    /// Creates a status message that clients send as plain text instead of an alert
+    ///
+    /// # Arguments
+    /// * `text` - Text of the message
+    pub fn system(text: String) -> BvrChirpMessage {
+        let mut message = BvrChirpMessage::new(String::new(), String::new(), String::new(),
+                                               String::new(), String::new(), Vec::new());
+        message.system_text = Some(text);
+        message
+    }
+
+    /// Serializes the alert in the MQTT payload format, with the image base64 encoded
+    pub fn to_payload_json(&self) -> Value {
+        let mut payload = json!({
+            "target": self.target,
+            "camera": self.camera_name,
+            "detections": self.detections,
+            "db_id": self.db_id,
+            "time": self.time,
+            "image": BASE64_STANDARD.encode(&self.image),
+        });
+        if let Some(image_url) = &self.image_url {
+            payload["image_url"] = json!(image_url);
+        }
+        if let Some(audio) = &self.audio {
+            payload["audio"] = json!(BASE64_STANDARD.encode(audio));
+        }
+        if let Some(object_count) = self.object_count {
+            payload["object_count"] = json!(object_count);
+        }
+        if !self.object_counts.is_empty() {
+            payload["object_counts"] = self.object_counts.iter()
+                .map(|(label, count)| (label.clone(), json!(count)))
+                .collect::<serde_json::Map<String, Value>>()
+                .into();
+        }
+        if !self.detections_detail.is_empty() {
+            payload["detections_detail"] = self.detections_detail.iter()
+                .map(|detail| json!({ "label": detail.label, "confidence": detail.confidence.map(percent_text) }))
+                .collect();
+        }
+        if let Some(confidence) = self.confidence {
+            payload["confidence"] = json!(percent_text(confidence));
+        }
+        payload
+    }
+
+    /// Metadata of the alert for `attach_metadata_json`, the payload fields without the
+    /// image and audio bytes, along with what bvr_chirp worked out from them
+    ///
+    /// # Returns
+    /// * JSON object with the payload fields, the parsed detections, the daily count and
+    ///   the metadata of the cameras collapsed into the alert
+    pub fn metadata_json(&self) -> Value {
+        let mut metadata = self.to_payload_json();
+        if let Some(fields) = metadata.as_object_mut() {
+            fields.remove("image");
+            fields.remove("audio");
+        }
+        metadata["detection_list"] = json!(self.detection_list);
+        metadata["has_image"] = json!(self.has_image());
+        metadata["has_audio"] = json!(self.audio.is_some());
+        if let Some(daily_count) = self.daily_count {
+            metadata["daily_count"] = json!(daily_count);
+        }
+        if self.debug {
+            metadata["debug"] = json!(true);
+        }
+        if !self.related.is_empty() {
+            metadata["related"] = self.related.iter().map(|related| related.metadata_json()).collect();
+        }
+        metadata
+    }
+
+    /// Parses an alert saved in the MQTT payload format, ie: by `to_payload_json`
+    ///
+    /// # Arguments
+    /// * `payload` - Parsed JSON payload
+    /// * `separators` - Characters that separate individual detections
+    ///
+    /// # Returns
+    /// * `Ok(BvrChirpMessage)` received now
+    /// * `Err` describing the missing or invalid field
+    pub fn from_payload_json(payload: &Value, separators: &str) -> Result<BvrChirpMessage, String> {
+        let field = |name: &str| payload[name].as_str()
+            .map(|value| value.to_owned())
+            .ok_or_else(|| format!("Missing '{}' field in JSON", name));
+
+        let image = decode_image(&field("image")?, Base64Variant::Standard)?;
+
+        let mut message = BvrChirpMessage::new(
+            field("target")?,
+            field("camera")?,
+            field("detections")?,
+            field("db_id")?,
+            field("time")?,
+            image,
+        );
+        message.detection_list = parse_detections(&message.detections, separators);
+        message.image_url = payload["image_url"].as_str().map(|url| url.to_owned());
+        message.audio = match payload["audio"].as_str() {
+            Some(audio) => Some(decode_audio(audio, Base64Variant::Standard)?),
+            None => None,
+        };
+        message.object_count = payload["object_count"].as_u64();
+        message.object_counts = parse_object_counts(&payload["object_counts"]);
+        message.detections_detail = parse_detections_detail(&payload["detections_detail"]);
+        message.confidence = parse_confidence(&payload["confidence"]);
+        Ok(message)
+    }
+
+    /// Confidence of the alert as a percentage, the payload's `confidence` or else the
+    /// highest confidence in the detections breakdown
+    ///
+    /// # Returns
+    /// * `None` if the source didn't report any confidence
+    pub fn best_confidence(&self) -> Option<f64> {
+        self.confidence.or_else(|| self.detections_detail.iter()
+            .filter_map(|detail| detail.confidence)
+            .reduce(f64::max))
+    }
+
+    /// One line per object in the detections breakdown (ie: "person (92%)")
+    pub fn detail_lines(&self) -> Vec<String> {
+        self.detections_detail.iter()
+            .map(|detail| match detail.confidence {
+                Some(confidence) => format!("{} ({:.0}%)", detail.label, confidence),
+                None => detail.label.clone(),
+            })
+            .collect()
+    }
+
+    /// One line per object type with its count (ie: "Person: 2"), empty without counts per type
+    pub fn count_lines(&self) -> Vec<String> {
+        self.object_counts.iter()
+            .map(|(label, count)| format!("{}: {}", capitalize(label), count))
+            .collect()
+    }
+
+    /// Lets the next service in `service_priority` order have the alert, with `ordered_delivery`
+    /// or the target's `primary_services`
+    ///
+    /// # Arguments
+    /// * `delivered` - Whether the service sent the alert
+    pub fn mark_handled(&self, delivered: bool) {
+        if let Some(handled) = &self.handled {
+            let _ = handled.try_send(delivered);
+        }
+    }
+
+    /// Returns true if the alert has an image to attach
+    pub fn has_image(&self) -> bool {
+        !self.image.is_empty()
+    }
+
+    /// Names of every camera that saw the event, this alert's camera first
+    pub fn camera_names(&self) -> Vec<&str> {
+        std::iter::once(self.camera_name.as_str())
+            .chain(self.related.iter().map(|alert| alert.camera_name.as_str()))
+            .collect()
+    }
+
+    /// Alerts of the other cameras that saw the event and have an image to attach
+    pub fn related_images(&self) -> impl Iterator<Item = &BvrChirpMessage> {
+        self.related.iter().filter(|alert| alert.has_image())
+    }
+
+    /// Returns true if the alert was received longer than `max_age_secs` ago
+    ///
+    /// A `max_age_secs` of 0 means alerts never go stale.
+    pub fn is_stale(&self, max_age_secs: u64) -> bool {
+        max_age_secs != 0 && self.received_at.elapsed() > Duration::from_secs(max_age_secs)
+    }
+
+    /// Describes the detections including the object count when known (ie: "2 vehicles")
+    ///
+    /// Counts per object type are listed together (ie: "2 people, 1 car"). A single count
+    /// for several detections applies to each of them (ie: "3 cars, dogs"). Falls back to
+    /// the detections string as received when there's no count.
+    pub fn count_summary(&self) -> String {
+        if !self.object_counts.is_empty() {
+            return self.object_counts.iter()
+                .map(|(label, count)| format!("{} {}", count, pluralize(label, *count)))
+                .collect::<Vec<String>>()
+                .join(", ");
+        }
+
+        match self.object_count {
+            Some(count) if self.detection_list.is_empty() => count.to_string(),
+            Some(count) => {
+                let labels: Vec<String> = self.detection_list.iter().map(|label| pluralize(label, count)).collect();
+                format!("{} {}", count, labels.join(", "))
+            }
+            None => self.detections.clone(),
+        }
+    }
+}
+
+/// Naive English plural of one detection label, good enough for object classes
+///
+/// Only the last word changes, ie: "delivery truck" gives "delivery trucks". Labels
+/// that already end with an s, like "cars", are kept as they are.
+fn pluralize(label: &str, count: u64) -> String {
+    let (start, word) = match label.rsplit_once(' ') {
+        Some((start, word)) => (&label[..start.len() + 1], word),
+        None => ("", label),
+    };
+
+    let plural = if ["person", "persons", "people"].iter().any(|person| word.eq_ignore_ascii_case(person)) {
+        let person = if count == 1 { "person" } else { "people" };
+        if word.starts_with(char::is_uppercase) { capitalize(person) } else { person.to_string() }
+    } else if count == 1 || word.is_empty() || word.ends_with(['s', 'S']) {
+        word.to_string()
+    } else {
+        format!("{}s", word)
+    };
+    format!("{}{}", start, plural)
+}
+
+/// Upper cases the first letter of a detection label, ie: "person" gives "Person"
+pub fn capitalize(label: &str) -> String {
+    let mut chars = label.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Decodes the base64 image of a payload, which may be a `data:` URI
+///
+/// A `data:image/jpeg;base64,...` URI is stripped down to its base64 data. Data URIs
+/// declaring something other than an image are rejected.
+///
+/// # Arguments
+/// * `image` - The `image` field of the payload
+/// * `variant` - Base64 alphabet the image is encoded with
+///
+/// # Returns
+/// * `Ok(Vec<u8>)` with the encoded image
+/// * `Err` describing why the image couldn't be decoded
+pub fn decode_image(image: &str, variant: Base64Variant) -> Result<Vec<u8>, String> {
+    decode_media(image, variant, "image")
+}
+
+/// Decodes the base64 audio clip of a payload, which may be a `data:` URI
+///
+/// # Arguments
+/// * `audio` - Base64 audio, or a data URI declaring an `audio/` MIME type
+/// * `variant` - Base64 alphabet the audio is encoded with
+///
+/// # Returns
+/// * `Ok(Vec<u8>)` with the encoded audio
+/// * `Err` describing why the audio couldn't be decoded
+pub fn decode_audio(audio: &str, variant: Base64Variant) -> Result<Vec<u8>, String> {
+    decode_media(audio, variant, "audio")
+}
+
+/// Decodes base64 media, rejecting a data URI declaring another kind of media than `kind`
+fn decode_media(text: &str, variant: Base64Variant, kind: &str) -> Result<Vec<u8>, String> {
+    let (mime_type, data) = split_data_uri(text);
+    if let Some(mime_type) = mime_type {
+        if !mime_type.to_lowercase().starts_with(&format!("{}/", kind)) {
+            return Err(format!("Data URI declares '{}' instead of an {}", mime_type, kind));
+        }
+    }
+
+    let data = data.trim();
+    let decoded = match variant {
+        Base64Variant::Standard => BASE64_STANDARD.decode(data),
+        Base64Variant::UrlSafe => BASE64_URL_SAFE.decode(data),
+        Base64Variant::Auto => BASE64_STANDARD.decode(data).or_else(|_| BASE64_URL_SAFE.decode(data)),
+    };
+    decoded.map_err(|_| format!("Failed to decode base64 {}", kind))
+}
+
+/// MIME type and file extension of an audio clip, recognized by its first bytes
+///
+/// # Returns
+/// * ie: `("audio/ogg", "ogg")`, MP3 when the format isn't recognized
+pub fn audio_format(audio: &[u8]) -> (&'static str, &'static str) {
+    match audio {
+        [b'O', b'g', b'g', b'S', ..] => ("audio/ogg", "ogg"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => ("audio/wav", "wav"),
+        [b'f', b'L', b'a', b'C', ..] => ("audio/flac", "flac"),
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => ("audio/mp4", "m4a"),
+        _ => ("audio/mpeg", "mp3"),
+    }
+}
+
+/// Splits a `data:<mime type>;base64,<data>` URI into its MIME type and data
+///
+/// # Returns
+/// * The declared MIME type, `None` when the text isn't a data URI or declares none,
+///   and the data after the comma, or the whole text when it isn't a data URI
+fn split_data_uri(text: &str) -> (Option<&str>, &str) {
+    let uri = match text.trim_start().strip_prefix("data:") {
+        Some(uri) => uri,
+        None => return (None, text),
+    };
+
+    match uri.split_once(',') {
+        Some((header, data)) => {
+            let mime_type = header.split(';').next().filter(|mime_type| !mime_type.is_empty());
+            (mime_type, data)
+        }
+        None => (None, text),
+    }
+}
+
+/// Splits a detections string into trimmed, non-empty detections
+///
+/// # Arguments
+/// * `detections` - Detections string as received (ie: "person, car")
+/// * `separators` - Characters that separate individual detections
+///
+/// # Returns
+/// * Vec containing each detection (ie: ["person", "car"])
+pub fn parse_detections(detections: &str, separators: &str) -> Vec<String> {
+    detections
+        .split(|c: char| separators.contains(c))
+        .map(str::trim)
+        .filter(|detection| !detection.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parses the optional object counts per type from a payload, ie: `{"person": 2, "car": 1}`
+///
+/// Counts may be numbers or numeric strings. Types with a count of 0 or an invalid
+/// count are skipped.
+///
+/// # Arguments
+/// * `value` - The `object_counts` value of the payload
+///
+/// # Returns
+/// * Each object type with its count, empty if the payload has no counts per type
+pub fn parse_object_counts(value: &Value) -> Vec<(String, u64)> {
+    let entries = match value.as_object() {
+        Some(entries) => entries,
+        None => return Vec::new(),
+    };
+
+    entries.iter()
+        .filter_map(|(label, count)| {
+            let count = count.as_u64().or_else(|| count.as_str().and_then(|count| count.trim().parse().ok()))?;
+            (count > 0 && !label.trim().is_empty()).then(|| (label.trim().to_string(), count))
+        })
+        .collect()
+}
+
+/// Parses the optional `confidence` of a payload as a percentage
+///
+/// Accepts a number or a numeric string, as a fraction (0.92) or a percentage
+/// (92 or "92%"), see `confidence_percent`.
+///
+/// # Arguments
+/// * `value` - The `confidence` value of the payload
+///
+/// # Returns
+/// * `None` if the payload has no confidence or it isn't a number
+pub fn parse_confidence(value: &Value) -> Option<f64> {
+    confidence_percent(value)
+}
+
+/// Writes a confidence as "92%", so reading it back doesn't take a low percentage for a fraction
+fn percent_text(confidence: f64) -> String {
+    format!("{}%", confidence)
+}
+
+/// Reads a confidence as a percentage
+///
+/// Only a bare decimal number up to 1.0 is a fraction and is scaled by 100. Whole
+/// numbers (1 is 1%) and values with a `%` are already a percentage.
+///
+/// # Arguments
+/// * `value` - A confidence given as a number or a numeric string
+///
+/// # Returns
+/// * `None` if the value isn't a number
+fn confidence_percent(value: &Value) -> Option<f64> {
+    let (confidence, is_fraction) = match value {
+        Value::Number(number) => {
+            let confidence = number.as_f64()?;
+            (confidence, number.is_f64() && confidence <= 1.0)
         }
+        Value::String(text) => {
+            let text = text.trim();
+            match text.strip_suffix('%') {
+                Some(percent) => (percent.trim().parse().ok()?, false),
+                None => {
+                    let confidence: f64 = text.parse().ok()?;
+                    (confidence, text.contains('.') && confidence <= 1.0)
+                }
+            }
+        }
+        _ => return None,
+    };
+    Some(if is_fraction { confidence * 100.0 } else { confidence })
+}
+
+/// Parses the optional `detections_detail` breakdown from a payload
+///
+/// Expects a list of objects with a `label` (or `name`) and an optional `confidence`,
+/// given either as a fraction (0.92) or a percentage (92 or "92%"). Entries without a label
+/// are skipped.
+///
+/// # Arguments
+/// * `value` - The `detections_detail` value of the payload
+///
+/// # Returns
+/// * Vec of detected objects, empty if the payload has no breakdown
+pub fn parse_detections_detail(value: &Value) -> Vec<DetectionDetail> {
+    let entries = match value.as_array() {
+        Some(entries) => entries,
+        None => return Vec::new(),
+    };
+
+    entries.iter()
+        .filter_map(|entry| {
+            let label = entry["label"].as_str().or_else(|| entry["name"].as_str())?;
+            let confidence = confidence_percent(&entry["confidence"]);
+            Some(DetectionDetail { label: label.to_string(), confidence })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(detections: &str) -> BvrChirpMessage {
+        BvrChirpMessage::new(String::new(), "Front".to_string(), detections.to_string(),
+                             "A1".to_string(), String::new(), Vec::new())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn pluralize_handles_people_and_plurals() {
+        assert_eq!(pluralize("car", 2), "cars");
+        assert_eq!(pluralize("car", 1), "car");
+        assert_eq!(pluralize("cars", 3), "cars");
+        assert_eq!(pluralize("person", 2), "people");
+        assert_eq!(pluralize("Person", 2), "People");
+        assert_eq!(pluralize("persons", 1), "person");
+        assert_eq!(pluralize("people", 4), "people");
+        assert_eq!(pluralize("delivery truck", 2), "delivery trucks");
+    }
+
+    #[test]
+    fn count_summary_pluralizes_each_label() {
+        let mut bvr_msg = alert("cars,dog");
+        bvr_msg.object_count = Some(3);
+        assert_eq!(bvr_msg.count_summary(), "3 cars, dogs");
+
+        let mut bvr_msg = alert("persons");
+        bvr_msg.object_count = Some(1);
+        assert_eq!(bvr_msg.count_summary(), "1 person");
+
+        let mut bvr_msg = alert("");
+        bvr_msg.object_count = Some(2);
+        assert_eq!(bvr_msg.count_summary(), "2");
+    }
+
+    #[test]
+    fn count_summary_lists_counts_per_type() {
+        let mut bvr_msg = alert("person,car");
+        bvr_msg.object_counts = vec![("person".to_string(), 2), ("car".to_string(), 1)];
+        assert_eq!(bvr_msg.count_summary(), "2 people, 1 car");
+
+        bvr_msg.object_counts.clear();
+        assert_eq!(bvr_msg.count_summary(), "person,car");
+    }
+
+    #[test]
+    fn confidence_fractions_and_percentages() {
+        assert_eq!(parse_confidence(&json!(0.92)), Some(92.0));
+        assert_eq!(parse_confidence(&json!("0.92")), Some(92.0));
+        assert_eq!(parse_confidence(&json!(92)), Some(92.0));
+        assert_eq!(parse_confidence(&json!("92%")), Some(92.0));
+        assert_eq!(parse_confidence(&json!("1%")), Some(1.0));
+        assert_eq!(parse_confidence(&json!("0.5%")), Some(0.5));
+        assert_eq!(parse_confidence(&json!(1)), Some(1.0));
+        assert_eq!(parse_confidence(&json!("high")), None);
+        assert_eq!(parse_confidence(&Value::Null), None);
+    }
+
+    #[test]
+    fn detail_confidence_uses_the_same_scale() {
+        let details = parse_detections_detail(&json!([
+            { "label": "person", "confidence": 0.92 },
+            { "label": "car", "confidence": "1%" },
+            { "name": "dog" },
+        ]));
+        assert_eq!(details.iter().map(|detail| detail.confidence).collect::<Vec<_>>(), [Some(92.0), Some(1.0), None]);
+    }
+
+    #[test]
+    fn low_confidence_survives_a_payload_round_trip() {
+        let mut bvr_msg = alert("person");
+        bvr_msg.confidence = Some(1.0);
+        assert_eq!(parse_confidence(&bvr_msg.to_payload_json()["confidence"]), Some(1.0));
+    }
+}
@@ -0,0 +1,86 @@
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use crate::escalation;
+
+#[derive(Debug, PartialEq)]
+enum BreakerState {
+    /// Alerts are sent normally
+    Closed,
+    /// Alerts are skipped until the cooldown ends
+    Open { until: Instant },
+    /// The cooldown ended, the next alert tests whether the service recovered
+    HalfOpen,
+}
+
+/// Skips a messaging service for a while after it fails repeatedly
+///
+/// After `threshold` consecutive failures the breaker opens and alerts are skipped
+/// until `cooldown` has passed. The next alert is then let through as a test: a
+/// success closes the breaker again, a failure re-opens it for another cooldown.
+pub struct CircuitBreaker {
+    name: &'static str,
+    threshold: u32,
+    cooldown: Duration,
+    failures: u32,
+    state: BreakerState,
+}
+
+impl CircuitBreaker {
+    /// Creates a closed circuit breaker
+    ///
+    /// # Arguments
+    /// * `name` - Service name used as the log prefix (ie: "SLACK")
+    /// * `threshold` - Consecutive failures before opening, 0 disables the breaker
+    /// * `cooldown_secs` - Seconds to skip the service once open
+    pub fn new(name: &'static str, threshold: u32, cooldown_secs: u64) -> Self {
+        Self {
+            name,
+            threshold,
+            cooldown: Duration::from_secs(cooldown_secs),
+            failures: 0,
+            state: BreakerState::Closed,
+        }
+    }
+
+    /// Returns true if an alert should be attempted
+    pub fn allow(&mut self) -> bool {
+        match self.state {
+            BreakerState::Open { until } if Instant::now() < until => false,
+            BreakerState::Open { .. } => {
+                info!("{}: Circuit breaker half-open, testing service", self.name);
+                self.state = BreakerState::HalfOpen;
+                true
+            }
+            _ => true,
+        }
+    }
+
+    /// Records a successful send, closing the breaker
+    pub fn record_success(&mut self) {
+        if self.state != BreakerState::Closed {
+            info!("{}: Circuit breaker closed, service recovered", self.name);
+        }
+        if self.failures > 0 {
+            escalation::record_recovery(self.name);
+        }
+        self.failures = 0;
+        self.state = BreakerState::Closed;
+    }
+
+    /// Records a failed send, opening the breaker once the threshold is reached
+    ///
+    /// Failures are counted for `failure_escalation` even with the breaker disabled.
+    pub fn record_failure(&mut self) {
+        self.failures = self.failures.saturating_add(1);
+        escalation::record_failure(self.name, self.failures);
+        if self.threshold == 0 {
+            return;
+        }
+
+        if self.state == BreakerState::HalfOpen || self.failures >= self.threshold {
+            warn!("{}: Circuit breaker opened after {} consecutive failures, skipping for {}s",
+                     self.name, self.failures, self.cooldown.as_secs());
+            self.state = BreakerState::Open { until: Instant::now() + self.cooldown };
+        }
+    }
+}
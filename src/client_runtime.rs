@@ -0,0 +1,106 @@
+use std::future::Future;
+use std::thread;
+use std::time::Duration;
+use crossbeam_channel::{Receiver, RecvError, RecvTimeoutError};
+use tokio::runtime::{Builder, Runtime};
+use tokio::task::{self, JoinHandle};
+use log::{error, info};
+
+/// Where the messaging clients run, see `shared_runtime`
+pub enum ClientRuntime {
+    /// Each client on its own thread, with its own runtime
+    Threads(Vec<thread::JoinHandle<()>>),
+    /// Every client as a task of a single multi-threaded runtime
+    Shared {
+        runtime: Runtime,
+        tasks: Vec<JoinHandle<()>>,
+    },
+}
+
+impl ClientRuntime {
+    /// Creates where the clients will run
+    ///
+    /// # Arguments
+    /// * `shared` - Run every client on one runtime instead of a thread and runtime each
+    ///
+    /// # Returns
+    /// * A shared runtime, or separate threads when not shared or the runtime can't be built
+    pub fn new(shared: bool) -> ClientRuntime {
+        if !shared {
+            return ClientRuntime::Threads(Vec::new());
+        }
+
+        // Clients wait for alerts with `recv`, off the worker threads, so the default of one per core is enough
+        match Builder::new_multi_thread().thread_name("bvr-chirp-client").enable_all().build() {
+            Ok(runtime) => {
+                info!("Running the messaging clients on a shared runtime");
+                ClientRuntime::Shared { runtime, tasks: Vec::new() }
+            }
+            Err(err) => {
+                error!("Failed to build the shared runtime, running each client on its own thread: {}", err);
+                ClientRuntime::Threads(Vec::new())
+            }
+        }
+    }
+
+    /// Starts a messaging client
+    ///
+    /// # Arguments
+    /// * `client` - The client's main loop, ie: `run_slack_client`
+    pub fn spawn<F>(&mut self, client: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        match self {
+            ClientRuntime::Threads(threads) => threads.push(thread::spawn(move || {
+                let rt = Runtime::new().unwrap();
+                rt.block_on(client);
+            })),
+            ClientRuntime::Shared { runtime, tasks } => tasks.push(runtime.spawn(client)),
+        }
+    }
+
+    /// Waits for every client to stop, ie: once their message channels are closed
+    pub fn join(self) {
+        match self {
+            ClientRuntime::Threads(threads) => {
+                for client_thread in threads {
+                    let _ = client_thread.join();
+                }
+            }
+            ClientRuntime::Shared { runtime, tasks } => {
+                for task in tasks {
+                    let _ = runtime.block_on(task);
+                }
+            }
+        }
+    }
+}
+
+/// Waits for the next message on a client's channel without holding up a worker thread
+///
+/// The wait happens on the runtime's blocking thread pool, so the other clients and
+/// background tasks like the Discord gateway keep running on the shared runtime.
+///
+/// # Arguments
+/// * `rx` - Receiver channel of the client
+///
+/// # Returns
+/// * `Err` once every sender is gone and the channel is empty
+pub async fn recv<T: Send + 'static>(rx: &Receiver<T>) -> Result<T, RecvError> {
+    let rx = rx.clone();
+    task::spawn_blocking(move || rx.recv()).await.unwrap_or(Err(RecvError))
+}
+
+/// Waits up to `timeout` for the next message on a client's channel, see `recv`
+///
+/// # Arguments
+/// * `rx` - Receiver channel of the client
+/// * `timeout` - Longest wait for a message
+///
+/// # Returns
+/// * `Err(RecvTimeoutError::Timeout)` if no message arrived in time
+pub async fn recv_timeout<T: Send + 'static>(rx: &Receiver<T>, timeout: Duration) -> Result<T, RecvTimeoutError> {
+    let rx = rx.clone();
+    task::spawn_blocking(move || rx.recv_timeout(timeout)).await.unwrap_or(Err(RecvTimeoutError::Disconnected))
+}
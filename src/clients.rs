@@ -1,4 +1,8 @@
 pub mod mqtt_client;
 pub mod matrix_client;
 pub mod discord_client;
-pub mod slack_client;
\ No newline at end of file
+pub mod slack_client;
+pub mod mastodon_client;
+pub mod chat_webhook_client;
+pub mod apprise_client;
+pub mod client_loop;
@@ -0,0 +1,199 @@
+use std::time::Duration;
+use reqwest::blocking::{multipart, Client};
+use crossbeam_channel::Receiver;
+
+use crate::bvr_chirp_config::BvrChirpConfig;
+use crate::bvr_chirp_error::{check_status, BvrChirpError, Result};
+use crate::bvr_chirp_message::BvrChirpMessage;
+use crate::clients::client_loop::{self, BlockingClient};
+use crate::image_processing;
+use log::{error, info};
+
+/// Seconds to wait for a connection to the Apprise server
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+/// Seconds a whole request may take, the server notifies every service before answering
+const REQUEST_TIMEOUT_SECS: u64 = 60;
+
+/// A client for sending alerts through an [Apprise API](https://github.com/caronc/apprise-api) server
+///
+/// The server relays each alert to every service of its saved configuration (`config_key`),
+/// or of the Apprise URLs sent along with it (`urls`).
+struct AppriseClient {
+    client: Client,
+    cfg: BvrChirpConfig,
+}
+
+impl AppriseClient {
+    /// Creates a new AppriseClient for the configured server
+    ///
+    /// # Arguments
+    /// * `cfg` - BvrChirpConfig containing the server URL and the services to notify
+    ///
+    /// # Returns
+    /// * `Ok(AppriseClient)` if the HTTP client could be built
+    /// * `Err` if the HTTP client configuration is invalid
+    fn new(cfg: &BvrChirpConfig) -> Result<Self> {
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS))
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| BvrChirpError::Config(format!("Failed to build HTTP client: {}", e)))?;
+
+        Ok(Self {
+            client,
+            cfg: cfg.clone(),
+        })
+    }
+
+    /// Sends a notification with the `/notify` API
+    ///
+    /// # Arguments
+    /// * `title` - Title of the notification, may be empty
+    /// * `body` - Text of the notification
+    /// * `attachments` - File names, data and MIME types of the files to attach
+    ///
+    /// # Returns
+    /// * `Ok(())` if the server notified the services
+    /// * `Err` if the request fails or no service could be notified
+    fn notify(&self, title: &str, body: &str, attachments: Vec<(String, Vec<u8>, &str)>) -> Result<()> {
+        let config = &self.cfg.apprise_config;
+        let server_url = config.server_url.trim_end_matches('/');
+
+        let mut form = multipart::Form::new()
+            .text("title", title.to_string())
+            .text("body", body.to_string())
+            .text("type", "info")
+            .text("format", "text");
+
+        // A saved configuration is addressed by its key, otherwise the URLs come with the request
+        let url = if config.config_key.is_empty() {
+            form = form.text("urls", config.urls.join(","));
+            format!("{}/notify/", server_url)
+        } else {
+            if !config.tag.is_empty() {
+                form = form.text("tag", config.tag.clone());
+            }
+            format!("{}/notify/{}", server_url, config.config_key)
+        };
+
+        for (filename, data, mime_type) in attachments {
+            form = form.part("attach", multipart::Part::bytes(data)
+                .file_name(filename)
+                .mime_str(mime_type)
+                .map_err(|e| BvrChirpError::Upload(e.to_string()))?);
+        }
+
+        let response = self.client
+            .post(&url)
+            .multipart(form)
+            .send()?;
+
+        check_status(response, BvrChirpError::Send)?;
+        Ok(())
+    }
+
+    /// Alert image as it's attached, with its file name and MIME type
+    fn image_attachment(&self, bvr_msg: &BvrChirpMessage) -> (String, Vec<u8>, &'static str) {
+        let image = self.cfg.upload_image(&bvr_msg.image).into_owned();
+        let mime_type = image_processing::mime_type(&image);
+        (self.cfg.upload_filename(bvr_msg, &image), image, mime_type)
+    }
+}
+
+impl BlockingClient for AppriseClient {
+    const SERVICE: &'static str = "apprise";
+    const LOG_NAME: &'static str = "APPRISE";
+
+    /// Sends a plain notification, used for status messages like heartbeats
+    ///
+    /// # Arguments
+    /// * `text` - Text of the notification
+    fn send_text(&self, text: &str) -> Result<()> {
+        self.notify("", text, Vec::new())
+    }
+
+    /// Processes an alert by sending it with its images attached
+    ///
+    /// # Arguments
+    /// * `bvr_msg` - BvrChirpMessage containing alert details and image
+    ///
+    /// # Returns
+    /// * `Ok(())` if the server notified the services
+    /// * `Err` if the request fails
+    fn process_alert(&self, bvr_msg: &BvrChirpMessage) -> Result<()> {
+        let mut attachments = Vec::new();
+        if bvr_msg.has_image() {
+            attachments.push(self.image_attachment(bvr_msg));
+        }
+        for related in bvr_msg.related_images() {
+            attachments.push(self.image_attachment(related));
+        }
+        // The metadata goes in its own part after the images, for automations archiving the alerts
+        if let Some((filename, json)) = self.cfg.metadata_attachment(bvr_msg) {
+            attachments.push((filename, json, "application/json"));
+        }
+
+        // With image_only the notification carries nothing but the images
+        let (title, body) = if self.cfg.apprise_config.image_only {
+            (String::new(), String::new())
+        } else {
+            build_notification(&self.cfg, bvr_msg)
+        };
+        self.notify(&title, &body, attachments)?;
+
+        info!("APPRISE: Notification sent - {}", chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S.%3f"));
+        Ok(())
+    }
+}
+
+/// Main entry point for running the Apprise client service
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig containing the Apprise server configuration
+/// * `rx` - Receiver channel for BvrChirpMessages
+///
+/// # Returns
+/// * `Ok(())` once the message channel closes
+/// * `Err` if client initialization fails
+pub async fn run_apprise_client(
+    cfg: BvrChirpConfig,
+    rx: Receiver<BvrChirpMessage>
+) -> Result<()> {
+    let apprise = match AppriseClient::new(&cfg) {
+        Ok(apprise) => apprise,
+        Err(err) => {
+            error!("APPRISE: Error creating Apprise client: {}", err);
+            return Err(err);
+        }
+    };
+
+    client_loop::run_blocking_client(cfg, apprise, rx).await;
+    Ok(())
+}
+
+/// Builds the title and body of the notification sent for an alert
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the title emoji and alert template settings
+/// * `bvr_msg` - BvrChirpMessage containing alert details
+///
+/// # Returns
+/// * Title, and a body with the detections, time, today's alert count and the recording link
+pub(crate) fn build_notification(cfg: &BvrChirpConfig, bvr_msg: &BvrChirpMessage) -> (String, String) {
+    let mut body = cfg.alert_description(bvr_msg);
+    if !body.is_empty() {
+        body.push_str("\n\n");
+    }
+    body.push_str(&format!("{}: {}\n{}: {}", cfg.label("detections_label"), bvr_msg.count_summary(),
+                           cfg.label("time_label"), bvr_msg.time));
+    let daily_count = cfg.daily_count_summary(bvr_msg);
+    if !daily_count.is_empty() {
+        body.push_str(&format!("\n{}", daily_count));
+    }
+    body.push_str(&format!("\n\n{}", cfg.alert_link(bvr_msg)));
+    if let Some(live_url) = cfg.live_url(&bvr_msg.camera_name) {
+        body.push_str(&format!("\n{}: {}", cfg.label("live_label"), live_url));
+    }
+
+    (cfg.alert_title(bvr_msg), body)
+}
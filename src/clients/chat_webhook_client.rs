@@ -0,0 +1,230 @@
+use std::time::Duration;
+use reqwest::blocking::Client;
+use serde_json::{json, Value};
+use crossbeam_channel::Receiver;
+
+use crate::bvr_chirp_config::{BvrChirpConfig, ChatPlatform};
+use crate::bvr_chirp_error::{check_status, BvrChirpError, Result};
+use crate::bvr_chirp_message::BvrChirpMessage;
+use crate::clients::client_loop::{self, BlockingClient};
+use log::{error, info};
+
+/// Seconds to wait for a connection to the chat server
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+/// Seconds a whole webhook request may take
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+
+/// A client for posting alerts to Synology Chat, Rocket.Chat or Mattermost through an incoming webhook
+///
+/// Incoming webhooks can't take uploads, so images are referenced by URL: the NVR's
+/// `image_url` when the payload has one, otherwise the frame served by the snapshot server.
+struct ChatWebhookClient {
+    client: Client,
+    cfg: BvrChirpConfig,
+}
+
+impl ChatWebhookClient {
+    /// Creates a new ChatWebhookClient for the configured webhook
+    ///
+    /// # Arguments
+    /// * `cfg` - BvrChirpConfig containing the webhook URL and platform
+    ///
+    /// # Returns
+    /// * `Ok(ChatWebhookClient)` if the HTTP client could be built
+    /// * `Err` if the HTTP client configuration is invalid
+    fn new(cfg: &BvrChirpConfig) -> Result<Self> {
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS))
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| BvrChirpError::Config(format!("Failed to build HTTP client: {}", e)))?;
+
+        Ok(Self {
+            client,
+            cfg: cfg.clone(),
+        })
+    }
+
+    /// Posts a payload to the webhook
+    ///
+    /// Synology Chat expects the JSON as a `payload` form field, the others take it as the body.
+    ///
+    /// # Arguments
+    /// * `payload` - Message built for the configured platform
+    ///
+    /// # Returns
+    /// * `Ok(())` if the webhook accepted the message
+    /// * `Err` if the request fails
+    fn post(&self, payload: &Value) -> Result<()> {
+        let request = self.client.post(&self.cfg.chat_webhook_config.webhook_url);
+        let request = match self.cfg.chat_webhook_config.platform {
+            ChatPlatform::Synology => request.form(&[("payload", payload.to_string())]),
+            ChatPlatform::RocketChat | ChatPlatform::Mattermost => request.json(payload),
+        };
+
+        check_status(request.send()?, BvrChirpError::Send)?;
+        Ok(())
+    }
+}
+
+impl BlockingClient for ChatWebhookClient {
+    const SERVICE: &'static str = "chat_webhook";
+    const LOG_NAME: &'static str = "CHAT WEBHOOK";
+
+    /// Posts a plain text message, used for status messages like heartbeats
+    ///
+    /// # Arguments
+    /// * `text` - Text of the message
+    fn send_text(&self, text: &str) -> Result<()> {
+        let config = &self.cfg.chat_webhook_config;
+        let payload = match config.platform {
+            ChatPlatform::Synology => json!({ "text": text }),
+            ChatPlatform::RocketChat => json!({ "alias": config.bot_name, "text": text }),
+            ChatPlatform::Mattermost => json!({ "username": config.bot_name, "text": text }),
+        };
+        self.post(&payload)
+    }
+
+    /// Processes an alert by posting it to the webhook
+    ///
+    /// # Arguments
+    /// * `bvr_msg` - BvrChirpMessage containing alert details
+    ///
+    /// # Returns
+    /// * `Ok(())` if the webhook accepted the alert
+    /// * `Err` if the request fails
+    fn process_alert(&self, bvr_msg: &BvrChirpMessage) -> Result<()> {
+        self.post(&build_payload(&self.cfg, bvr_msg))?;
+
+        info!("CHAT WEBHOOK: Message sent - {}", chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S.%3f"));
+        Ok(())
+    }
+
+    /// Incoming webhooks reference images by URL, see `image_url`
+    fn has_image(&self, bvr_msg: &BvrChirpMessage) -> bool {
+        image_url(&self.cfg, bvr_msg).is_some()
+    }
+}
+
+/// Main entry point for running the chat webhook client service
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig containing the chat webhook configuration
+/// * `rx` - Receiver channel for BvrChirpMessages
+///
+/// # Returns
+/// * `Ok(())` once the message channel closes
+/// * `Err` if client initialization fails
+pub async fn run_chat_webhook_client(
+    cfg: BvrChirpConfig,
+    rx: Receiver<BvrChirpMessage>
+) -> Result<()> {
+    let chat_webhook = match ChatWebhookClient::new(&cfg) {
+        Ok(chat_webhook) => chat_webhook,
+        Err(err) => {
+            error!("CHAT WEBHOOK: Error creating chat webhook client: {}", err);
+            return Err(err);
+        }
+    };
+
+    client_loop::run_blocking_client(cfg, chat_webhook, rx).await;
+    Ok(())
+}
+
+/// URL the chat server can load an alert's image from
+///
+/// The NVR's `image_url` when the payload has one, otherwise the frame served by the
+/// snapshot server. Alerts without a decoded image have nothing on the snapshot server.
+///
+/// # Returns
+/// * `None` if there's no image to reference
+fn image_url(cfg: &BvrChirpConfig, bvr_msg: &BvrChirpMessage) -> Option<String> {
+    cfg.image_link(bvr_msg).or_else(|| {
+        if !bvr_msg.has_image() {
+            return None;
+        }
+        cfg.snapshot_link(&bvr_msg.db_id)
+    })
+}
+
+/// Builds the webhook payload for an alert in the configured platform's format
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the platform, bot name and alert template settings
+/// * `bvr_msg` - BvrChirpMessage containing alert details
+///
+/// # Returns
+/// * JSON payload with the title, description, detections, time and image URL
+pub(crate) fn build_payload(cfg: &BvrChirpConfig, bvr_msg: &BvrChirpMessage) -> Value {
+    let config = &cfg.chat_webhook_config;
+    let image_url = image_url(cfg, bvr_msg);
+    let title = cfg.alert_title(bvr_msg);
+    let link = cfg.alert_link(bvr_msg);
+
+    let mut details = format!("{}: {}\n{}: {}", cfg.label("detections_label"), bvr_msg.count_summary(),
+                              cfg.label("time_label"), bvr_msg.time);
+    let daily_count = cfg.daily_count_summary(bvr_msg);
+    if !daily_count.is_empty() {
+        details.push_str(&format!("\n{}", daily_count));
+    }
+    let description = cfg.alert_description(bvr_msg);
+    let mut text = if description.is_empty() { details } else { format!("{}\n\n{}", description, details) };
+
+    // Link to the camera's live view, Synology Chat has its own link syntax
+    if let Some(live_url) = cfg.live_url(&bvr_msg.camera_name) {
+        let live_link = match config.platform {
+            ChatPlatform::Synology => format!("<{}|{}>", live_url, cfg.label("live_label")),
+            ChatPlatform::RocketChat | ChatPlatform::Mattermost => format!("[{}]({})", cfg.label("live_label"), live_url),
+        };
+        text.push_str(&format!("\n{}", live_link));
+    }
+
+    match config.platform {
+        // Synology Chat has no attachments, the image is shown from `file_url`
+        ChatPlatform::Synology => {
+            let mut payload = if config.image_only {
+                json!({ "text": "" })
+            } else {
+                json!({ "text": format!("*{}*\n{}\n<{}|{}>", title, text, link, cfg.label("details_label")) })
+            };
+            if let Some(image_url) = image_url {
+                payload["file_url"] = json!(image_url);
+            }
+            payload
+        }
+        ChatPlatform::RocketChat => {
+            let attachment = if config.image_only {
+                json!({ "image_url": image_url })
+            } else {
+                json!({
+                    "title": title,
+                    "title_link": link,
+                    "text": text,
+                    "image_url": image_url,
+                })
+            };
+            json!({
+                "alias": config.bot_name,
+                "attachments": [attachment],
+            })
+        }
+        ChatPlatform::Mattermost => {
+            let attachment = if config.image_only {
+                json!({ "fallback": title, "image_url": image_url })
+            } else {
+                json!({
+                    "fallback": title,
+                    "title": title,
+                    "title_link": link,
+                    "text": text,
+                    "image_url": image_url,
+                    "footer": cfg.footer_text(&config.bot_name),
+                })
+            };
+            json!({
+                "username": config.bot_name,
+                "attachments": [attachment],
+            })
+        }
+    }
+}
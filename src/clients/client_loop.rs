@@ -0,0 +1,335 @@
+use std::future::Future;
+use std::thread;
+use std::time::Duration;
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use tokio::{task, time};
+
+use crate::backoff::Backoff;
+use crate::bvr_chirp_config::{BvrChirpConfig, ImageErrorAction};
+use crate::bvr_chirp_error::{BvrChirpError, Result};
+use crate::bvr_chirp_message::BvrChirpMessage;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::{client_runtime, dead_letter, recent_alerts};
+use crate::delivery_stats::{self, Outcome};
+use log::{error, info, warn};
+
+/// A messaging client whose requests block the thread, run by `run_blocking_client`
+pub trait BlockingClient {
+    /// Name of the service in the config, ie: "chat_webhook"
+    const SERVICE: &'static str;
+
+    /// Name of the service in logs, ie: "CHAT WEBHOOK"
+    const LOG_NAME: &'static str;
+
+    /// Sends a status message like a heartbeat as is
+    fn send_text(&self, text: &str) -> Result<()>;
+
+    /// Sends an alert
+    fn process_alert(&self, bvr_msg: &BvrChirpMessage) -> Result<()>;
+
+    /// Returns true if the client has an image to post for the alert, see `image_only`
+    fn has_image(&self, bvr_msg: &BvrChirpMessage) -> bool {
+        bvr_msg.has_image()
+    }
+}
+
+/// A messaging client with async requests, run by `run_async_client`
+pub trait AsyncClient {
+    /// Name of the service in the config, ie: "discord"
+    const SERVICE: &'static str;
+
+    /// Name of the service in logs, ie: "DISCORD"
+    const LOG_NAME: &'static str;
+
+    /// Longest wait for a message before `on_idle` runs, `None` to only run it after a message
+    const IDLE_INTERVAL: Option<Duration> = None;
+
+    /// Sends a status message like a heartbeat as is
+    fn send_text(&self, text: &str) -> impl Future<Output = Result<()>> + Send;
+
+    /// Sends an alert
+    fn process_alert(&self, bvr_msg: BvrChirpMessage) -> impl Future<Output = Result<()>> + Send;
+
+    /// Returns true if the client has an image to post for the alert, see `image_only`
+    fn has_image(&self, bvr_msg: &BvrChirpMessage) -> bool {
+        bvr_msg.has_image()
+    }
+
+    /// Work to do between messages, runs after every wait for a message
+    fn on_idle(&self) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+}
+
+/// Checks every messaging client makes before sending an alert
+///
+/// Leaves out test alerts for services without a `debug_target` destination, stale
+/// alerts, alerts with a too small image when `small_image_action` drops them and
+/// alerts without an image for services set to `image_only`. Each is reported to
+/// `dead_letter`. A too small image that isn't dropped is removed from the alert.
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the service's settings
+/// * `service` - Name of the service, ie: "chat_webhook"
+/// * `bvr_msg` - The alert to check
+/// * `has_image` - Whether the service has an image to post for the alert
+///
+/// # Returns
+/// * `true` if the alert should be sent
+pub fn accept_alert(
+    cfg: &BvrChirpConfig,
+    service: &str,
+    bvr_msg: &mut BvrChirpMessage,
+    has_image: impl Fn(&BvrChirpMessage) -> bool,
+) -> bool {
+    let stats_name = service.to_uppercase();
+    let prefix = stats_name.replace('_', " ");
+
+    // Test alerts only go to services with a sandbox for them in `debug_target`
+    if bvr_msg.debug && !cfg.has_debug_destination(service) {
+        dead_letter::report(cfg, bvr_msg, true);
+        return false;
+    }
+
+    // Alerts queued up while the service was unreachable are no longer actionable
+    if bvr_msg.is_stale(cfg.max_alert_age_secs) {
+        warn!("{}: Skipping stale alert from {} camera received {}s ago",
+                 prefix, bvr_msg.camera_name, bvr_msg.received_at.elapsed().as_secs());
+        delivery_stats::record(&stats_name, Outcome::Dropped);
+        dead_letter::report(cfg, bvr_msg, false);
+        return false;
+    }
+
+    // Tiny images, like icons sent on some triggers, aren't worth sending
+    if cfg.is_image_too_small(service, &bvr_msg.image) {
+        if cfg.small_image_action == ImageErrorAction::Drop {
+            info!("{}: Skipping alert from {} camera, the image is below the minimum size", prefix, bvr_msg.camera_name);
+            delivery_stats::record(&stats_name, Outcome::Dropped);
+            dead_letter::report(cfg, bvr_msg, true);
+            return false;
+        }
+        info!("{}: Image from {} camera is below the minimum size, sending the alert without it", prefix, bvr_msg.camera_name);
+        bvr_msg.image = Vec::new();
+        bvr_msg.image_url = None;
+    }
+
+    // An image-only feed has nothing to post for an alert without an image
+    if cfg.image_only(service) && !has_image(bvr_msg) {
+        info!("{}: Skipping alert from {} camera, it has no image to post with image_only", prefix, bvr_msg.camera_name);
+        delivery_stats::record(&stats_name, Outcome::Dropped);
+        dead_letter::report(cfg, bvr_msg, true);
+        return false;
+    }
+
+    true
+}
+
+/// Sends an alert, trying again after a temporary failure
+///
+/// Up to `alert_retries` retries, waiting between them as set in `[backoff]`. The clients
+/// only fail an alert that no destination got, so a retry doesn't post it twice.
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the `[backoff]` settings
+/// * `prefix` - Name of the service in logs, ie: "DISCORD"
+/// * `send` - Sends the alert once
+///
+/// # Returns
+/// * The result of the last attempt
+pub async fn send_with_retries<F, Fut>(cfg: &BvrChirpConfig, prefix: &str, mut send: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut backoff = Backoff::new(&cfg.backoff);
+    let mut retries = 0;
+    loop {
+        match send().await {
+            Err(err) => match retry_delay(cfg, prefix, &mut backoff, &mut retries, &err) {
+                Some(delay) => time::sleep(delay).await,
+                None => return Err(err),
+            },
+            Ok(()) => return Ok(()),
+        }
+    }
+}
+
+/// Sends an alert from a blocking client, see `send_with_retries`
+fn send_blocking_with_retries(cfg: &BvrChirpConfig, prefix: &str, send: impl Fn() -> Result<()>) -> Result<()> {
+    let mut backoff = Backoff::new(&cfg.backoff);
+    let mut retries = 0;
+    loop {
+        match send() {
+            Err(err) => match retry_delay(cfg, prefix, &mut backoff, &mut retries, &err) {
+                Some(delay) => thread::sleep(delay),
+                None => return Err(err),
+            },
+            Ok(()) => return Ok(()),
+        }
+    }
+}
+
+/// How long to wait before trying a failed alert again, `None` once it shouldn't be retried
+fn retry_delay(cfg: &BvrChirpConfig, prefix: &str, backoff: &mut Backoff, retries: &mut u32, err: &BvrChirpError) -> Option<Duration> {
+    if !err.is_retryable() || *retries >= cfg.backoff.alert_retries {
+        return None;
+    }
+
+    *retries += 1;
+    let delay = backoff.next_delay();
+    warn!("{}: Failed to send alert, retrying in {}ms ({}/{}): {}",
+          prefix, delay.as_millis(), retries, cfg.backoff.alert_retries, err);
+    Some(delay)
+}
+
+/// Runs a blocking messaging client until its message channel closes
+///
+/// The whole loop runs on the runtime's blocking thread pool, so waiting for alerts
+/// and for the service's responses doesn't hold up the other clients.
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the service's settings
+/// * `client` - The client that sends the messages
+/// * `rx` - Receiver channel for BvrChirpMessages
+pub async fn run_blocking_client<C>(cfg: BvrChirpConfig, client: C, rx: Receiver<BvrChirpMessage>)
+where
+    C: BlockingClient + Send + 'static,
+{
+    if let Err(err) = task::spawn_blocking(move || blocking_client_loop(&cfg, &client, &rx)).await {
+        // A panic stops the client the same as it would on the client's own task
+        if let Ok(panic) = err.try_into_panic() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+/// Sends every message received on the channel, see `run_blocking_client`
+fn blocking_client_loop<C: BlockingClient>(cfg: &BvrChirpConfig, client: &C, rx: &Receiver<BvrChirpMessage>) {
+    let stats_name = C::SERVICE.to_uppercase();
+    let prefix = C::LOG_NAME;
+    let mut breaker = CircuitBreaker::new(prefix, cfg.breaker_threshold, cfg.breaker_cooldown_secs);
+
+    info!("{}: Client ready", prefix);
+
+    loop {
+        let mut bvr_msg = match rx.recv() {
+            Ok(msg) => msg,
+            Err(_) => return channel_closed(prefix, &stats_name),
+        };
+
+        if let Some(text) = &bvr_msg.system_text {
+            if let Err(e) = client.send_text(text) {
+                error!("{}: Failed to send status message: {}", prefix, e);
+            }
+            continue;
+        }
+
+        if !ready_to_send(cfg, C::SERVICE, prefix, &mut breaker, &mut bvr_msg, |bvr_msg| client.has_image(bvr_msg)) {
+            continue;
+        }
+
+        let result = send_blocking_with_retries(cfg, prefix, || client.process_alert(&bvr_msg));
+        record_result(cfg, C::SERVICE, prefix, &mut breaker, &bvr_msg, result);
+    }
+}
+
+/// Runs an async messaging client until its message channel closes
+///
+/// Does the same bookkeeping as `run_blocking_client` around each alert: the checks of
+/// `accept_alert`, the circuit breaker, retries, delivery stats, recent alerts and dead letters.
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the service's settings
+/// * `client` - The client that sends the messages
+/// * `rx` - Receiver channel for BvrChirpMessages
+pub async fn run_async_client<C>(cfg: &BvrChirpConfig, client: &C, rx: &Receiver<BvrChirpMessage>)
+where
+    C: AsyncClient + Sync,
+{
+    let stats_name = C::SERVICE.to_uppercase();
+    let prefix = C::LOG_NAME;
+    let mut breaker = CircuitBreaker::new(prefix, cfg.breaker_threshold, cfg.breaker_cooldown_secs);
+
+    info!("{}: Client ready", prefix);
+
+    loop {
+        let received = match C::IDLE_INTERVAL {
+            Some(interval) => client_runtime::recv_timeout(rx, interval).await,
+            None => client_runtime::recv(rx).await.map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        client.on_idle().await;
+
+        let mut bvr_msg = match received {
+            Ok(msg) => msg,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return channel_closed(prefix, &stats_name),
+        };
+
+        if let Some(text) = &bvr_msg.system_text {
+            if let Err(e) = client.send_text(text).await {
+                error!("{}: Failed to send status message: {}", prefix, e);
+            }
+            continue;
+        }
+
+        if !ready_to_send(cfg, C::SERVICE, prefix, &mut breaker, &mut bvr_msg, |bvr_msg| client.has_image(bvr_msg)) {
+            continue;
+        }
+
+        let result = send_with_retries(cfg, prefix, || client.process_alert(bvr_msg.to_owned())).await;
+        record_result(cfg, C::SERVICE, prefix, &mut breaker, &bvr_msg, result);
+    }
+}
+
+/// Logs that every sender is gone and there will be no more messages
+fn channel_closed(prefix: &str, stats_name: &str) {
+    info!("{}: Message channel closed, stopping. {}", prefix, delivery_stats::line(stats_name));
+}
+
+/// Checks an alert with `accept_alert` and the circuit breaker before it's sent
+///
+/// Status messages like heartbeats aren't alerts, they're sent as is without these checks.
+///
+/// # Returns
+/// * `true` if the alert should be sent
+fn ready_to_send(
+    cfg: &BvrChirpConfig,
+    service: &str,
+    prefix: &str,
+    breaker: &mut CircuitBreaker,
+    bvr_msg: &mut BvrChirpMessage,
+    has_image: impl Fn(&BvrChirpMessage) -> bool,
+) -> bool {
+    if !accept_alert(cfg, service, bvr_msg, has_image) {
+        return false;
+    }
+
+    if !breaker.allow() {
+        info!("{}: Circuit breaker open, skipping message", prefix);
+        delivery_stats::record(&service.to_uppercase(), Outcome::Dropped);
+        dead_letter::report(cfg, bvr_msg, false);
+        return false;
+    }
+
+    true
+}
+
+/// Records how sending an alert went with the circuit breaker, delivery stats, recent alerts and dead letters
+fn record_result(cfg: &BvrChirpConfig, service: &str, prefix: &str, breaker: &mut CircuitBreaker, bvr_msg: &BvrChirpMessage, result: Result<()>) {
+    let stats_name = service.to_uppercase();
+    match result {
+        Ok(_) => {
+            breaker.record_success();
+            delivery_stats::record(&stats_name, Outcome::Sent);
+            recent_alerts::record(bvr_msg);
+            dead_letter::report(cfg, bvr_msg, true);
+        }
+        Err(e) => {
+            error!("{}: Error processing message: {}", prefix, e);
+            breaker.record_failure();
+            delivery_stats::record(&stats_name, Outcome::Failed);
+            dead_letter::report(cfg, bvr_msg, false);
+        }
+    }
+}
@@ -1,107 +1,695 @@
+use std::collections::{BTreeSet, HashMap};
 use std::process::exit;
-use serenity::model::id::ChannelId;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serenity::async_trait;
+use serenity::model::id::{ChannelId, MessageId};
 use serenity::prelude::*;
-use serenity::all::{Colour, CreateEmbed, Timestamp};
-use serenity::builder::{CreateAttachment, CreateMessage};
-use anyhow::{anyhow, Result};
+use serenity::all::{ButtonStyle, Colour, Command, CommandInteraction, CommandOptionType, CreateActionRow, CreateButton,
+                    CreateEmbed, Interaction, Ready, Timestamp, Webhook};
+use serenity::builder::{CreateAttachment, CreateCommand, CreateCommandOption, CreateEmbedFooter, CreateForumPost,
+                        CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage, EditMessage,
+                        ExecuteWebhook};
+use serenity::http::{Http, HttpError};
 use crossbeam_channel::Receiver;
-use crate::bvr_chirp_config::DiscordConfig;
-use crate::bvr_chirp_message::BvrChirpMessage;
+use crate::bvr_chirp_config::{BvrChirpConfig, DiscordMode};
+use crate::bvr_chirp_error::{BvrChirpError, Result};
+use crate::bvr_chirp_message::{audio_format, capitalize, BvrChirpMessage};
+use crate::clients::client_loop::{self, AsyncClient};
+use crate::interaction_server::DISCORD_ACK_PREFIX;
+use crate::message_templates::truncate;
+use crate::recent_alerts::{self, DEFAULT_RECENT_COUNT, RECENT_ALERTS_CAPACITY};
+use log::{error, info, warn};
+
+/// Discord limits thread (forum post) names to 100 characters
+const FORUM_POST_NAME_LIMIT: usize = 100;
+/// Discord limits button custom IDs to 100 characters
+const CUSTOM_ID_LIMIT: usize = 100;
+/// Discord limits embed field values to 1024 characters
+const EMBED_FIELD_VALUE_LIMIT: usize = 1024;
+/// Discord limits embed titles to 256 characters
+const EMBED_TITLE_LIMIT: usize = 256;
+/// Discord limits embed descriptions to 4096 characters
+const EMBED_DESCRIPTION_LIMIT: usize = 4096;
+/// Discord limits message content to 2000 characters
+const MESSAGE_CONTENT_LIMIT: usize = 2000;
+
+/// How long to wait for an alert before checking for ended events to unpin
+const EVENT_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Name of the slash command listing recently sent alerts
+pub const RECENT_COMMAND: &str = "recent";
+
+/// The latest alert sent to a channel, for `pin_active_events`
+struct ActiveEvent {
+    last_alert: Instant,
+    /// The alert pinned while the event is ongoing
+    pinned: Option<MessageId>,
+}
 
 struct DiscordClient {
-    client: Client,
-    alert_endpoint: String,
+    http: Arc<Http>,
+    /// Webhook alerts are posted through instead of the bot, when `webhook_url` is set
+    webhook: Option<Webhook>,
+    /// Events by channel, only tracked with `pin_active_events`
+    events: Mutex<HashMap<ChannelId, ActiveEvent>>,
+    cfg: BvrChirpConfig,
 }
 
 impl DiscordClient {
-    async fn new(token: String, alert_endpoint: String) -> Result<Self> {
-        let client = Client::builder(
-            token,
+    async fn new(cfg: &BvrChirpConfig) -> Result<Self> {
+        // A webhook is authenticated by its URL, so there's no bot to log in unless commands need it
+        if !cfg.discord_config.webhook_url.is_empty() && !cfg.discord_config.enable_commands {
+            let http = Arc::new(Http::new(""));
+            let webhook = fetch_webhook(&http, &cfg.discord_config.webhook_url).await?;
+            return Ok(Self {
+                http,
+                webhook: Some(webhook),
+                events: Mutex::new(HashMap::new()),
+                cfg: cfg.clone(),
+            });
+        }
+
+        // Sending only needs the REST API, without the gateway client and its cache
+        if cfg.discord_mode == DiscordMode::Rest {
+            let http = Arc::new(Http::new(&cfg.discord_config.token));
+            let user = http.get_current_user()
+                .await
+                .map_err(|e| BvrChirpError::Auth(format!("Failed to verify Discord token: {}", e)))?;
+            info!("DISCORD: Logged in as {} (REST only)", user.name);
+            if cfg.discord_config.enable_commands {
+                warn!("DISCORD: Slash commands need discord_mode \"gateway\", /{} won't be registered", RECENT_COMMAND);
+            }
+
+            let webhook = match cfg.discord_config.webhook_url.as_str() {
+                "" => None,
+                webhook_url => Some(fetch_webhook(&http, webhook_url).await?),
+            };
+            return Ok(Self {
+                http,
+                webhook,
+                events: Mutex::new(HashMap::new()),
+                cfg: cfg.clone(),
+            });
+        }
+
+        let mut builder = Client::builder(
+            cfg.discord_config.token.as_str(),
             GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT,
-        )
+        );
+        if cfg.discord_config.enable_commands {
+            builder = builder.event_handler(CommandHandler { cfg: cfg.clone() });
+        }
+
+        let mut client = builder
+            .await
+            .map_err(|e| BvrChirpError::Auth(format!("Failed to create Discord client: {}", e)))?;
+        let http = client.http.clone();
+
+        // Building the client doesn't contact Discord, so check the token works before reporting ready
+        let user = http.get_current_user()
             .await
-            .map_err(|e| anyhow!("Failed to create Discord client: {}", e))?;
+            .map_err(|e| BvrChirpError::Auth(format!("Failed to verify Discord token: {}", e)))?;
+        info!("DISCORD: Logged in as {}", user.name);
+
+        // Slash commands arrive over the gateway, which only needs to run when they're enabled
+        if cfg.discord_config.enable_commands {
+            tokio::spawn(async move {
+                if let Err(e) = client.start().await {
+                    error!("DISCORD: Gateway connection stopped: {}", e);
+                }
+            });
+        }
+
+        let webhook = match cfg.discord_config.webhook_url.as_str() {
+            "" => None,
+            webhook_url => Some(fetch_webhook(&http, webhook_url).await?),
+        };
 
         Ok(Self {
-            client,
-            alert_endpoint,
+            http,
+            webhook,
+            events: Mutex::new(HashMap::new()),
+            cfg: cfg.clone(),
         })
     }
 
-    async fn send_message(&self, channel_id: u64, bvr_msg: &BvrChirpMessage) -> Result<()> {
-        let channel = ChannelId::try_from(channel_id)
-            .map_err(|e| anyhow!("Failed to convert channel ID: {}", e))?;
+    /// Posts an alert through the webhook, with the avatar of its camera
+    ///
+    /// Webhooks post to their own channel, so the alert's target isn't used. Buttons
+    /// are left out as Discord only shows them on webhooks owned by an application.
+    ///
+    /// # Arguments
+    /// * `webhook` - Webhook to post through
+    /// * `bvr_msg` - BvrChirpMessage containing alert details and image
+    ///
+    /// # Returns
+    /// * `Ok(())` if the message was posted
+    /// * `Err` if executing the webhook fails
+    async fn send_webhook(&self, webhook: &Webhook, bvr_msg: &BvrChirpMessage) -> Result<()> {
+        let mut embed = self.alert_embed(bvr_msg);
+        let mut execute = ExecuteWebhook::new().username(self.cfg.discord_config.bot_name.clone());
+        if let Some(avatar_url) = self.cfg.discord_avatar_for(&bvr_msg.camera_name) {
+            execute = execute.avatar_url(avatar_url);
+        }
 
-        // Create the embed message
-        let title = format!("Detection on {} camera", bvr_msg.camera_name);
-        let url = format!(
-            "{}/ui3.htm?rec={}&cam={}&m=1",
-            self.alert_endpoint, bvr_msg.db_id, bvr_msg.camera_name
-        );
+        match self.cfg.image_link(bvr_msg) {
+            Some(image_url) if self.cfg.discord_config.use_image_url => {
+                embed = Some(embed.unwrap_or_default().image(image_url));
+            }
+            _ if bvr_msg.has_image() => {
+                execute = execute.add_file(image_attachment(&self.cfg, bvr_msg, self.cfg.upload_image(&bvr_msg.image).into_owned()));
+            }
+            _ => {}
+        }
+        for attachment in self.related_attachments(bvr_msg).into_iter()
+            .chain(self.audio_attachment(bvr_msg))
+            .chain(self.metadata_attachment(bvr_msg)) {
+            execute = execute.add_file(attachment);
+        }
+
+        if let Some(embed) = embed {
+            execute = execute.embed(embed);
+        }
 
-        let embed = CreateEmbed::new()
-            .title(title)
-            .url(url)
-            .colour(Colour::BLITZ_BLUE)
-            .fields(vec![
-                ("**Detections**", &bvr_msg.detections, false),
-                ("**Time**", &bvr_msg.time, false),
-            ])
-            .timestamp(Timestamp::now());
-
-        // Attach the image to the message
-        let message = CreateMessage::new()
-            .embed(embed)
-            .add_file(CreateAttachment::bytes(
-                bvr_msg.image.clone(),
-                format!("{}.jpg", bvr_msg.camera_name),
-            ));
-
-        channel.send_message(self.client.http.as_ref(), message)
+        webhook.execute(self.http.as_ref(), true, execute)
             .await
-            .map_err(|e| anyhow!("Failed to send message: {}", e))?;
+            .map_err(map_send_error)?;
 
-        println!("DISCORD: Message sent - {}", chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S.%3f"));
+        info!("DISCORD: Message sent through webhook - {}", chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S.%3f"));
         Ok(())
     }
 
-    async fn process_alert(&self, bvr_msg: BvrChirpMessage) -> Result<()> {
-        // Parse the channel ID from the target
-        let channel_id = bvr_msg.target.parse::<u64>()
-            .map_err(|_| anyhow!("Invalid channel ID: {}", bvr_msg.target))?;
+    /// Sends an alert to a channel, or as a new post in a forum channel
+    ///
+    /// With `fast_preview` the message is sent with a small version of the image first
+    /// and edited to show the full image once it's been prepared and uploaded. An alert
+    /// collapsed from several cameras has the image of each camera attached, and an
+    /// alert's audio clip and, with `attach_metadata_json`, its metadata are attached
+    /// after the images.
+    ///
+    /// # Arguments
+    /// * `channel` - Channel to send to
+    /// * `bvr_msg` - BvrChirpMessage containing alert details and image
+    ///
+    /// # Returns
+    /// * `Ok(())` if the message was sent, even if replacing the preview failed
+    /// * `Err` if the message send fails
+    async fn send_message(&self, channel: ChannelId, bvr_msg: &BvrChirpMessage) -> Result<()> {
+        let mut embed = self.alert_embed(bvr_msg);
+
+        let mut message = CreateMessage::new();
+        let mut preview_sent = false;
+
+        // Reference the snapshot hosted by the NVR when available, otherwise attach the image bytes if there are any
+        match self.cfg.image_link(bvr_msg) {
+            Some(image_url) if self.cfg.discord_config.use_image_url => {
+                embed = Some(embed.unwrap_or_default().image(image_url));
+            }
+            _ if bvr_msg.has_image() => {
+                let image = match self.cfg.preview_image(bvr_msg) {
+                    Some(preview) => {
+                        preview_sent = true;
+                        preview
+                    }
+                    None => self.cfg.upload_image(&bvr_msg.image).into_owned(),
+                };
+                message = message.add_file(image_attachment(&self.cfg, bvr_msg, image));
+            }
+            _ => {}
+        }
+        for attachment in self.related_attachments(bvr_msg).into_iter()
+            .chain(self.audio_attachment(bvr_msg))
+            .chain(self.metadata_attachment(bvr_msg)) {
+            message = message.add_file(attachment);
+        }
+
+        if let Some(embed) = embed {
+            message = message.embed(embed);
+        }
+
+        let buttons = build_buttons(&self.cfg, bvr_msg);
+        if !buttons.is_empty() && !self.cfg.discord_config.image_only {
+            message = message.components(vec![CreateActionRow::Buttons(buttons)]);
+        }
+
+        let (sent_channel, sent_message) = if self.cfg.discord_config.forum_mode {
+            // Forum channels don't accept messages directly, each alert starts a new post
+            let post_name: String = format!("{} - {}", bvr_msg.camera_name, bvr_msg.time)
+                .chars()
+                .take(FORUM_POST_NAME_LIMIT)
+                .collect();
+            let post = channel.create_forum_post(self.http.as_ref(), CreateForumPost::new(post_name, message))
+                .await
+                .map_err(map_send_error)?;
+            // The first message of a forum post shares the post's ID
+            (post.id, MessageId::new(post.id.get()))
+        } else {
+            let sent = channel.send_message(self.http.as_ref(), message)
+                .await
+                .map_err(map_send_error)?;
+            if self.cfg.discord_config.pin_active_events {
+                self.track_event(channel, sent.id).await;
+            }
+            (channel, sent.id)
+        };
+
+        info!("DISCORD: Message sent - {}", chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S.%3f"));
+
+        if preview_sent {
+            let full_image = image_attachment(&self.cfg, bvr_msg, self.cfg.upload_image(&bvr_msg.image).into_owned());
+            let mut edit = EditMessage::new().remove_all_attachments().new_attachment(full_image);
+            for attachment in self.related_attachments(bvr_msg).into_iter()
+                .chain(self.audio_attachment(bvr_msg))
+                .chain(self.metadata_attachment(bvr_msg)) {
+                edit = edit.new_attachment(attachment);
+            }
+            match sent_channel.edit_message(self.http.as_ref(), sent_message, edit).await {
+                Ok(_) => info!("DISCORD: Replaced preview with full image"),
+                Err(e) => error!("DISCORD: Failed to replace preview with full image: {}", e),
+            }
+        }
 
-        self.send_message(channel_id, &bvr_msg).await?;
         Ok(())
     }
+
+    /// Pins an alert that continues an ongoing event in its channel, unpinning the alert it replaces
+    ///
+    /// An alert sent within `active_event_secs` of the channel's previous alert makes it an
+    /// event. Pin failures, like a missing Manage Messages permission, are only logged.
+    ///
+    /// # Arguments
+    /// * `channel` - Channel the alert was sent to
+    /// * `message` - ID of the sent alert
+    async fn track_event(&self, channel: ChannelId, message: MessageId) {
+        let window = Duration::from_secs(self.cfg.discord_config.active_event_secs);
+        let mut events = self.events.lock().await;
+
+        let ongoing = events.get(&channel).is_some_and(|event| event.last_alert.elapsed() < window);
+        let previous = events.remove(&channel).and_then(|event| event.pinned);
+
+        let mut pinned = None;
+        if ongoing {
+            match channel.pin(self.http.as_ref(), message).await {
+                Ok(_) => pinned = Some(message),
+                Err(e) => error!("DISCORD: Failed to pin the alert of an ongoing event: {}", e),
+            }
+        }
+        if let Some(previous) = previous {
+            self.unpin(channel, previous).await;
+        }
+
+        events.insert(channel, ActiveEvent { last_alert: Instant::now(), pinned });
+    }
+
+    /// Unpins the alerts of events without an alert for `active_event_secs`
+    async fn unpin_ended_events(&self) {
+        let window = Duration::from_secs(self.cfg.discord_config.active_event_secs);
+        let mut events = self.events.lock().await;
+
+        let ended: Vec<ChannelId> = events.iter()
+            .filter(|(_, event)| event.last_alert.elapsed() >= window)
+            .map(|(channel, _)| *channel)
+            .collect();
+        for channel in ended {
+            if let Some(pinned) = events.remove(&channel).and_then(|event| event.pinned) {
+                self.unpin(channel, pinned).await;
+            }
+        }
+    }
+
+    /// Unpins an alert, logging failures
+    async fn unpin(&self, channel: ChannelId, message: MessageId) {
+        match channel.unpin(self.http.as_ref(), message).await {
+            Ok(_) => info!("DISCORD: Unpinned the alert of an ended event"),
+            Err(e) => error!("DISCORD: Failed to unpin alert: {}", e),
+        }
+    }
+
+    /// Embed with the alert details, or none with `image_only` so the message is just the image
+    fn alert_embed(&self, bvr_msg: &BvrChirpMessage) -> Option<CreateEmbed> {
+        if self.cfg.discord_config.image_only {
+            None
+        } else {
+            Some(build_embed(&self.cfg, bvr_msg))
+        }
+    }
+
+    /// Images of the other cameras of an alert collapsed from several cameras
+    fn related_attachments(&self, bvr_msg: &BvrChirpMessage) -> Vec<CreateAttachment> {
+        bvr_msg.related_images()
+            .map(|related| image_attachment(&self.cfg, related, self.cfg.upload_image(&related.image).into_owned()))
+            .collect()
+    }
+
+    /// Audio clip of the alert, attached as a file Discord can play inline, left out with `image_only`
+    fn audio_attachment(&self, bvr_msg: &BvrChirpMessage) -> Option<CreateAttachment> {
+        if self.cfg.discord_config.image_only {
+            return None;
+        }
+        bvr_msg.audio.as_ref().map(|audio| {
+            let (_, extension) = audio_format(audio);
+            CreateAttachment::bytes(audio.clone(), self.cfg.audio_filename(bvr_msg, extension))
+        })
+    }
+
+    /// Metadata of the alert as a JSON file, with `attach_metadata_json`
+    fn metadata_attachment(&self, bvr_msg: &BvrChirpMessage) -> Option<CreateAttachment> {
+        self.cfg.metadata_attachment(bvr_msg)
+            .map(|(filename, json)| CreateAttachment::bytes(json, filename))
+    }
+
+    /// Fetches each configured channel to open the connection to Discord and check access
+    ///
+    /// The first request after startup is the slowest, this keeps it from delaying the
+    /// first alert. Problems are logged, alerts may still be sent to other channels.
+    async fn warm_up(&self) {
+        // The webhook was already fetched when the client started
+        if self.webhook.is_some() {
+            return;
+        }
+
+        let channel_ids: BTreeSet<&str> = std::iter::once(self.cfg.discord_config.channel_id.as_str())
+            .chain(self.cfg.targets.values().map(|target| target.discord_channel_id.as_str()))
+            .filter(|channel_id| !channel_id.is_empty())
+            .collect();
+
+        for channel_id in channel_ids {
+            let channel = match parse_channel_id(channel_id) {
+                Some(channel) => channel,
+                None => {
+                    error!("DISCORD: Warm-up found an invalid channel ID: {}", channel_id);
+                    continue;
+                }
+            };
+
+            match self.http.get_channel(channel).await {
+                Ok(fetched) => {
+                    let name = fetched.guild().map(|guild_channel| guild_channel.name).unwrap_or_else(|| channel_id.to_string());
+                    info!("DISCORD: Warm-up reached channel {}", name);
+                }
+                Err(e) => error!("DISCORD: Warm-up failed to fetch channel {}, alerts to it will fail: {}", channel_id, e),
+            }
+        }
+    }
+}
+
+impl AsyncClient for DiscordClient {
+    const SERVICE: &'static str = "discord";
+    const LOG_NAME: &'static str = "DISCORD";
+    const IDLE_INTERVAL: Option<Duration> = Some(EVENT_CHECK_INTERVAL);
+
+    /// Sends a plain text message to the configured channel
+    ///
+    /// # Arguments
+    /// * `text` - Text of the message
+    ///
+    /// # Returns
+    /// * `Ok(())` if the message was sent
+    /// * `Err` if the channel ID is invalid or the message send fails
+    async fn send_text(&self, text: &str) -> Result<()> {
+        if let Some(webhook) = &self.webhook {
+            let execute = ExecuteWebhook::new()
+                .username(self.cfg.discord_config.bot_name.clone())
+                .content(text);
+            webhook.execute(self.http.as_ref(), true, execute)
+                .await
+                .map_err(map_send_error)?;
+            return Ok(());
+        }
+
+        let channel = parse_channel_id(&self.cfg.discord_config.channel_id)
+            .ok_or_else(|| BvrChirpError::Config(format!("Invalid channel ID: {}", self.cfg.discord_config.channel_id)))?;
+
+        channel.say(self.http.as_ref(), text)
+            .await
+            .map_err(map_send_error)?;
+        Ok(())
+    }
+
+    async fn process_alert(&self, bvr_msg: BvrChirpMessage) -> Result<()> {
+        if let Some(webhook) = &self.webhook {
+            return self.send_webhook(webhook, &bvr_msg).await;
+        }
+
+        // Parse the channel ID from the target, which may be a name from `targets`
+        let channel = parse_channel_id(self.cfg.discord_channel_for(&bvr_msg.target))
+            .ok_or_else(|| BvrChirpError::Config(format!("Invalid channel ID: {}", bvr_msg.target)))?;
+
+        self.send_message(channel, &bvr_msg).await
+    }
+
+    /// With use_image_url an alert's image can be posted by its link
+    fn has_image(&self, bvr_msg: &BvrChirpMessage) -> bool {
+        bvr_msg.has_image() || (self.cfg.discord_config.use_image_url && self.cfg.image_link(bvr_msg).is_some())
+    }
+
+    /// An event that went quiet is over, its alert no longer needs to stay pinned
+    async fn on_idle(&self) {
+        if self.cfg.discord_config.pin_active_events {
+            self.unpin_ended_events().await;
+        }
+    }
+}
+
+/// Fetches the webhook alerts are posted through, checking its URL and token
+///
+/// # Arguments
+/// * `http` - HTTP client, webhook requests don't need a bot token
+/// * `webhook_url` - URL of the webhook, including its token
+///
+/// # Returns
+/// * `Ok(Webhook)` if Discord knows the webhook
+/// * `Err` if the URL is invalid or the webhook was deleted
+async fn fetch_webhook(http: &Http, webhook_url: &str) -> Result<Webhook> {
+    let webhook = Webhook::from_url(http, webhook_url)
+        .await
+        .map_err(|e| BvrChirpError::Auth(format!("Failed to fetch Discord webhook: {}", e)))?;
+    info!("DISCORD: Posting alerts through webhook {}", webhook.name.as_deref().unwrap_or_default());
+    Ok(webhook)
+}
+
+/// Registers and answers the bot's slash commands
+struct CommandHandler {
+    cfg: BvrChirpConfig,
+}
+
+#[async_trait]
+impl EventHandler for CommandHandler {
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        let command = CreateCommand::new(RECENT_COMMAND)
+            .description("List the most recent alerts")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "count", "Number of alerts to list")
+                    .min_int_value(1)
+                    .max_int_value(RECENT_ALERTS_CAPACITY as u64)
+                    .required(false),
+            );
+
+        match Command::create_global_command(&ctx.http, command).await {
+            Ok(_) => info!("DISCORD: Registered /{} command as {}", RECENT_COMMAND, ready.user.name),
+            Err(e) => error!("DISCORD: Failed to register /{} command: {}", RECENT_COMMAND, e),
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let command = match interaction {
+            Interaction::Command(command) if command.data.name == RECENT_COMMAND => command,
+            _ => return,
+        };
+
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(recent_alerts_reply(&self.cfg, &command))
+                .ephemeral(true),
+        );
+        if let Err(e) = command.create_response(&ctx.http, response).await {
+            error!("DISCORD: Failed to answer /{} command: {}", RECENT_COMMAND, e);
+        }
+    }
+}
+
+/// Lists the recent alerts, as many as the command's `count` option asks for
+fn recent_alerts_reply(cfg: &BvrChirpConfig, command: &CommandInteraction) -> String {
+    let count = command.data.options.iter()
+        .find(|option| option.name == "count")
+        .and_then(|option| option.value.as_i64())
+        .map(|count| count.clamp(1, RECENT_ALERTS_CAPACITY as i64) as usize)
+        .unwrap_or(DEFAULT_RECENT_COUNT);
+
+    recent_alerts::summary(cfg, count, MESSAGE_CONTENT_LIMIT)
 }
 
 pub async fn run_discord_client(
-    config: DiscordConfig,
-    alert_endpoint: &str,
+    cfg: BvrChirpConfig,
     rx: Receiver<BvrChirpMessage>
 ) -> Result<()> {
-    let discord = match DiscordClient::new(config.token, alert_endpoint.to_owned()).await {
-        Ok(discord_client) => {
-            println!("DISCORD: Client ready");
-            discord_client },
+    let discord = match DiscordClient::new(&cfg).await {
+        Ok(discord_client) => discord_client,
         Err(err) => {
-            println!("DISCORD: Error creating Discord client: {}", err);
+            error!("DISCORD: Error creating Discord client: {}", err);
             exit(1)
         }
     };
 
-    loop {
-        let bvr_msg = match rx.recv() {
-            Ok(msg) => msg,
-            Err(err) => {
-                println!("DISCORD: Failed to receive message: {}", err);
-                continue;
-            }
-        };
+    if cfg.discord_config.warm_up {
+        discord.warm_up().await;
+    }
 
-        if let Err(e) = discord.process_alert(bvr_msg).await {
-            println!("DISCORD: Error processing message: {}", e);
-            continue;
-        }
+    client_loop::run_async_client(&cfg, &discord, &rx).await;
+    Ok(())
+}
+
+/// Builds the Discord embed for an alert
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the alert endpoint and embed settings
+/// * `bvr_msg` - BvrChirpMessage containing alert details
+///
+/// # Returns
+/// * CreateEmbed with the title, link and fields, without any image
+pub(crate) fn build_embed(cfg: &BvrChirpConfig, bvr_msg: &BvrChirpMessage) -> CreateEmbed {
+    let title = truncate(&cfg.alert_title(bvr_msg), EMBED_TITLE_LIMIT);
+    let description = truncate(&cfg.alert_description(bvr_msg), EMBED_DESCRIPTION_LIMIT);
+    let url = cfg.alert_link(bvr_msg);
+
+    // Render the configured fields in order, skipping unknown keys and empty values.
+    // Detections counted per object type get an inline field for each type instead.
+    let fields: Vec<(String, String, bool)> = cfg.discord_config.embed_fields.iter()
+        .flat_map(|key| match key.to_lowercase().as_str() {
+            "detections" if !bvr_msg.object_counts.is_empty() => bvr_msg.object_counts.iter()
+                .map(|(label, count)| (format!("**{}**", capitalize(label)), count.to_string(), true))
+                .collect(),
+            _ => embed_field(cfg, key, bvr_msg)
+                .map(|(name, value)| (name, value, false))
+                .into_iter()
+                .collect::<Vec<_>>(),
+        })
+        .collect();
+
+    let embed = CreateEmbed::new()
+        .title(title)
+        .url(url)
+        .colour(Colour::BLITZ_BLUE)
+        .fields(fields)
+        .footer(CreateEmbedFooter::new(cfg.footer_text(&cfg.discord_config.bot_name)))
+        .timestamp(Timestamp::now());
+
+    if description.is_empty() {
+        embed
+    } else {
+        embed.description(description)
+    }
+}
+
+/// Builds the buttons shown below the embed
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the snapshot, interaction server and camera location settings
+/// * `bvr_msg` - BvrChirpMessage containing alert details
+///
+/// # Returns
+/// * Vec of buttons, empty if none are enabled
+fn build_buttons(cfg: &BvrChirpConfig, bvr_msg: &BvrChirpMessage) -> Vec<CreateButton> {
+    let mut buttons = Vec::new();
+
+    // Link to the full resolution frame served by the snapshot server
+    if let Some(snapshot_link) = cfg.snapshot_link(&bvr_msg.db_id) {
+        buttons.push(CreateButton::new_link(snapshot_link)
+            .label(cfg.label("full_image_label")));
+    }
+
+    // Link to where the camera physically is
+    if let Some(map_url) = cfg.map_url(&bvr_msg.camera_name) {
+        buttons.push(CreateButton::new_link(map_url).label(cfg.label("map_label")));
+    }
+
+    // Link to the camera's live view
+    if let Some(live_url) = cfg.live_url(&bvr_msg.camera_name) {
+        buttons.push(CreateButton::new_link(live_url).label(cfg.label("live_label")));
+    }
+
+    // Clicks are sent to the interaction server, which mutes the camera for a while
+    if cfg.discord_acknowledge_enabled() {
+        let custom_id: String = format!("{}{}", DISCORD_ACK_PREFIX, bvr_msg.camera_name)
+            .chars()
+            .take(CUSTOM_ID_LIMIT)
+            .collect();
+        buttons.push(CreateButton::new(custom_id)
+            .label(cfg.label("acknowledge_label"))
+            .style(ButtonStyle::Success));
     }
-}
\ No newline at end of file
+
+    buttons
+}
+
+/// Looks up the embed field name and value for a configured field key
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the `labels` used as field names
+/// * `key` - Field key from the `embed_fields` config (ie: "detections", "time")
+/// * `bvr_msg` - BvrChirpMessage containing alert details
+///
+/// # Returns
+/// * `Some((name, value))` if the key is known and the value isn't empty
+/// * `None` otherwise
+fn embed_field(cfg: &BvrChirpConfig, key: &str, bvr_msg: &BvrChirpMessage) -> Option<(String, String)> {
+    let (label, value) = match key.to_lowercase().as_str() {
+        "camera" => ("camera_label", bvr_msg.camera_name.clone()),
+        "detections" => ("detections_label", bvr_msg.count_summary()),
+        "time" => ("time_label", bvr_msg.time.clone()),
+        "db_id" => ("alert_id_label", bvr_msg.db_id.clone()),
+        "daily_count" => ("today_label", cfg.daily_count_summary(bvr_msg)),
+        "details" => ("details_label", bvr_msg.detail_lines().join("\n")),
+        _ => return None,
+    };
+    // A long detections string or camera name would get the whole alert rejected
+    let value = truncate(&value, cfg.discord_config.max_field_length.min(EMBED_FIELD_VALUE_LIMIT));
+
+    if value.trim().is_empty() {
+        None
+    } else {
+        Some((format!("**{}**", cfg.label(label)), value))
+    }
+}
+
+/// Attaches an alert image, named with the extension of the format it's in
+fn image_attachment(cfg: &BvrChirpConfig, bvr_msg: &BvrChirpMessage, image: Vec<u8>) -> CreateAttachment {
+    let filename = cfg.upload_filename(bvr_msg, &image);
+    CreateAttachment::bytes(image, filename)
+}
+
+/// Parses a channel ID from the config
+///
+/// # Returns
+/// * `None` if it isn't a number or is 0, which no channel has
+fn parse_channel_id(channel_id: &str) -> Option<ChannelId> {
+    channel_id.trim().parse::<u64>().ok().filter(|id| *id != 0).map(ChannelId::new)
+}
+
+/// Classifies a serenity error by the HTTP status Discord responded with
+fn map_send_error(err: serenity::Error) -> BvrChirpError {
+    let status = match &err {
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response)) => Some(response.status_code.as_u16()),
+        _ => None,
+    };
+
+    match status {
+        Some(401) | Some(403) => BvrChirpError::Auth(format!("Failed to send message: {}", err)),
+        Some(429) => BvrChirpError::Transient(format!("Failed to send message: {}", err)),
+        Some(status) if status >= 500 => BvrChirpError::Transient(format!("Failed to send message: {}", err)),
+        _ => BvrChirpError::Send(format!("Failed to send message: {}", err)),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_channel_id_rejects_zero_and_non_numbers() {
+        assert_eq!(parse_channel_id(" 123456 "), Some(ChannelId::new(123456)));
+        assert_eq!(parse_channel_id("0"), None);
+        assert_eq!(parse_channel_id("front"), None);
+        assert_eq!(parse_channel_id(""), None);
+    }
+}
@@ -0,0 +1,248 @@
+use std::thread;
+use std::time::Duration;
+use reqwest::blocking::{multipart, Client};
+use serde_json::json;
+use crossbeam_channel::Receiver;
+
+use crate::bvr_chirp_config::BvrChirpConfig;
+use crate::bvr_chirp_error::{check_status, BvrChirpError, Result};
+use crate::bvr_chirp_message::BvrChirpMessage;
+use crate::clients::client_loop::{self, BlockingClient};
+use crate::image_processing;
+use log::{error, info};
+
+/// Seconds to wait for a connection to the instance
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+/// Seconds a whole request, including the image upload, may take
+const REQUEST_TIMEOUT_SECS: u64 = 60;
+/// How often to check whether the instance finished processing an uploaded image
+const MEDIA_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How many times to check before posting without waiting any longer
+const MEDIA_POLL_ATTEMPTS: u32 = 10;
+/// Most images a status can have attached
+const MAX_STATUS_MEDIA: usize = 4;
+
+/// A client for posting statuses with an image to a Mastodon (or compatible) account
+struct MastodonClient {
+    client: Client,
+    instance_url: String,
+    access_token: String,
+    cfg: BvrChirpConfig,
+}
+
+impl MastodonClient {
+    /// Creates a new MastodonClient for the configured instance and account
+    ///
+    /// # Arguments
+    /// * `cfg` - BvrChirpConfig containing the instance URL, access token and visibility
+    ///
+    /// # Returns
+    /// * `Ok(MastodonClient)` if the HTTP client could be built
+    /// * `Err` if the HTTP client configuration is invalid
+    fn new(cfg: &BvrChirpConfig) -> Result<Self> {
+        let config = &cfg.mastodon_config;
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS))
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| BvrChirpError::Config(format!("Failed to build HTTP client: {}", e)))?;
+
+        Ok(Self {
+            client,
+            instance_url: config.instance_url.trim_end_matches('/').to_string(),
+            access_token: config.access_token.clone(),
+            cfg: cfg.clone(),
+        })
+    }
+
+    /// Uploads an image as a media attachment with the
+    /// [media](https://docs.joinmastodon.org/methods/media/#v2) API
+    ///
+    /// # Arguments
+    /// * `filename` - Name of the image file
+    /// * `file_data` - Byte array containing the image data
+    /// * `description` - Alt text for the image
+    ///
+    /// # Returns
+    /// * `Ok((String, bool))` with the media ID, and whether the instance is still processing it
+    /// * `Err` if the upload fails
+    fn upload_media(&self, filename: String, file_data: &[u8], description: &str) -> Result<(String, bool)> {
+        let form = multipart::Form::new()
+            .text("description", description.to_string())
+            .part("file", multipart::Part::bytes(file_data.to_vec())
+                .file_name(filename)
+                .mime_str(image_processing::mime_type(file_data))
+                .map_err(|e| BvrChirpError::Upload(e.to_string()))?);
+
+        let response = self.client
+            .post(&format!("{}/api/v2/media", self.instance_url))
+            .bearer_auth(&self.access_token)
+            .multipart(form)
+            .send()?;
+
+        // 202 means the upload was accepted but the image is still being processed
+        let processing = response.status().as_u16() == 202;
+        let response = check_status(response, BvrChirpError::Upload)?
+            .json::<serde_json::Value>()?;
+
+        let media_id = response["id"].as_str()
+            .ok_or_else(|| BvrChirpError::Upload("Failed to get media ID".to_string()))?;
+        Ok((media_id.to_string(), processing))
+    }
+
+    /// Waits until the instance finished processing an uploaded image
+    ///
+    /// Statuses referencing media that's still processing are rejected. Gives up
+    /// quietly after a few attempts and lets the status post report any error.
+    fn wait_for_media(&self, media_id: &str) {
+        for _ in 0..MEDIA_POLL_ATTEMPTS {
+            thread::sleep(MEDIA_POLL_INTERVAL);
+
+            let ready = self.client
+                .get(&format!("{}/api/v1/media/{}", self.instance_url, media_id))
+                .bearer_auth(&self.access_token)
+                .send()
+                .map(|response| response.status().as_u16() == 200)
+                .unwrap_or(false);
+            if ready {
+                return;
+            }
+        }
+    }
+
+    /// Posts a status with attached images using the
+    /// [statuses](https://docs.joinmastodon.org/methods/statuses/#create) API
+    ///
+    /// # Arguments
+    /// * `status` - Text of the status
+    /// * `media_ids` - IDs of the uploaded images, empty for a text only status
+    ///
+    /// # Returns
+    /// * `Ok(())` if the status was posted
+    /// * `Err` if the API request fails
+    fn post_status(&self, status: &str, media_ids: &[String]) -> Result<()> {
+        let response = self.client
+            .post(&format!("{}/api/v1/statuses", self.instance_url))
+            .bearer_auth(&self.access_token)
+            .json(&json!({
+                "status": status,
+                "media_ids": media_ids,
+                "visibility": self.cfg.mastodon_config.visibility,
+            }))
+            .send()?;
+
+        check_status(response, BvrChirpError::Send)?;
+        Ok(())
+    }
+}
+
+impl BlockingClient for MastodonClient {
+    const SERVICE: &'static str = "mastodon";
+    const LOG_NAME: &'static str = "MASTODON";
+
+    /// Posts a status message like a heartbeat as a text only status
+    ///
+    /// # Arguments
+    /// * `text` - Text of the status
+    fn send_text(&self, text: &str) -> Result<()> {
+        self.post_status(text, &[])
+    }
+
+    /// Processes an alert by uploading the image and posting a status with the caption
+    ///
+    /// An alert collapsed from several cameras has the images of the other cameras
+    /// attached too, up to the 4 images a status can have.
+    ///
+    /// # Arguments
+    /// * `bvr_msg` - BvrChirpMessage containing alert details and image
+    ///
+    /// # Returns
+    /// * `Ok(())` if processing succeeds
+    /// * `Err` if the image upload or status post fails
+    fn process_alert(&self, bvr_msg: &BvrChirpMessage) -> Result<()> {
+        // With image_only the status carries nothing but the media
+        let status = if self.cfg.mastodon_config.image_only { String::new() } else { build_status(&self.cfg, bvr_msg) };
+        let description = format!("Snapshot from the {} camera", bvr_msg.camera_name);
+
+        // Alerts whose image couldn't be decoded are posted as text only
+        let mut media_ids = Vec::new();
+        if bvr_msg.has_image() {
+            let image = self.cfg.upload_image(&bvr_msg.image);
+            let (media_id, processing) = self.upload_media(self.cfg.upload_filename(bvr_msg, &image), &image, &description)?;
+            if processing {
+                self.wait_for_media(&media_id);
+            }
+            media_ids.push(media_id);
+        }
+
+        for related in bvr_msg.related_images() {
+            if media_ids.len() == MAX_STATUS_MEDIA {
+                break;
+            }
+            let description = format!("Snapshot from the {} camera", related.camera_name);
+            let image = self.cfg.upload_image(&related.image);
+            match self.upload_media(self.cfg.upload_filename(related, &image), &image, &description) {
+                Ok((media_id, processing)) => {
+                    if processing {
+                        self.wait_for_media(&media_id);
+                    }
+                    media_ids.push(media_id);
+                }
+                Err(e) => error!("MASTODON: Failed to upload image of {} camera: {}", related.camera_name, e),
+            }
+        }
+
+        self.post_status(&status, &media_ids)?;
+
+        info!("MASTODON: Status posted - {}", chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S.%3f"));
+        Ok(())
+    }
+}
+
+/// Main entry point for running the Mastodon client service
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig containing the Mastodon instance and account configuration
+/// * `rx` - Receiver channel for BvrChirpMessages
+///
+/// # Returns
+/// * `Ok(())` once the message channel closes
+/// * `Err` if client initialization fails
+pub async fn run_mastodon_client(
+    cfg: BvrChirpConfig,
+    rx: Receiver<BvrChirpMessage>
+) -> Result<()> {
+    let mastodon = match MastodonClient::new(&cfg) {
+        Ok(mastodon) => mastodon,
+        Err(err) => {
+            error!("MASTODON: Error creating Mastodon client: {}", err);
+            return Err(err);
+        }
+    };
+
+    client_loop::run_blocking_client(cfg, mastodon, rx).await;
+    Ok(())
+}
+
+/// Builds the caption of the status posted for an alert
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the title emoji and alert template settings
+/// * `bvr_msg` - BvrChirpMessage containing alert details
+///
+/// # Returns
+/// * Status text with the title, detections, time and today's alert count
+pub(crate) fn build_status(cfg: &BvrChirpConfig, bvr_msg: &BvrChirpMessage) -> String {
+    let mut status = cfg.alert_title(bvr_msg);
+    let description = cfg.alert_description(bvr_msg);
+    if !description.is_empty() {
+        status.push_str(&format!("\n{}", description));
+    }
+    status.push_str(&format!("\n\n{}: {}\n{}: {}", cfg.label("detections_label"), bvr_msg.count_summary(),
+                             cfg.label("time_label"), bvr_msg.time));
+    let daily_count = cfg.daily_count_summary(bvr_msg);
+    if !daily_count.is_empty() {
+        status.push_str(&format!("\n{}", daily_count));
+    }
+    status
+}
@@ -1,176 +1,645 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 use std::process::exit;
-use std::str::FromStr;
-use matrix_sdk::{Client, config::SyncSettings};
-use matrix_sdk::ruma::events::room::message::RoomMessageEventContent;
-use matrix_sdk::ruma::{OwnedRoomId, RoomId};
-use anyhow::{anyhow, Result};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use matrix_sdk::{Client, Room, config::SyncSettings};
+use matrix_sdk::ruma::events::reaction::OriginalSyncReactionEvent;
+use matrix_sdk::ruma::events::room::message::{AudioInfo, AudioMessageEventContent, ImageMessageEventContent, MessageType,
+                                              ReplacementMetadata, RoomMessageEventContent};
+use matrix_sdk::ruma::{OwnedEventId, OwnedMxcUri, OwnedRoomId, RoomId, UInt};
 use crossbeam_channel::Receiver;
-use crate::bvr_chirp_config::MatrixConfig;
-use crate::bvr_chirp_message::BvrChirpMessage;
-use crate::message_templates::MATRIX_TEMPLATE;
-use std::sync::Arc;
-use mime::Mime;
+use crate::backoff::Backoff;
+use crate::bvr_chirp_config::{BvrChirpConfig, MatrixFormat};
+use crate::bvr_chirp_error::{BvrChirpError, Result};
+use crate::bvr_chirp_message::{audio_format, BvrChirpMessage};
+use crate::clients::client_loop::{self, AsyncClient};
+use crate::false_positives;
+use crate::image_processing;
+use crate::message_templates::{json_escape, plain_ascii, truncate, MATRIX_TEMPLATE};
+use mime::IMAGE_JPEG;
+use serde_json::Value;
+use log::{error, info, warn};
 
 /// A client for sending messages and uploading files to Matrix chat rooms
 ///
-/// Handles authentication, file uploads, and sending formatted messages to a specified
-/// Matrix room using the Matrix SDK.
+/// Handles authentication, file uploads, and sending formatted messages to the configured
+/// Matrix rooms using the Matrix SDK.
 struct MatrixClient {
     client: Client,
-    room_id: Arc<OwnedRoomId>,
+    room_ids: Vec<OwnedRoomId>,
+    /// Rooms of the named targets that have their own, replacing `room_ids` for their alerts
+    target_room_ids: HashMap<String, Vec<OwnedRoomId>>,
+    cfg: BvrChirpConfig,
+    sent_alerts: SentAlerts,
 }
 
+/// Reaction that marks an alert as a false positive
+const FALSE_POSITIVE_REACTION: &str = "❌";
+
+/// Number of sent alerts remembered so reactions can be mapped back to them
+const SENT_ALERTS_CAPACITY: usize = 500;
+
+/// Recently sent alerts by the ID of their Matrix event, oldest first
+type SentAlerts = Arc<Mutex<VecDeque<(OwnedEventId, BvrChirpMessage)>>>;
+
 impl MatrixClient {
     /// Creates a new authenticated Matrix client
     ///
     /// # Arguments
-    /// * `token` - Authentication token for the Matrix bot
-    /// * `bot_name` - Display name for the bot in Matrix
-    /// * `room_id_str` - ID of the Matrix room to send messages to
-    /// * `homeserver_url` - URL of the Matrix homeserver
+    /// * `cfg` - BvrChirpConfig containing the Matrix credentials, homeserver URL and room IDs
     ///
     /// # Returns
     /// * `Ok(MatrixClient)` if authentication and initialization succeed
     /// * `Err` if client creation, authentication, or initial sync fails
-    async fn new(config: &MatrixConfig) -> Result<Self> {
+    async fn new(cfg: &BvrChirpConfig) -> Result<Self> {
+        let config = &cfg.matrix_config;
         let client = Client::builder()
             .homeserver_url(config.homeserver_url.as_str())
             .build()
-            .await?;
+            .await
+            .map_err(|e| BvrChirpError::Config(format!("Failed to build client: {}", e)))?;
 
         client.matrix_auth()
             .login_username(config.username.as_str(), config.password.as_str())
             .initial_device_display_name(config.bot_name.as_str())
-            .await?;
+            .await
+            .map_err(|e| BvrChirpError::Auth(format!("Login failed: {}", e)))?;
+
+        let sync_token = client.sync_once(SyncSettings::default()).await
+            .ok()
+            .map(|response| response.next_batch);
+
+        let room_ids = parse_room_ids(&config.room_id)?;
+
+        if room_ids.is_empty() {
+            return Err(BvrChirpError::Config("No room ID configured".to_string()));
+        }
+
+        let mut target_room_ids = HashMap::new();
+        for (name, target) in &cfg.targets {
+            if !target.matrix_room_id.is_empty() {
+                target_room_ids.insert(name.clone(), parse_room_ids(&target.matrix_room_id)?);
+            }
+        }
 
-        let _ = client.sync_once(SyncSettings::default()).await;
+        let sent_alerts: SentAlerts = Arc::new(Mutex::new(VecDeque::with_capacity(SENT_ALERTS_CAPACITY)));
 
-        let room_id = Arc::new(RoomId::parse(config.room_id.as_str())?);
-        Ok(Self { client, room_id })
+        // Reactions only arrive while syncing, which is only needed when they're enabled
+        if config.enable_reactions {
+            listen_for_reactions(&client, sent_alerts.clone(), Duration::from_secs(config.reaction_mute_secs), sync_token);
+        }
+
+        Ok(Self { client, room_ids, target_room_ids, cfg: cfg.clone(), sent_alerts })
     }
 
     /// Uploads file data to the Matrix media repository
     ///
+    /// The media repository doesn't store a file name, it's sent with the message instead.
+    ///
     /// # Arguments
-    /// * `filename` - Name of the file to be uploaded
     /// * `file_data` - Byte array containing the file contents
     ///
     /// # Returns
     /// * `Ok(String)` containing the Matrix content URI of the uploaded file
     /// * `Err` if the upload fails or returns an error
     async fn upload_file(&self, file_data: &[u8]) -> Result<String> {
-        let mime_type = Mime::from_str("image/jpeg")?;
+        // Images may have been converted to another `upload_format`, and previews are JPEG
+        let content_type: mime::Mime = image_processing::mime_type(file_data).parse().unwrap_or(IMAGE_JPEG);
         let content_uri = self.client
             .media()
-            .upload(&mime_type, file_data.to_vec())
+            .upload(&content_type, file_data.to_vec())
             .await
-            .map_err(|e| anyhow!("Upload failed: {}", e))?;
+            .map_err(|e| BvrChirpError::Upload(e.to_string()))?;
 
         Ok(content_uri.content_uri.to_string())
     }
 
-    /// Sends a formatted message to the configured Matrix room
+    /// Sends a formatted message to each of the configured Matrix rooms, or the rooms
+    /// of the alert's named target
+    ///
+    /// A room that fails is logged and skipped so the remaining rooms still get the alert.
     ///
     /// # Arguments
-    /// * `alert_endpoint` - Base URL for alert links (ie: BlueIris server address)
     /// * `content_uri` - Matrix content URI of the uploaded image
+    /// * `filename` - File name of the uploaded image, see `upload_filename`
     /// * `bvr_msg` - BvrChirpMessage containing alert details
     ///
     /// # Returns
-    /// * `Ok(())` if message send succeeds
+    /// * `Ok(Vec)` with each room the message was sent to and the ID of the sent message
+    /// * `Err` with the last failure if no room could be sent to
+    async fn send_message(&self, content_uri: &str, filename: &str, bvr_msg: &BvrChirpMessage) -> Result<Vec<(OwnedRoomId, OwnedEventId)>> {
+        let content = self.alert_content(content_uri, filename, bvr_msg)?;
+
+        let room_ids = self.target_room_ids.get(&bvr_msg.target).unwrap_or(&self.room_ids);
+
+        let mut last_error = None;
+        let mut sent = Vec::new();
+        for room_id in room_ids {
+            match self.send_to_room(room_id, content.clone()).await {
+                Ok(event_id) => {
+                    self.remember(event_id.clone(), bvr_msg);
+                    sent.push((room_id.clone(), event_id));
+                }
+                Err(err) => {
+                    error!("MATRIX: Failed to send message to room {}: {}", room_id, err);
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        match last_error {
+            Some(err) if sent.is_empty() => Err(err),
+            _ => Ok(sent),
+        }
+    }
+
+    /// Content of an alert message, a bare image message with `image_only`
+    ///
+    /// # Arguments
+    /// * `content_uri` - Matrix content URI of the uploaded image
+    /// * `filename` - File name of the uploaded image, see `upload_filename`
+    /// * `bvr_msg` - BvrChirpMessage containing alert details
+    fn alert_content(&self, content_uri: &str, filename: &str, bvr_msg: &BvrChirpMessage) -> Result<RoomMessageEventContent> {
+        if self.cfg.matrix_config.image_only {
+            return Ok(RoomMessageEventContent::new(MessageType::Image(ImageMessageEventContent::plain(
+                filename.to_string(),
+                OwnedMxcUri::from(content_uri),
+            ))));
+        }
+
+        build_content(self.cfg.matrix_config.format, self.cfg.matrix_config.plain_ascii,
+                      &build_message(&self.cfg, content_uri, filename, bvr_msg))
+    }
+
+    /// Replaces the preview image of sent alerts with the full image by editing them
+    ///
+    /// Failures are logged, the alert has already been delivered with its preview.
+    ///
+    /// # Arguments
+    /// * `sent` - Each room the alert was sent to with the ID of its message
+    /// * `bvr_msg` - BvrChirpMessage containing alert details and image
+    async fn replace_preview(&self, sent: Vec<(OwnedRoomId, OwnedEventId)>, bvr_msg: &BvrChirpMessage) {
+        let image = self.cfg.upload_image(&bvr_msg.image);
+        let content = match self.upload_file(&image).await
+            .and_then(|content_uri| self.alert_content(&content_uri, &self.cfg.upload_filename(bvr_msg, &image), bvr_msg)) {
+            Ok(content) => content,
+            Err(err) => {
+                error!("MATRIX: Failed to prepare full image to replace preview: {}", err);
+                return;
+            }
+        };
+
+        for (room_id, event_id) in sent {
+            let edit = content.clone().make_replacement(ReplacementMetadata::new(event_id, None), None);
+            if let Err(err) = self.send_to_room(&room_id, edit).await {
+                error!("MATRIX: Failed to replace preview with full image in room {}: {}", room_id, err);
+            }
+        }
+    }
+
+    /// Sends the images of the other cameras of a collapsed alert right after the alert
+    ///
+    /// Matrix messages hold a single image, so each camera's image is its own message.
+    /// Failures are logged, the alert itself has already been delivered.
+    ///
+    /// # Arguments
+    /// * `sent` - Each room the alert was sent to with the ID of its message
+    /// * `bvr_msg` - BvrChirpMessage with the alerts of the other cameras
+    async fn send_related(&self, sent: &[(OwnedRoomId, OwnedEventId)], bvr_msg: &BvrChirpMessage) {
+        for related in bvr_msg.related_images() {
+            let content_uri = match self.upload_file(&self.cfg.upload_image(&related.image)).await {
+                Ok(content_uri) => content_uri,
+                Err(err) => {
+                    error!("MATRIX: Failed to upload image of {} camera: {}", related.camera_name, err);
+                    continue;
+                }
+            };
+            let content = RoomMessageEventContent::new(MessageType::Image(ImageMessageEventContent::plain(
+                format!("{} camera", related.camera_name),
+                OwnedMxcUri::from(content_uri),
+            )));
+
+            for (room_id, _) in sent {
+                if let Err(err) = self.send_to_room(room_id, content.clone()).await {
+                    error!("MATRIX: Failed to send image of {} camera to room {}: {}", related.camera_name, room_id, err);
+                }
+            }
+        }
+    }
+
+    /// Sends the audio clip of an alert as an `m.audio` message right after the alert
+    ///
+    /// Left out with `image_only`. Failures are logged, the alert itself has already been delivered.
+    ///
+    /// # Arguments
+    /// * `sent` - Each room the alert was sent to with the ID of its message
+    /// * `bvr_msg` - BvrChirpMessage with the audio clip
+    async fn send_audio(&self, sent: &[(OwnedRoomId, OwnedEventId)], bvr_msg: &BvrChirpMessage) {
+        let audio = match &bvr_msg.audio {
+            Some(audio) if !self.cfg.matrix_config.image_only => audio,
+            _ => return,
+        };
+
+        let (mime_type, extension) = audio_format(audio);
+        let content_type: mime::Mime = match mime_type.parse() {
+            Ok(content_type) => content_type,
+            Err(_) => return,
+        };
+        let content_uri = match self.client.media().upload(&content_type, audio.clone()).await {
+            Ok(response) => response.content_uri,
+            Err(err) => {
+                error!("MATRIX: Failed to upload audio clip of {} camera: {}", bvr_msg.camera_name, err);
+                return;
+            }
+        };
+
+        let mut info = AudioInfo::new();
+        info.mimetype = Some(mime_type.to_string());
+        info.size = UInt::new(audio.len() as u64);
+        let content = RoomMessageEventContent::new(MessageType::Audio(
+            AudioMessageEventContent::plain(self.cfg.audio_filename(bvr_msg, extension), content_uri)
+                .info(Some(Box::new(info)))
+        ));
+
+        for (room_id, _) in sent {
+            if let Err(err) = self.send_to_room(room_id, content.clone()).await {
+                error!("MATRIX: Failed to send audio clip to room {}: {}", room_id, err);
+            }
+        }
+    }
+
+    /// Sends message content to a single Matrix room
+    ///
+    /// # Arguments
+    /// * `room_id` - ID of the room to send to
+    /// * `content` - Message content to send
+    ///
+    /// # Returns
+    /// * `Ok(OwnedEventId)` with the ID of the sent message if message send succeeds
     /// * `Err` if room access or message send fails
-    async fn send_message(&self, alert_endpoint: &str, content_uri: &str, bvr_msg: &BvrChirpMessage) -> Result<()> {
-        let msg = build_message(content_uri, alert_endpoint, bvr_msg);
-        let room = self.client.get_room(&self.room_id)
-            .ok_or_else(|| anyhow!("Failed to find the room"))?;
+    async fn send_to_room(&self, room_id: &RoomId, content: RoomMessageEventContent) -> Result<OwnedEventId> {
+        let room = self.find_room(room_id).await?;
 
-        let content = RoomMessageEventContent::text_plain(msg);
-        room.send(content).await?;
-        Ok(())
+        let response = room.send(content).await
+            .map_err(|e| BvrChirpError::Send(e.to_string()))?;
+        Ok(response.event_id)
+    }
+
+    /// Looks up a joined room, syncing and retrying with a backoff if it isn't known yet
+    ///
+    /// Right after startup the initial sync may not have populated the room list, so
+    /// an alert arriving then would otherwise fail.
+    ///
+    /// # Arguments
+    /// * `room_id` - ID of the room to find
+    ///
+    /// # Returns
+    /// * `Ok(Room)` once the room is known
+    /// * `Err` if it's still unknown after `room_lookup_retries` attempts, or right away
+    ///   if the homeserver rejects the sync, ie: an expired access token
+    async fn find_room(&self, room_id: &RoomId) -> Result<Room> {
+        let mut backoff = Backoff::new(&self.cfg.backoff);
+        for attempt in 0..=self.cfg.matrix_config.room_lookup_retries {
+            if let Some(room) = self.client.get_room(room_id) {
+                return Ok(room);
+            }
+            if attempt == self.cfg.matrix_config.room_lookup_retries {
+                break;
+            }
+
+            let delay = backoff.next_delay();
+            warn!("MATRIX: Room {} not found yet, syncing and retrying in {}ms", room_id, delay.as_millis());
+            if let Err(err) = self.client.sync_once(SyncSettings::default()).await {
+                let err = classify_error(&err);
+                if !err.is_retryable() {
+                    return Err(err);
+                }
+                warn!("MATRIX: Sync failed: {}", err);
+            }
+            tokio::time::sleep(delay).await;
+        }
+
+        Err(BvrChirpError::Transient("Failed to find the room".to_string()))
+    }
+
+    /// Remembers which alert a sent event belongs to, so reactions to it can be acted on
+    fn remember(&self, event_id: OwnedEventId, bvr_msg: &BvrChirpMessage) {
+        if !self.cfg.matrix_config.enable_reactions {
+            return;
+        }
+
+        if let Ok(mut sent_alerts) = self.sent_alerts.lock() {
+            if sent_alerts.len() == SENT_ALERTS_CAPACITY {
+                sent_alerts.pop_front();
+            }
+            // The image is already sent, there's no need to keep it around
+            let mut alert = bvr_msg.clone();
+            alert.image = Vec::new();
+            alert.audio = None;
+            alert.related = Vec::new();
+            sent_alerts.push_back((event_id, alert));
+        }
+    }
+}
+
+impl AsyncClient for MatrixClient {
+    const SERVICE: &'static str = "matrix";
+    const LOG_NAME: &'static str = "MATRIX";
+
+    /// Sends a plain text message to each of the configured Matrix rooms
+    ///
+    /// # Arguments
+    /// * `text` - Text of the message
+    ///
+    /// # Returns
+    /// * `Ok(())` if the message was sent to at least one room
+    /// * `Err` with the last failure if no room could be sent to
+    async fn send_text(&self, text: &str) -> Result<()> {
+        let content = if self.cfg.matrix_config.plain_ascii {
+            RoomMessageEventContent::text_plain(plain_ascii(text))
+        } else {
+            RoomMessageEventContent::text_plain(text)
+        };
+
+        let mut last_error = None;
+        let mut sent = false;
+        for room_id in &self.room_ids {
+            match self.send_to_room(room_id, content.clone()).await {
+                Ok(_) => sent = true,
+                Err(err) => last_error = Some(err),
+            }
+        }
+
+        match last_error {
+            Some(err) if !sent => Err(err),
+            _ => Ok(()),
+        }
     }
 
     /// Processes an alert by uploading an image and sending a formatted message
     ///
     /// # Arguments
-    /// * `alert_endpoint` - Base URL for alert links (ie: BlueIris server address)
     /// * `bvr_msg` - BvrChirpMessage containing alert details and image
     ///
     /// # Returns
     /// * `Ok(())` if processing succeeds
     /// * `Err` if image upload or message send fails
-    async fn process_alert(&self, alert_endpoint: &str, bvr_msg: BvrChirpMessage) -> Result<()> {
-        let content_uri = self.upload_file(&bvr_msg.image).await?;
-        self.send_message(alert_endpoint, &content_uri, &bvr_msg).await?;
+    async fn process_alert(&self, bvr_msg: BvrChirpMessage) -> Result<()> {
+        // With fast_preview a small version of the image goes out first and the message is
+        // edited once the full image is uploaded. Alerts whose image couldn't be decoded are sent as text only.
+        let preview = self.cfg.preview_image(&bvr_msg);
+        let image = match &preview {
+            Some(preview) => Cow::Borrowed(preview.as_slice()),
+            None => self.cfg.upload_image(&bvr_msg.image),
+        };
+        let content_uri = if image.is_empty() { String::new() } else { self.upload_file(&image).await? };
+        let sent = self.send_message(&content_uri, &self.cfg.upload_filename(&bvr_msg, &image), &bvr_msg).await?;
+
+        info!("MATRIX: Message sent - {}", chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S.%3f"));
 
-        println!("MATRIX: Message sent - {}", chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S.%3f"));
+        self.send_related(&sent, &bvr_msg).await;
+        self.send_audio(&sent, &bvr_msg).await;
+
+        if preview.is_some() {
+            self.replace_preview(sent, &bvr_msg).await;
+        }
         Ok(())
     }
 }
 
+/// Parses configured room IDs
+///
+/// # Returns
+/// * `Err` naming the first invalid room ID
+fn parse_room_ids(room_ids: &[String]) -> Result<Vec<OwnedRoomId>> {
+    room_ids.iter()
+        .map(|room_id| RoomId::parse(room_id.as_str())
+            .map_err(|e| BvrChirpError::Config(format!("Invalid room ID '{}': {}", room_id, e))))
+        .collect()
+}
+
+/// Starts syncing in the background and suppresses alerts that get a ❌ reaction
+///
+/// A ❌ on one of the bot's alerts marks that camera and detections combination as
+/// a false positive, so matching alerts are dropped for `mute_for`.
+///
+/// # Arguments
+/// * `client` - Logged in Matrix client
+/// * `sent_alerts` - Alerts sent by the bot, by event ID
+/// * `mute_for` - How long to suppress a false positive combination
+/// * `sync_token` - Token of the initial sync, so older reactions aren't replayed
+fn listen_for_reactions(client: &Client, sent_alerts: SentAlerts, mute_for: Duration, sync_token: Option<String>) {
+    let own_user_id = client.user_id().map(|user_id| user_id.to_owned());
+
+    client.add_event_handler(move |event: OriginalSyncReactionEvent| {
+        let sent_alerts = sent_alerts.clone();
+        let own_user_id = own_user_id.clone();
+        async move {
+            let relation = &event.content.relates_to;
+            if relation.key != FALSE_POSITIVE_REACTION || Some(&event.sender) == own_user_id.as_ref() {
+                return;
+            }
+
+            let alert = sent_alerts.lock().ok().and_then(|sent_alerts| {
+                sent_alerts.iter()
+                    .find(|(event_id, _)| *event_id == relation.event_id)
+                    .map(|(_, alert)| alert.clone())
+            });
+
+            if let Some(alert) = alert {
+                false_positives::mark(&alert, mute_for);
+                info!("MATRIX: {} marked '{}' on {} camera as a false positive, suppressed for {}s",
+                         event.sender, alert.detections, alert.camera_name, mute_for.as_secs());
+            }
+        }
+    });
+
+    let client = client.clone();
+    tokio::spawn(async move {
+        let mut settings = SyncSettings::default();
+        if let Some(token) = sync_token {
+            settings = settings.token(token);
+        }
+        if let Err(err) = client.sync(settings).await {
+            error!("MATRIX: Sync stopped, reactions are no longer handled: {}", err);
+        }
+    });
+}
+
 /// Main entry point for running the Matrix client service
 ///
 /// Creates and initializes a Matrix client, then enters the main processing loop
 /// to handle incoming messages. Will exit the program if client initialization fails.
 ///
 /// # Arguments
-/// * `config` - MatrixConfig containing authentication and connection details
-/// * `alert_endpoint` - Base URL for alert links (ie: BlueIris server address)
+/// * `cfg` - BvrChirpConfig containing the Matrix authentication and connection details
 /// * `rx` - Receiver channel for BvrChirpMessages
 ///
 /// # Returns
 /// * `Ok(())` if client runs successfully
 /// * `Err` if a fatal error occurs during operation
 pub async fn run_matrix_client(
-    config: MatrixConfig,
-    alert_endpoint: &str,
+    cfg: BvrChirpConfig,
     rx: Receiver<BvrChirpMessage>
 ) -> Result<()> {
-    let matrix_result = MatrixClient::new(&config).await;
+    let matrix_result = MatrixClient::new(&cfg).await;
 
     let matrix = match matrix_result {
         Ok(matrix) => matrix,
         Err(err) => {
-            println!("SLACK: unable to create client. Aborting: {}", err);
+            error!("MATRIX: unable to create client. Aborting: {}", err);
             exit(1);
         }
     };
 
-    println!("MATRIX: Client ready");
+    client_loop::run_async_client(&cfg, &matrix, &rx).await;
+    Ok(())
+}
 
-    loop {
-        let bvr_msg = match rx.recv() {
-            Ok(msg) => msg,
-            Err(err) => {
-                println!("MATRIX: Failed to receive message: {}", err);
-                continue;
-            }
-        };
+/// Creates the message content in the configured format from a rendered template
+///
+/// # Arguments
+/// * `format` - Whether to send plain text, HTML or markdown
+/// * `ascii_only` - Send the plain text body without emoji or other non-ASCII characters, whatever the format
+/// * `rendered` - Template rendered by `build_message`
+///
+/// # Returns
+/// * `Ok(RoomMessageEventContent)` ready to send
+/// * `Err` if the rendered template isn't valid JSON
+fn build_content(format: MatrixFormat, ascii_only: bool, rendered: &str) -> Result<RoomMessageEventContent> {
+    let json: Value = serde_json::from_str(rendered)
+        .map_err(|e| BvrChirpError::Config(format!("Matrix template is not valid JSON: {}", e)))?;
+    let body = json["body"].as_str().unwrap_or_default();
 
-        if let Err(err) = matrix.process_alert(alert_endpoint, bvr_msg.to_owned()).await {
-            println!("MATRIX: Error processing message: {}", err);
-        }
+    // Bridges to SMS or IRC garble HTML and emoji
+    if ascii_only {
+        return Ok(RoomMessageEventContent::text_plain(plain_ascii(body)));
     }
+
+    Ok(match format {
+        MatrixFormat::Plain => RoomMessageEventContent::text_plain(body),
+        MatrixFormat::Html => RoomMessageEventContent::text_html(body, json["formatted_body"].as_str().unwrap_or(body)),
+        MatrixFormat::Markdown => RoomMessageEventContent::text_markdown(json["markdown_body"].as_str().unwrap_or(body)),
+    })
 }
 
 /// Builds a formatted Matrix message from a template using the provided data
 ///
 /// # Arguments
+/// * `cfg` - BvrChirpConfig with the alert endpoint
 /// * `content_uri` - Matrix content URI of the uploaded image
-/// * `alert_endpoint` - Base URL for alert links
+/// * `filename` - File name of the uploaded image, see `upload_filename`
 /// * `bvr_msg` - BvrChirpMessage containing alert details
 ///
 /// # Returns
 /// * String containing the formatted message ready to send to Matrix
-fn build_message(content_uri: &str, alert_endpoint: &str, bvr_msg: &BvrChirpMessage) -> String {
+pub(crate) fn build_message(cfg: &BvrChirpConfig, content_uri: &str, filename: &str, bvr_msg: &BvrChirpMessage) -> String {
     let mut msg = MATRIX_TEMPLATE.clone();
+    // Long values are cut so an unusual detections string can't make the event too large
+    let limit = cfg.matrix_config.max_field_length;
+    // Replaced first so the labels can use the placeholders below
+    for key in ["detections_label", "time_label", "today_label"] {
+        msg = msg.replace(&format!("<{}>", key.to_uppercase()), &json_escape(&cfg.label(key)));
+    }
+    msg = msg.replace("<DAILY_COUNT_TEXT>", &json_escape(&cfg.render_label("daily_count_format", bvr_msg)));
     msg = msg.replace("<IMG_URI>", content_uri);
-    msg = msg.replace("<CAMERA_NAME>", &bvr_msg.camera_name);
+    msg = msg.replace("<FILENAME>", &json_escape(filename));
+    msg = msg.replace("<TITLE>", &json_escape(&truncate(&cfg.alert_title(bvr_msg), limit)));
+    let description = truncate(&cfg.alert_description(bvr_msg), limit);
+    if description.is_empty() {
+        msg = msg.replace("<DESCRIPTION_HTML>", "");
+        msg = msg.replace("<DESCRIPTION_MARKDOWN>", "");
+        msg = msg.replace("<DESCRIPTION>", "");
+    } else {
+        let description = json_escape(&description);
+        msg = msg.replace("<DESCRIPTION_HTML>", &format!("<br>{}", description));
+        msg = msg.replace("<DESCRIPTION_MARKDOWN>", &format!("  \\n{}", description));
+        msg = msg.replace("<DESCRIPTION>", &format!("\\n{}", description));
+    }
+    msg = msg.replace("<CAMERA_NAME>", &truncate(&bvr_msg.camera_name, limit));
     msg = msg.replace("<TIME>", &bvr_msg.time);
-    msg = msg.replace("<DETECTIONS>", &bvr_msg.detections);
-    msg = msg.replace("<ENDPOINT_URL>",
-                      &format!("{}/ui3.htm?rec={}&cam={}&m=1",
-                               alert_endpoint,
-                               bvr_msg.db_id,
-                               bvr_msg.camera_name));
+    msg = msg.replace("<DETECTIONS>", &truncate(&bvr_msg.detections, limit));
+    // Detections counted per object type are listed one type per line
+    let count_lines = bvr_msg.count_lines();
+    if count_lines.is_empty() {
+        let count = json_escape(&truncate(&bvr_msg.count_summary(), limit));
+        msg = msg.replace("<COUNT_HTML>", &count);
+        msg = msg.replace("<COUNT_MARKDOWN>", &count);
+        msg = msg.replace("<COUNT>", &count);
+    } else {
+        msg = msg.replace("<COUNT_HTML>", &json_escape(&count_lines.join("<br>")));
+        msg = msg.replace("<COUNT_MARKDOWN>", &json_escape(&format!("  \n{}", count_lines.join("  \n"))));
+        msg = msg.replace("<COUNT>", &json_escape(&format!("\n{}", count_lines.join("\n"))));
+    }
+    msg = msg.replace("<DAILY_COUNT>", &bvr_msg.daily_count.map(|count| count.to_string()).unwrap_or_default());
+    let details = bvr_msg.detail_lines();
+    if details.is_empty() {
+        msg = msg.replace("<DETAILS_HTML>", "");
+    } else {
+        // Labels come from the payload, a quote or `<` in one mustn't break the JSON or the HTML
+        let details: Vec<String> = details.iter().map(|detail| json_escape(&htmlescape::encode_minimal(detail))).collect();
+        msg = msg.replace("<DETAILS_HTML>", &format!("<br><br><details><summary>{}</summary>{}</details>",
+                                                    json_escape(&cfg.label("details_label")), details.join("<br>")));
+    }
+    match cfg.map_url(&bvr_msg.camera_name) {
+        Some(map_url) => {
+            let map_label = json_escape(&cfg.label("map_label"));
+            msg = msg.replace("<MAP_LINK_HTML>", &format!("<br><br><a href=\\\"{}\\\">{}</a>", map_url, map_label));
+            msg = msg.replace("<MAP_LINK_MARKDOWN>", &format!("\\n\\n[{}]({})", map_label, map_url));
+            msg = msg.replace("<MAP_LINK>", &format!("\\n{}: {}", map_label, map_url));
+        }
+        None => {
+            msg = msg.replace("<MAP_LINK_HTML>", "");
+            msg = msg.replace("<MAP_LINK_MARKDOWN>", "");
+            msg = msg.replace("<MAP_LINK>", "");
+        }
+    }
+    match cfg.live_url(&bvr_msg.camera_name) {
+        Some(live_url) => {
+            let live_url = json_escape(&live_url);
+            let live_label = json_escape(&cfg.label("live_label"));
+            msg = msg.replace("<LIVE_LINK_HTML>", &format!("<br><br><a href=\\\"{}\\\">{}</a>", live_url, live_label));
+            msg = msg.replace("<LIVE_LINK_MARKDOWN>", &format!("\\n\\n[{}]({})", live_label, live_url));
+            msg = msg.replace("<LIVE_LINK>", &format!("\\n{}: {}", live_label, live_url));
+        }
+        None => {
+            msg = msg.replace("<LIVE_LINK_HTML>", "");
+            msg = msg.replace("<LIVE_LINK_MARKDOWN>", "");
+            msg = msg.replace("<LIVE_LINK>", "");
+        }
+    }
+    msg = msg.replace("<ENDPOINT_URL>", &cfg.alert_link(bvr_msg));
     msg
 }
+
+/// Classifies a Matrix SDK error by the HTTP status the homeserver answered with
+///
+/// # Returns
+/// * `Auth` for a rejected access token or missing permissions, which retrying won't fix
+/// * `Transient` for everything else, like a timeout or the homeserver being down
+fn classify_error(err: &matrix_sdk::Error) -> BvrChirpError {
+    match err.as_client_api_error().map(|api_error| api_error.status_code.as_u16()) {
+        Some(401) | Some(403) => BvrChirpError::Auth(err.to_string()),
+        _ => BvrChirpError::Transient(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvr_chirp_message::DetectionDetail;
+
+    #[test]
+    fn build_message_escapes_detail_labels() {
+        let cfg = BvrChirpConfig::default();
+        let mut bvr_msg = BvrChirpMessage::new(String::new(), "Front".to_string(), "person".to_string(),
+                                               "A1".to_string(), String::new(), Vec::new());
+        bvr_msg.detections_detail = vec![DetectionDetail { label: r#"a "b" \ <c>"#.to_string(), confidence: Some(90.0) }];
+
+        let json: Value = serde_json::from_str(&build_message(&cfg, "mxc://example.org/A1", "Front.jpg", &bvr_msg))
+            .expect("rendered template is valid JSON");
+        let formatted_body = json["formatted_body"].as_str().unwrap_or_default();
+        assert!(formatted_body.contains(r#"a &quot;b&quot; \ &lt;c&gt; (90%)"#), "{}", formatted_body);
+    }
+}
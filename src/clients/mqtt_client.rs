@@ -1,80 +1,446 @@
-use std::str;
+use std::{str, thread};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Read;
+use std::sync::Arc;
 // TODO: Optional config between v3 and v5 for MQTT
-use rumqttc::v5::{MqttOptions, Client, Event, Incoming};
-use rumqttc::v5::mqttbytes::QoS;
-use std::time::Duration;
+use rumqttc::v5::{MqttOptions, Client, ConnectionError, Event, Incoming};
+use rumqttc::v5::mqttbytes::{valid_filter, QoS};
+use rumqttc::v5::mqttbytes::v5::{ConnectReturnCode, SubscribeReasonCode};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
-use crossbeam_channel::Sender;
+use crossbeam_channel::{Receiver, RecvTimeoutError, SendError, Sender, TrySendError};
+use flate2::read::GzDecoder;
+use hmac::{Hmac, Mac};
+use rand::Rng;
 use serde_json::{Value};
-use crate::bvr_chirp_config::MqttConfig;
-use crate::bvr_chirp_message::BvrChirpMessage;
+use sha2::Sha256;
+use crate::bvr_chirp_config::{BvrChirpConfig, ImageErrorAction, MqttConfig, PayloadEncoding, QueueFullPolicy, OVERLAY_DEFAULT_COLOR};
+use crate::backoff::Backoff;
+use crate::bvr_chirp_error::{self, check_status, BvrChirpError};
+use crate::blue_iris::RecordingVerifier;
+use crate::delivery_stats::{self, Outcome};
+use crate::bvr_chirp_message::{decode_audio, decode_image, parse_confidence, parse_detections, parse_detections_detail, parse_object_counts, BvrChirpMessage};
+use crate::{acknowledgements, address, burst_capture, cooldowns, daily_counts, daily_summary, dead_letter, false_positives, image_processing, maintenance, motion_delta,
+            multi_cam};
+use log::{error, info, warn};
 
+/// How far apart an image on the binary image topic and its metadata may arrive to be paired
+const BINARY_IMAGE_PAIRING_WINDOW: Duration = Duration::from_secs(10);
+/// Longest wait for a service to finish with an alert before the next service gets it anyway
+const ORDERED_DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long the `--test-mqtt` self-test waits for its message to come back
+const SELF_TEST_TIMEOUT: Duration = Duration::from_secs(15);
+/// Longest string value shown in full by pass-through mode, longer ones (ie: images) are summarized
+const PASSTHROUGH_MAX_VALUE_LEN: usize = 200;
+/// Longest pass-through message, Discord messages are limited to 2000 characters
+const PASSTHROUGH_MAX_LEN: usize = 1900;
+/// Most alert IDs remembered by `dedup_by_db_id`, the oldest are forgotten first
+const DEDUP_MAX_IDS: usize = 1000;
+/// How long fetching broker credentials from `credential_url` may take
+const CREDENTIAL_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Signed payloads whose `signed_at` is further than this from the clock are dropped as replays
+const MAX_SIGNATURE_AGE_SECS: u64 = 300;
+
+#[derive(Clone)]
 pub struct TxClient {
     pub name: String,
     pub tx: Sender<BvrChirpMessage>,
+    /// Wait until the client is done with an alert before passing it to the next client
+    pub wait_for_delivery: bool,
+    /// Receiving end of the same channel, used to drop the oldest alert when it's full
+    oldest: Option<Receiver<BvrChirpMessage>>,
+    /// Config the dropped alerts are reported to `dead_letter` with
+    cfg: Arc<BvrChirpConfig>,
+}
+
+impl TxClient {
+    /// Creates the channel a messaging client receives its alerts on
+    ///
+    /// The channel holds at most `max_queued_alerts` messages, or any number when it's 0,
+    /// so a slow service can't keep piling up images in memory.
+    ///
+    /// # Arguments
+    /// * `name` - Name of the messaging service, used in logs
+    /// * `cfg` - BvrChirpConfig with the queue size and what to do when it's full
+    ///
+    /// # Returns
+    /// * The sending end for the MQTT client and the receiving end for the messaging client
+    pub fn channel(name: &str, cfg: &BvrChirpConfig) -> (TxClient, Receiver<BvrChirpMessage>) {
+        let config = &cfg.mqtt_config;
+        let (tx, rx) = if config.max_queued_alerts == 0 {
+            crossbeam_channel::unbounded()
+        } else {
+            crossbeam_channel::bounded(config.max_queued_alerts)
+        };
+
+        let oldest = (config.max_queued_alerts != 0 && config.queue_full_policy == QueueFullPolicy::DropOldest)
+            .then(|| rx.clone());
+
+        (TxClient { name: name.to_string(), tx, wait_for_delivery: false, oldest, cfg: Arc::new(cfg.clone()) }, rx)
+    }
+
+    /// Queues a message for the messaging client
+    ///
+    /// When the queue is full this either waits for room or drops the oldest queued
+    /// message, depending on `queue_full_policy`. A dropped alert counts as not delivered
+    /// by the service, for `dead_letter` and whoever waits for the service to handle it.
+    ///
+    /// # Returns
+    /// * `Err` if the messaging client has stopped
+    pub fn send(&self, message: BvrChirpMessage) -> Result<(), Box<SendError<BvrChirpMessage>>> {
+        let mut message = match self.tx.try_send(message) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Disconnected(message)) => return Err(Box::new(SendError(message))),
+            Err(TrySendError::Full(message)) => message,
+        };
+
+        let oldest = match &self.oldest {
+            Some(oldest) => oldest,
+            None => {
+                warn!("MQTT: {} has {} alerts queued, waiting for it to catch up", self.name, self.tx.len());
+                return self.tx.send(message).map_err(Box::new);
+            }
+        };
+
+        loop {
+            if let Ok(dropped) = oldest.try_recv() {
+                warn!("MQTT: {} queue is full, dropped the oldest alert from {} ({})",
+                          self.name, dropped.camera_name, dropped.db_id);
+                delivery_stats::record(&self.name.to_uppercase(), Outcome::Dropped);
+                if dropped.system_text.is_none() {
+                    dead_letter::report(&self.cfg, &dropped, false);
+                }
+            }
+
+            message = match self.tx.try_send(message) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Disconnected(message)) => return Err(Box::new(SendError(message))),
+                Err(TrySendError::Full(message)) => message,
+            };
+        }
+    }
 }
 
 /// Initializes and runs the MQTT client, processing incoming messages and
 /// sending them through a channel after decoding and validation.
 ///
 /// # Arguments
-/// * `config` - Configuration options for the MQTT client, including host, port, credentials, and topic.
+/// * `cfg` - Configuration containing the MQTT client options (host, port, credentials, and topic) and message processing options.
 /// * `tx` - A channel sender to pass processed `BvrMessage` instances to other parts of the application.
 ///
 /// # Workflow
 /// - Configures the MQTT client with provided options.
 /// - Subscribes to the specified MQTT topic.
+/// - Subscribes to the binary image topic when configured, pairing raw images with the metadata messages.
+/// - Subscribes to the arm state topic when configured, suppressing alerts while the system is disarmed.
 /// - Listens for incoming MQTT messages, processes them by extracting necessary fields from the payload, and sends the processed message through a channel.
 ///
 /// # Error Handling
 /// - Logs and continues on failure to convert the payload to a string, parse JSON, or extract fields.
-/// - Logs and skips processing if decoding the base64 image fails.
-/// - Stops processing further messages if a critical error occurs in receiving an MQTT event.
-pub fn run(config: MqttConfig, tx_clients: Vec<TxClient>) {
+/// - Logs and skips processing if decoding the base64 image fails, or sends the alert without the image when `on_image_error` is `text_only`.
+/// - Reconnects with a jittered exponential backoff if an error occurs in receiving an MQTT event.
+/// - Fetches new credentials from `credential_url` before reconnecting when the broker refuses them.
+/// - Stops when the broker refuses the credentials and there are no new ones to try.
+pub fn run(cfg: BvrChirpConfig, tx_clients: Vec<TxClient>) {
+    let config = cfg.mqtt_config.clone();
+    let started = Instant::now();
+    let startup_grace = Duration::from_secs(config.startup_grace_secs);
+
+    // A random suffix keeps instances sharing a device_id from taking over each other's session
+    let client_id = if config.randomize_client_id {
+        format!("{}-{:06x}", config.device_id, rand::thread_rng().gen_range(0..0x1000000))
+    } else {
+        config.device_id.clone()
+    };
+    info!("MQTT: Using client ID '{}'", client_id);
+
     // Define MQTT options
-    let mut mqttoptions = MqttOptions::new(config.device_id, config.host, config.port);
-    mqttoptions.set_credentials(config.username, config.password);
+    let mut mqttoptions = MqttOptions::new(client_id, address::mqtt_host(&config.host), config.port);
+    let (username, password) = connect_credentials(&config);
+    mqttoptions.set_credentials(username, password);
     mqttoptions.set_keep_alive(Duration::from_secs(5));
 
-    let max_packet: Option<u32> = u32::try_from(2048000).ok();
-    mqttoptions.set_max_packet_size(max_packet);
+    mqttoptions.set_max_packet_size(Some(config.max_packet_size));
 
     // Create an MQTT client and connection
-    let (client, mut connection) = Client::new(mqttoptions, 10);
-    eprintln!("MQTT: Client connected");
+    let (client, mut connection) = Client::new(mqttoptions, config.mqtt_channel_cap);
+    info!("MQTT: Client connected");
 
     // Subscribe to a topic
-    client.subscribe(config.topic.clone(), QoS::AtMostOnce).unwrap();
-    eprintln!("MQTT: Successfully subscribed to topic='{}'", config.topic.as_str());
+    if !subscribe(&client, &config.topic, "topic") {
+        return;
+    }
+
+    // Images published as raw bytes on their own topic, paired with the metadata on `topic`
+    let binary_image_topic = Some(config.binary_image_topic.clone()).filter(|topic| !topic.is_empty());
+    if let Some(binary_image_topic) = &binary_image_topic {
+        if !subscribe(&client, binary_image_topic, "binary image topic") {
+            return;
+        }
+    }
+    let mut latest_image: Option<(Vec<u8>, Instant)> = None;
+    let mut pending_message: Option<(BvrChirpMessage, Instant)> = None;
+
+    // Alarm panel state, alerts are sent as if armed until the first state arrives
+    let arm_state_topic = Some(config.arm_state_topic.clone()).filter(|topic| !topic.is_empty());
+    if let Some(arm_state_topic) = &arm_state_topic {
+        if !subscribe(&client, arm_state_topic, "arm state topic") {
+            return;
+        }
+    }
+    let mut armed = true;
+
+    // Alert IDs seen recently, a publisher retrying sends the same recording again
+    let mut seen_ids = RecentIds::new(DEDUP_MAX_IDS, Duration::from_secs(config.dedup_window_secs));
+
+    // Commands muting a camera while it's being worked on
+    let maintenance_topic = Some(config.maintenance_topic.clone()).filter(|topic| !topic.is_empty());
+    if let Some(maintenance_topic) = &maintenance_topic {
+        if !subscribe(&client, maintenance_topic, "maintenance topic") {
+            return;
+        }
+    }
+
+    // Pass-through mode shows every payload as is on one service instead of sending alerts
+    let passthrough_client = if config.passthrough_mode {
+        let client = tx_clients.iter().find(|client| client.name.eq_ignore_ascii_case(&config.passthrough_service));
+        match client {
+            Some(client) => info!("MQTT: Pass-through mode, forwarding every payload to {}", client.name),
+            None => warn!("MQTT: Pass-through mode, but passthrough_service '{}' is not an enabled service",
+                          config.passthrough_service),
+        }
+        client.cloned()
+    } else {
+        None
+    };
+
+    let mut backoff = Backoff::new(&cfg.backoff);
+    let mut reconnecting = false;
+
+    let send_delay = Duration::from_millis(cfg.alert_send_delay_ms);
+
+    // Alerts end up with the messaging clients, going through the enabled buffering stages first.
+    // Burst capture forwards the sharpest frame of an event, multi-camera grouping collapses
+    // alerts from several cameras into one. The images are edited once these have picked the alerts.
+    let verbose = config.verbose_mqtt_logs;
+    let recording_verifier = if cfg.verify_recording {
+        match RecordingVerifier::new(&cfg) {
+            Ok(verifier) => Some(Arc::new(verifier)),
+            Err(e) => {
+                error!("MQTT: Failed to set up recording verification, sending alerts without it: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let verify_timeout = Duration::from_secs(cfg.verify_recording_timeout_secs);
+    // The clients move into the dispatch closure, topic routing only needs their names
+    let enabled_services: Vec<String> = tx_clients.iter().map(|client| client.name.clone()).collect();
+    let image_cfg = cfg.clone();
+    let mut next: Box<dyn Fn(BvrChirpMessage) + Send> = Box::new(move |mut message| {
+        edit_images(&image_cfg, &mut message);
+        match &recording_verifier {
+            // Polling Blue Iris takes a while, so it happens on its own thread like the send delay
+            Some(verifier) if !message.db_id.is_empty() => {
+                let verifier = verifier.clone();
+                let tx_clients = tx_clients.clone();
+                thread::spawn(move || {
+                    if !verifier.wait_for_recording(&message, verify_timeout) {
+                        warn!("MQTT: Blue Iris doesn't list recording {} after {}s, sending the alert anyway",
+                              message.db_id, verify_timeout.as_secs());
+                    }
+                    dispatch_delayed(message, &tx_clients, verbose, send_delay);
+                });
+            }
+            _ => dispatch_delayed(message, &tx_clients, verbose, send_delay),
+        }
+    });
+    if cfg.multi_cam_window_ms > 0 {
+        let multi_cam_tx = multi_cam::spawn(Duration::from_millis(cfg.multi_cam_window_ms), verbose, next);
+        next = Box::new(move |message| {
+            if multi_cam_tx.send(message).is_err() {
+                error!("MQTT: Failed to send message through channel to multi-camera grouping");
+            }
+        });
+    }
+    if cfg.best_frame_window_ms > 0 {
+        let burst_tx = burst_capture::spawn(Duration::from_millis(cfg.best_frame_window_ms), verbose, next);
+        next = Box::new(move |message| {
+            if burst_tx.send(message).is_err() {
+                error!("MQTT: Failed to send message through channel to burst capture");
+            }
+        });
+    }
+
+    let forward = |mut message: BvrChirpMessage| {
+        // Lighting changes at night often trigger with a black frame that shows nothing
+        if cfg.is_blank_frame(&message.image) {
+            if cfg.blank_frame_action == ImageErrorAction::Drop {
+                info!("MQTT: Skipping blank frame from camera: {:?}", message.camera_name);
+                return;
+            }
+            info!("MQTT: Blank frame from camera {:?}, sending the alert without it", message.camera_name);
+            message.image = Vec::new();
+        }
+
+        next(message);
+    };
+
+    // Loop over incoming messages, the connection is needed in the loop to renew credentials
+    while let Ok(event) = connection.recv() {
+        // Metadata whose image never came is sent without it, keep-alive pings wake the loop to check
+        if pending_message.as_ref().is_some_and(|(_, received)| received.elapsed() > BINARY_IMAGE_PAIRING_WINDOW) {
+            if let Some((message, _)) = pending_message.take() {
+                warn!("MQTT: No image arrived for message from camera: {:?}, sending it without one", message.camera_name);
+                forward(message);
+            }
+        }
 
-    // Loop over incoming messages
-    for event in connection.iter() {
         match event {
+            Ok(Event::Incoming(Incoming::ConnAck(_))) => {
+                info!("MQTT: Connected to broker");
+                backoff.reset();
+
+                // The broker drops the subscription along with the session, so renew it after a reconnect
+                if reconnecting {
+                    if let Err(e) = client.try_subscribe(config.topic.clone(), QoS::AtMostOnce) {
+                        error!("MQTT: Failed to resubscribe to topic='{}': {}", config.topic.as_str(), e);
+                    }
+                    if let Some(binary_image_topic) = &binary_image_topic {
+                        if let Err(e) = client.try_subscribe(binary_image_topic.clone(), QoS::AtMostOnce) {
+                            error!("MQTT: Failed to resubscribe to binary image topic='{}': {}", binary_image_topic, e);
+                        }
+                    }
+                    if let Some(arm_state_topic) = &arm_state_topic {
+                        if let Err(e) = client.try_subscribe(arm_state_topic.clone(), QoS::AtMostOnce) {
+                            error!("MQTT: Failed to resubscribe to arm state topic='{}': {}", arm_state_topic, e);
+                        }
+                    }
+                    if let Some(maintenance_topic) = &maintenance_topic {
+                        if let Err(e) = client.try_subscribe(maintenance_topic.clone(), QoS::AtMostOnce) {
+                            error!("MQTT: Failed to resubscribe to maintenance topic='{}': {}", maintenance_topic, e);
+                        }
+                    }
+                    reconnecting = false;
+                }
+            }
             Ok(Event::Incoming(Incoming::Publish(publish))) => {
+                // The alarm panel's state is usually retained, so it's read even when skipping retained alerts
+                if let Some(arm_state_topic) = arm_state_topic.as_ref().filter(|topic| publish.topic.as_ref() == topic.as_bytes()) {
+                    // Otherwise anyone who can publish on the broker could disarm and silence every alert
+                    let state = match signed_command(&config.payload_hmac_secret, arm_state_topic, &publish.payload, "state") {
+                        Some(state) => state.trim().to_lowercase(),
+                        None => {
+                            warn!("MQTT: Dropping arm state with a missing or invalid signature");
+                            continue;
+                        }
+                    };
+                    let now_armed = state != "disarmed";
+                    if now_armed != armed {
+                        info!("MQTT: System {} ('{}')", if now_armed { "armed, sending alerts" } else { "disarmed, suppressing alerts" }, state);
+                    }
+                    armed = now_armed;
+                    continue;
+                }
+
+                if let Some(maintenance_topic) = maintenance_topic.as_ref().filter(|topic| publish.topic.as_ref() == topic.as_bytes()) {
+                    match signed_command(&config.payload_hmac_secret, maintenance_topic, &publish.payload, "command") {
+                        Some(command) => match maintenance::handle_command(&command) {
+                            Ok(change) => info!("MQTT: {}", change),
+                            Err(e) => warn!("MQTT: {}", e),
+                        },
+                        None => warn!("MQTT: Dropping maintenance command with a missing or invalid signature"),
+                    }
+                    continue;
+                }
+
+                // Retained messages are replayed by the broker on subscribe, they're old alerts
+                if config.skip_retained && publish.retain {
+                    if config.verbose_mqtt_logs {
+                        info!("MQTT: Skipping retained message");
+                    }
+                    continue;
+                }
+
+                // Raw image bytes, pair them with metadata waiting for an image or keep them for the next metadata
+                if binary_image_topic.as_ref().is_some_and(|topic| publish.topic.as_ref() == topic.as_bytes()) {
+                    let image = if cfg.fix_image_orientation {
+                        image_processing::fix_orientation(publish.payload.to_vec())
+                    } else {
+                        publish.payload.to_vec()
+                    };
+
+                    match pending_message.take() {
+                        Some((mut message, received)) if received.elapsed() <= BINARY_IMAGE_PAIRING_WINDOW => {
+                            message.image = image;
+                            forward(message);
+                        }
+                        _ => latest_image = Some((image, Instant::now())),
+                    }
+                    continue;
+                }
+
+                // Test messages from `self_test`, possibly another instance's, aren't alerts.
+                // They're published as plain JSON whatever the payload_encoding.
+                if is_self_test(&publish.payload) {
+                    if config.verbose_mqtt_logs {
+                        info!("MQTT: Skipping self-test message");
+                    }
+                    continue;
+                }
+
+                // Decompress the payload if needed, log error, and continue on failure
+                let payload = match config.payload_encoding {
+                    PayloadEncoding::Plain => publish.payload.to_vec(),
+                    PayloadEncoding::Gzip => match gunzip(&publish.payload) {
+                        Ok(payload) => payload,
+                        Err(e) => {
+                            error!("MQTT: Failed to decompress gzip payload: {}", e);
+                            continue;
+                        }
+                    },
+                };
+
                 // Convert payload to string, log error, and continue on failure
-                let payload_str = match str::from_utf8(&publish.payload) {
+                let payload_str = match str::from_utf8(&payload) {
                     Ok(payload) => payload,
                     Err(_) => {
-                        eprintln!("MQTT: Failed to convert payload to string");
+                        error!("MQTT: Failed to convert payload to string");
                         continue;
                     }
                 };
 
+                if config.passthrough_mode {
+                    if let Some(client) = &passthrough_client {
+                        if client.send(BvrChirpMessage::system(passthrough_text(payload_str))).is_err() {
+                            error!("MQTT: Failed to send message through channel to {}", client.name);
+                        }
+                    }
+                    continue;
+                }
+
                 // Parse JSON, log error, and continue on failure
                 let payload_json: Value = match serde_json::from_str(payload_str) {
                     Ok(json) => json,
                     Err(_) => {
-                        eprintln!("MQTT: Failed to parse JSON");
+                        error!("MQTT: Failed to parse JSON");
                         continue;
                     }
                 };
 
+                // With topic_routing the topic can name the service and target instead of the JSON
+                let topic = String::from_utf8_lossy(&publish.topic);
+                let route = Some(config.topic_routing.as_str())
+                    .filter(|pattern| !pattern.is_empty())
+                    .and_then(|pattern| TopicRoute::parse(pattern, &topic));
+
                 // Extract required fields, log error, and continue on failure
-                let target = match payload_json["target"].as_str() {
+                let target = match route.as_ref().and_then(|route| route.target.as_deref()).or_else(|| payload_json["target"].as_str()) {
                     Some(target) => target,
                     None => {
-                        eprintln!("MQTT: Missing 'target' field in JSON");
+                        error!("MQTT: Missing 'target' field in JSON");
                         continue;
                     }
                 };
@@ -82,7 +448,7 @@ pub fn run(config: MqttConfig, tx_clients: Vec<TxClient>) {
                 let camera = match payload_json["camera"].as_str() {
                     Some(camera) => camera,
                     None => {
-                        eprintln!("MQTT: Missing 'camera' field in JSON");
+                        error!("MQTT: Missing 'camera' field in JSON");
                         continue;
                     }
                 };
@@ -90,7 +456,7 @@ pub fn run(config: MqttConfig, tx_clients: Vec<TxClient>) {
                 let detections = match payload_json["detections"].as_str() {
                     Some(detections) => detections,
                     None => {
-                        eprintln!("MQTT: Missing 'detections' field in JSON");
+                        error!("MQTT: Missing 'detections' field in JSON");
                         continue;
                     }
                 };
@@ -98,7 +464,7 @@ pub fn run(config: MqttConfig, tx_clients: Vec<TxClient>) {
                 let db_id = match payload_json["db_id"].as_str() {
                     Some(db_id) => db_id,
                     None => {
-                        eprintln!("MQTT: Missing 'db_id' field in JSON");
+                        error!("MQTT: Missing 'db_id' field in JSON");
                         continue;
                     }
                 };
@@ -106,53 +472,1052 @@ pub fn run(config: MqttConfig, tx_clients: Vec<TxClient>) {
                 let time = match payload_json["time"].as_str() {
                     Some(time) => time,
                     None => {
-                        eprintln!("MQTT: Missing 'time' field in JSON");
+                        error!("MQTT: Missing 'time' field in JSON");
                         continue;
                     }
                 };
 
-                let image_base64 = match payload_json["image"].as_str() {
-                    Some(image_base64) => image_base64,
-                    None => {
-                        eprintln!("MQTT: Missing 'image' field in JSON");
-                        continue;
+                // Without a binary image topic the image must be part of the metadata,
+                // unless the alert is an audio clip on its own
+                let image_base64 = payload_json["image"].as_str();
+                let audio_base64 = payload_json["audio"].as_str();
+                if image_base64.is_none() && audio_base64.is_none() && binary_image_topic.is_none() {
+                    error!("MQTT: Missing 'image' field in JSON");
+                    continue;
+                }
+
+                // Anyone who can publish on a shared broker could otherwise inject alerts, or redirect
+                // a signed one by republishing it on another topic
+                if !config.payload_hmac_secret.is_empty()
+                    && !verify_payload_signature(&config.payload_hmac_secret, &topic, &payload_json, unix_now()) {
+                    warn!("MQTT: Dropping message for camera {:?} with a missing or invalid signature", camera);
+                    continue;
+                }
+
+                // A publisher retry is the same recording, it was already alerted on
+                if config.dedup_by_db_id && !seen_ids.insert(db_id) {
+                    if config.verbose_mqtt_logs {
+                        info!("MQTT: Skipping repeated message with db_id {:?} from camera: {:?}", db_id, camera);
                     }
-                };
+                    continue;
+                }
 
-                eprintln!("MQTT: Received message for camera: {:?}", camera);
+                // Messages right after startup are often a backlog that isn't current anymore
+                if started.elapsed() < startup_grace {
+                    info!("MQTT: Not sending message from camera {:?} received during the startup grace period", camera);
+                    continue;
+                }
 
-                // Decode the image from base64, log error, and continue on failure
-                let image = match BASE64_STANDARD.decode(image_base64) {
-                    Ok(image) => image,
-                    Err(_) => {
-                        eprintln!("MQTT: Failed to decode base64 image");
-                        continue;
+                if config.verbose_mqtt_logs {
+                    info!("MQTT: Received message for camera: {:?}", camera);
+                }
+
+                // Motion triggers without AI classification arrive with no detections
+                if config.suppress_empty_detections && detections.trim().is_empty() {
+                    if config.verbose_mqtt_logs {
+                        info!("MQTT: Skipping message without detections from camera: {:?}", camera);
                     }
+                    continue;
+                }
+
+                // Someone acknowledged this camera's event recently, don't alert again yet
+                if acknowledgements::is_muted(camera) {
+                    if config.verbose_mqtt_logs {
+                        info!("MQTT: Skipping message for acknowledged camera: {:?}", camera);
+                    }
+                    continue;
+                }
+
+                // The camera is being worked on, its alerts are junk until maintenance ends
+                if maintenance::is_active(&cfg, camera) {
+                    if config.verbose_mqtt_logs {
+                        info!("MQTT: Skipping message for camera under maintenance: {:?}", camera);
+                    }
+                    continue;
+                }
+
+                let image = match image_base64 {
+                    // Decode the image from base64 or a data URI, on failure drop the alert or send it without the image
+                    Some(image_base64) => match decode_image(image_base64, config.base64_variant) {
+                        // Rotate sideways snapshots upright before any client uploads them
+                        Ok(image) if cfg.fix_image_orientation => image_processing::fix_orientation(image),
+                        Ok(image) => image,
+                        Err(e) if config.on_image_error == ImageErrorAction::TextOnly => {
+                            warn!("MQTT: {}, sending alert from camera {:?} without it", e, camera);
+                            Vec::new()
+                        }
+                        Err(e) => {
+                            error!("MQTT: {}", e);
+                            continue;
+                        }
+                    },
+                    // Use the image that arrived on the binary image topic, or wait for it
+                    None => match latest_image.take() {
+                        Some((image, received)) if received.elapsed() <= BINARY_IMAGE_PAIRING_WINDOW => image,
+                        _ => Vec::new(),
+                    },
+                };
+
+                // Some publishers HTML encode the text, ie: "person &amp; dog"
+                let (camera, detections) = if config.decode_html_entities {
+                    (decode_html(camera), decode_html(detections))
+                } else {
+                    (camera.to_owned(), detections.to_owned())
                 };
 
                 // Create the message and send it through the channel, log error on failure
-                let message = BvrChirpMessage::new(
+                let mut message = BvrChirpMessage::new(
                     target.to_owned(),
-                    camera.to_owned(),
-                    detections.to_owned(),
+                    camera.clone(),
+                    detections,
                     db_id.to_owned(),
                     time.to_owned(),
                     image,
                 );
 
-                for client in &tx_clients {
-                    if client.tx.send(message.clone()).is_err() {
-                        eprintln!("MQTT: Failed to send message through channel to {}", client.name);
-                    } else {
-                        eprintln!("MQTT: Passed message to {}", client.name);
+                message.detection_list = parse_detections(&message.detections, &config.detection_separators);
+
+                // Test alerts go to the sandbox instead of the usual channels
+                let debug_flag = payload_json["debug"].as_bool()
+                    .unwrap_or_else(|| payload_json["debug"].as_str().is_some_and(|flag| flag.eq_ignore_ascii_case("true")));
+                if cfg.is_debug_alert(target, debug_flag) {
+                    if config.verbose_mqtt_logs {
+                        info!("MQTT: Routing test alert from camera {:?} to {}", camera, cfg.debug_target);
+                    }
+                    message.target = cfg.debug_target.clone();
+                    message.debug = true;
+                }
+
+                // Someone reading every channel of the target only needs the alert from one service
+                if let Some(named) = cfg.targets.get(&message.target) {
+                    message.primary_services = named.primary_services.clone();
+                }
+
+                // The topic named the one service the alert goes to
+                if let Some(service) = route.and_then(|route| route.service) {
+                    if !enabled_services.iter().any(|name| name.eq_ignore_ascii_case(&service)) {
+                        warn!("MQTT: Skipping message from camera {:?}, the topic names '{}' which is not an enabled service",
+                              camera, service);
+                        continue;
                     }
+                    message.service = Some(service);
                 }
+
+                // Optional URL of the snapshot as hosted by the NVR
+                message.image_url = payload_json["image_url"].as_str().map(|url| url.to_owned());
+
+                // Optional audio clip, ie: a doorbell press or glass breaking, the alert still goes out without it
+                message.audio = audio_base64.and_then(|audio| match decode_audio(audio, config.base64_variant) {
+                    Ok(audio) => Some(audio),
+                    Err(e) => {
+                        warn!("MQTT: {}, sending alert from camera {:?} without it", e, camera);
+                        None
+                    }
+                });
+
+                // Optional number of objects detected, sent as a number or a numeric string
+                message.object_count = payload_json["object_count"].as_u64()
+                    .or_else(|| payload_json["object_count"].as_str().and_then(|count| count.trim().parse().ok()));
+
+                // Optional counts per object type, ie: {"person": 2, "car": 1}, also accepted as `object_count`
+                message.object_counts = match parse_object_counts(&payload_json["object_counts"]) {
+                    object_counts if object_counts.is_empty() => parse_object_counts(&payload_json["object_count"]),
+                    object_counts => object_counts,
+                };
+
+                // Optional breakdown of the individual objects and their confidence
+                message.detections_detail = parse_detections_detail(&payload_json["detections_detail"]);
+
+                // Optional confidence of the detection, noisy cameras can ask for more than the others
+                message.confidence = parse_confidence(&payload_json["confidence"]);
+                if let Some((threshold, confidence)) = below_confidence_threshold(&cfg.camera_confidence_thresholds, &message) {
+                    if config.verbose_mqtt_logs {
+                        info!("MQTT: Skipping message below the {}% confidence threshold ({:.0}%) from camera: {:?}",
+                              threshold, confidence, camera);
+                    }
+                    continue;
+                }
+
+                // Disarmed (ie: someone's home), only detections that matter even then get through
+                if !armed && !allowed_while_disarmed(&message, &config.disarmed_detections) {
+                    if config.verbose_mqtt_logs {
+                        info!("MQTT: Skipping message while disarmed from camera: {:?}", camera);
+                    }
+                    continue;
+                }
+
+                // Someone marked this camera and detections combination as a false positive
+                if false_positives::is_suppressed(&message) {
+                    if config.verbose_mqtt_logs {
+                        info!("MQTT: Skipping false positive from camera: {:?}", camera);
+                    }
+                    continue;
+                }
+
+                // The camera alerted recently, unless this is a new kind of detection
+                if !cooldowns::allow(&message, Duration::from_secs(config.camera_cooldown_secs), config.cooldown_break_on_new_type) {
+                    if config.verbose_mqtt_logs {
+                        info!("MQTT: Skipping message during cooldown from camera: {:?}", camera);
+                    }
+                    continue;
+                }
+
+                // The image hasn't arrived on the binary image topic yet, send once it does
+                if message.image.is_empty() && image_base64.is_none() && binary_image_topic.is_some() {
+                    if let Some((waiting, _)) = pending_message.replace((message, Instant::now())) {
+                        warn!("MQTT: No image arrived for message from camera: {:?}, sending it without one", waiting.camera_name);
+                        forward(waiting);
+                    }
+                    continue;
+                }
+
+                forward(message);
             }
             Err(e) => {
-                eprintln!("MQTT: Error parsing message: {}", e);
-                break;
+                // Reconnecting with refused credentials can't work, only new ones from credential_url can
+                let error = connection_error(&e);
+                if !error.is_retryable() {
+                    if config.credential_url.is_empty() {
+                        error!("MQTT: {}. Stopping, fix the credentials and restart", error);
+                        return;
+                    }
+                    match fetch_credentials(&config.credential_url, &config.credential_token) {
+                        Ok((username, password)) => {
+                            info!("MQTT: Fetched new credentials from credential_url");
+                            connection.eventloop.options.set_credentials(username, password);
+                        }
+                        Err(fetch_error) if !fetch_error.is_retryable() => {
+                            error!("MQTT: {}. Stopping, credential_url refused the request: {}", error, fetch_error);
+                            return;
+                        }
+                        Err(fetch_error) => error!("MQTT: Failed to fetch new credentials, retrying with the current ones: {}", fetch_error),
+                    }
+                }
+
+                let delay = backoff.next_delay();
+                error!("MQTT: Connection error: {}. Reconnecting in {:.1}s", e, delay.as_secs_f32());
+                thread::sleep(delay);
+                reconnecting = true;
             }
             _ => {}
         }
     }
 }
+
+/// Checks that messages published on the alert topic reach the bot
+///
+/// Connects with its own client ID, subscribes to `topic`, publishes a test message
+/// to it and waits for the message to come back. Other subscribers of the topic,
+/// like another running instance, also receive the test message and skip it.
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the MQTT broker, credentials and topic
+///
+/// # Returns
+/// * `true` if the test message came back within the timeout
+pub fn self_test(cfg: &BvrChirpConfig) -> bool {
+    let config = &cfg.mqtt_config;
+    let nonce = format!("{:016x}", rand::thread_rng().gen::<u64>());
+
+    let mut mqttoptions = MqttOptions::new(format!("{}-self-test-{}", config.device_id, &nonce[..6]),
+                                           address::mqtt_host(&config.host), config.port);
+    let (username, password) = connect_credentials(config);
+    mqttoptions.set_credentials(username, password);
+    mqttoptions.set_keep_alive(Duration::from_secs(5));
+    mqttoptions.set_max_packet_size(Some(config.max_packet_size));
+
+    let (client, mut connection) = Client::new(mqttoptions, config.mqtt_channel_cap);
+    if let Err(e) = client.subscribe(config.topic.clone(), QoS::AtLeastOnce) {
+        error!("MQTT: Self-test failed to subscribe to topic='{}': {}", config.topic, e);
+        return false;
+    }
+
+    let payload = serde_json::json!({ "bvr_chirp_self_test": nonce }).to_string();
+    let publish_topic = self_test_topic(&config.topic);
+    let started = Instant::now();
+    let mut published_at = None;
+
+    loop {
+        let remaining = SELF_TEST_TIMEOUT.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            match published_at {
+                Some(_) => error!("MQTT: Self-test message didn't come back within {}s, check the broker's ACL for topic='{}'",
+                                  SELF_TEST_TIMEOUT.as_secs(), config.topic),
+                None => error!("MQTT: Self-test couldn't subscribe within {}s", SELF_TEST_TIMEOUT.as_secs()),
+            }
+            return false;
+        }
+
+        let event = match connection.recv_timeout(remaining) {
+            Ok(event) => event,
+            // The timeout is reported on the next pass
+            Err(_) => continue,
+        };
+
+        match event {
+            Ok(Event::Incoming(Incoming::ConnAck(_))) => info!("MQTT: Self-test connected to broker"),
+            Ok(Event::Incoming(Incoming::SubAck(suback))) => {
+                if !suback.return_codes.iter().all(|code| matches!(code, SubscribeReasonCode::Success(_))) {
+                    error!("MQTT: Self-test subscription to topic='{}' was refused: {:?}", config.topic, suback.return_codes);
+                    return false;
+                }
+                info!("MQTT: Self-test subscribed to topic='{}', publishing a test message to topic='{}'", config.topic, publish_topic);
+
+                if let Err(e) = client.publish(publish_topic.clone(), QoS::AtLeastOnce, false, payload.clone()) {
+                    error!("MQTT: Self-test failed to publish: {}", e);
+                    return false;
+                }
+                published_at = Some(Instant::now());
+            }
+            Ok(Event::Incoming(Incoming::Publish(publish))) if publish.payload.as_ref() == payload.as_bytes() => {
+                let round_trip = published_at.map(|published_at| published_at.elapsed().as_millis()).unwrap_or_default();
+                info!("MQTT: Self-test passed, the test message came back in {}ms", round_trip);
+                let _ = client.disconnect();
+                return true;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                error!("MQTT: Self-test failed to connect: {}", e);
+                return false;
+            }
+        }
+    }
+}
+
+/// Returns true if the raw payload is a test message published by `self_test`
+fn is_self_test(payload: &[u8]) -> bool {
+    // Only payloads naming the key are parsed, so alerts aren't parsed twice
+    str::from_utf8(payload).is_ok_and(|payload| payload.contains("bvr_chirp_self_test"))
+        && serde_json::from_slice::<Value>(payload)
+            .map(|json| json.get("bvr_chirp_self_test").is_some())
+            .unwrap_or(false)
+}
+
+/// Topic the self-test publishes to, the alert topic with its wildcards filled in
+///
+/// Messages can't be published to a wildcard, so each `+` or `#` level becomes
+/// `bvr_chirp_self_test`, a topic the subscription still matches.
+///
+/// # Arguments
+/// * `topic` - The alert topic, ie: "BlueIris/+/alert"
+fn self_test_topic(topic: &str) -> String {
+    topic.split('/')
+        .map(|level| if level == "+" || level == "#" { "bvr_chirp_self_test" } else { level })
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
+/// Passes a message to every enabled messaging client
+///
+/// # Arguments
+/// * `message` - The message to send
+/// * `tx_clients` - Channel senders of the messaging clients
+/// * `verbose` - Log each successful hand-off
+fn dispatch(message: &BvrChirpMessage, tx_clients: &[TxClient], verbose: bool) {
+    // An alert routed by its topic only goes to the service the topic named, and only it reports the alert
+    let routed: Vec<TxClient>;
+    let routed_message: BvrChirpMessage;
+    let (message, tx_clients) = match &message.service {
+        Some(service) => {
+            routed = tx_clients.iter().filter(|client| client.name.eq_ignore_ascii_case(service)).cloned().collect();
+            let mut copy = message.clone();
+            copy.expected_reports = Some(routed.len());
+            routed_message = copy;
+            (&routed_message, routed.as_slice())
+        }
+        None => (message, tx_clients),
+    };
+
+    // The target's primary services are waited for on another thread too
+    if !message.primary_services.is_empty() {
+        let message = message.clone();
+        let tx_clients = tx_clients.to_vec();
+        thread::spawn(move || dispatch_to_primary(&message, &tx_clients, verbose));
+        return;
+    }
+
+    // Clients that must finish first are waited for on another thread, keeping MQTT serviced
+    if tx_clients.iter().any(|client| client.wait_for_delivery) {
+        let message = message.clone();
+        let tx_clients = tx_clients.to_vec();
+        thread::spawn(move || dispatch_in_order(&message, &tx_clients, verbose));
+        return;
+    }
+
+    for client in tx_clients {
+        if client.send(message.clone()).is_err() {
+            error!("MQTT: Failed to send message through channel to {}", client.name);
+        } else if verbose {
+            info!("MQTT: Passed message to {}", client.name);
+        }
+    }
+}
+
+/// Text of a message on the maintenance or arm state topic
+///
+/// With `payload_hmac_secret` the message must be signed like an alert, as JSON with the
+/// text in `field` and the `signed_at` and `signature` fields, so only the publishers can
+/// mute cameras or disarm the system.
+///
+/// # Arguments
+/// * `secret` - `payload_hmac_secret`, messages are taken as plain text when empty
+/// * `topic` - The topic the message was published on
+/// * `payload` - Payload of the message
+/// * `field` - Field of the signed JSON holding the text, ie: "command"
+///
+/// # Returns
+/// * `None` if the message has to be signed and isn't, or its signature is invalid
+fn signed_command(secret: &str, topic: &str, payload: &[u8], field: &str) -> Option<String> {
+    let payload = String::from_utf8_lossy(payload);
+    if secret.is_empty() {
+        return Some(payload.into_owned());
+    }
+
+    let payload_json: Value = serde_json::from_str(&payload).ok()?;
+    if !verify_payload_signature(secret, topic, &payload_json, unix_now()) {
+        return None;
+    }
+    payload_json[field].as_str().map(|text| text.to_owned())
+}
+
+/// Seconds since the Unix epoch
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or(0)
+}
+
+/// Verifies the `signature` field of an alert payload
+///
+/// The signature is the hex encoded HMAC-SHA256 of `signed_content`, so the topic and
+/// every field but the signature itself are covered. The payload must carry `signed_at`, in seconds since
+/// the Unix epoch, within `MAX_SIGNATURE_AGE_SECS` of `now`, so a captured payload can't
+/// be replayed later.
+///
+/// # Arguments
+/// * `secret` - Secret shared with the publisher
+/// * `topic` - Topic the payload was published on
+/// * `payload_json` - The parsed alert payload
+/// * `now` - Current time in seconds since the Unix epoch
+///
+/// # Returns
+/// * `true` if the payload carries a valid and current signature
+fn verify_payload_signature(secret: &str, topic: &str, payload_json: &Value, now: u64) -> bool {
+    let signed_at = payload_json["signed_at"].as_u64()
+        .or_else(|| payload_json["signed_at"].as_str().and_then(|signed_at| signed_at.trim().parse().ok()));
+    if signed_at.is_none_or(|signed_at| now.abs_diff(signed_at) > MAX_SIGNATURE_AGE_SECS) {
+        return false;
+    }
+
+    let signature = match payload_json["signature"].as_str().and_then(|signature| hex::decode(signature.trim()).ok()) {
+        Some(signature) => signature,
+        None => return false,
+    };
+
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(signed_content(topic, payload_json).as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Canonical form of a payload that its signature covers
+///
+/// The topic as `<topic length>:<topic>`, then every top-level field except `signature`, ordered
+/// by name, as `<name length>:<name><value length>:<value>` with lengths in bytes. String values are taken as is, other values as compact JSON with the
+/// keys of objects sorted. The lengths keep text from moving between fields unnoticed.
+fn signed_content(topic: &str, payload_json: &Value) -> String {
+    let mut content = format!("{}:{}", topic.len(), topic);
+    // Objects keep their keys sorted, so the fields come out ordered by name
+    for (name, value) in payload_json.as_object().into_iter().flatten().filter(|(name, _)| name.as_str() != "signature") {
+        let value = match value {
+            Value::String(text) => text.clone(),
+            other => other.to_string(),
+        };
+        content.push_str(&format!("{}:{}{}:{}", name.len(), name, value.len(), value));
+    }
+    content
+}
+
+/// Formats a payload as a code block for pass-through mode
+///
+/// JSON is pretty printed with long string values, like base64 images, replaced by their
+/// length. Anything else is shown as received.
+///
+/// # Arguments
+/// * `payload` - The payload text
+///
+/// # Returns
+/// * The code block, truncated to fit in a chat message
+fn passthrough_text(payload: &str) -> String {
+    let text = match serde_json::from_str::<Value>(payload) {
+        Ok(mut json) => {
+            shorten_long_strings(&mut json);
+            serde_json::to_string_pretty(&json).unwrap_or_else(|_| payload.to_string())
+        }
+        Err(_) => payload.to_string(),
+    };
+
+    let text = if text.chars().count() > PASSTHROUGH_MAX_LEN {
+        format!("{}\n…", text.chars().take(PASSTHROUGH_MAX_LEN).collect::<String>())
+    } else {
+        text
+    };
+    format!("```\n{}\n```", text)
+}
+
+/// Replaces string values longer than `PASSTHROUGH_MAX_VALUE_LEN` with a summary of their length
+fn shorten_long_strings(json: &mut Value) {
+    match json {
+        Value::String(value) if value.chars().count() > PASSTHROUGH_MAX_VALUE_LEN => {
+            *value = format!("<{} characters>", value.chars().count());
+        }
+        Value::Array(values) => values.iter_mut().for_each(shorten_long_strings),
+        Value::Object(values) => values.values_mut().for_each(shorten_long_strings),
+        _ => {}
+    }
+}
+
+/// Passes a message to the clients one after another, in `service_priority` order
+///
+/// A client with `wait_for_delivery` gets to finish with the alert, or fail, before the
+/// next client has it. A client that takes longer than `ORDERED_DELIVERY_TIMEOUT` is
+/// no longer waited for.
+///
+/// # Arguments
+/// * `message` - The message to send
+/// * `tx_clients` - Channel senders of the messaging clients, highest priority first
+/// * `verbose` - Log each successful hand-off
+fn dispatch_in_order(message: &BvrChirpMessage, tx_clients: &[TxClient], verbose: bool) {
+    for client in tx_clients {
+        let mut message = message.clone();
+        let handled = if client.wait_for_delivery {
+            let (handled_tx, handled_rx) = crossbeam_channel::bounded(1);
+            message.handled = Some(handled_tx);
+            Some(handled_rx)
+        } else {
+            None
+        };
+
+        if client.send(message).is_err() {
+            error!("MQTT: Failed to send message through channel to {}", client.name);
+            continue;
+        } else if verbose {
+            info!("MQTT: Passed message to {}", client.name);
+        }
+
+        if let Some(handled) = handled {
+            if handled.recv_timeout(ORDERED_DELIVERY_TIMEOUT).is_err() {
+                warn!("MQTT: {} didn't finish with the alert within {}s, passing it on",
+                      client.name, ORDERED_DELIVERY_TIMEOUT.as_secs());
+            }
+        }
+    }
+}
+
+/// Passes a message to the first of its target's `primary_services`, and to the next
+/// only when the previous didn't deliver it
+///
+/// A primary service that takes longer than `ORDERED_DELIVERY_TIMEOUT` is still waited for,
+/// the next one only gets the alert once it's known the slow one didn't deliver it.
+/// Services that aren't listed get the message as usual, before the primary services.
+/// The primary services count as one service for `dead_letter`, whichever of them has
+/// the alert last reports its outcome.
+///
+/// # Arguments
+/// * `message` - The message to send, with the target's primary services
+/// * `tx_clients` - Channel senders of the messaging clients
+/// * `verbose` - Log each successful hand-off
+fn dispatch_to_primary(message: &BvrChirpMessage, tx_clients: &[TxClient], verbose: bool) {
+    let (primary, others): (Vec<TxClient>, Vec<TxClient>) = tx_clients.iter()
+        .cloned()
+        .partition(|client| message.primary_services.iter().any(|service| service.eq_ignore_ascii_case(&client.name)));
+    let primary: Vec<TxClient> = message.primary_services.iter()
+        .filter_map(|service| primary.iter().find(|client| service.eq_ignore_ascii_case(&client.name)).cloned())
+        .collect();
+
+    let mut message = message.clone();
+    message.expected_reports = Some(others.len() + usize::from(!primary.is_empty()));
+    dispatch_in_order(&message, &others, verbose);
+
+    for (index, client) in primary.iter().enumerate() {
+        let mut message = message.clone();
+        let (handled_tx, handled_rx) = crossbeam_channel::bounded(1);
+        message.handled = Some(handled_tx);
+        message.fallback_follows = index + 1 < primary.len();
+        if client.send(message).is_err() {
+            error!("MQTT: Failed to send message through channel to {}", client.name);
+            continue;
+        } else if verbose {
+            info!("MQTT: Passed message to primary service {}", client.name);
+        }
+
+        let delivered = match handled_rx.recv_timeout(ORDERED_DELIVERY_TIMEOUT) {
+            // Passing the alert on now would notify twice once the slow service delivers it after all
+            Err(RecvTimeoutError::Timeout) => {
+                warn!("MQTT: {} didn't finish with the alert within {}s, waiting for it instead of trying the next primary service",
+                      client.name, ORDERED_DELIVERY_TIMEOUT.as_secs());
+                handled_rx.recv().ok()
+            }
+            handled => handled.ok(),
+        };
+        match delivered {
+            Some(true) => return,
+            Some(false) => warn!("MQTT: Primary service {} didn't deliver the alert, trying the next one", client.name),
+            None => warn!("MQTT: Primary service {} stopped without reporting the alert, trying the next one", client.name),
+        }
+    }
+}
+
+/// Bounded set of recently seen alert IDs, forgetting the least recently seen first
+struct RecentIds {
+    capacity: usize,
+    window: Duration,
+    /// When each ID was last seen
+    seen: HashMap<String, Instant>,
+    /// IDs from least to most recently seen
+    order: VecDeque<String>,
+}
+
+impl RecentIds {
+    fn new(capacity: usize, window: Duration) -> Self {
+        Self { capacity, window, seen: HashMap::new(), order: VecDeque::new() }
+    }
+
+    /// Records an alert ID as seen now
+    ///
+    /// # Returns
+    /// * `false` if the ID was already seen within the window, `true` otherwise and for an empty ID
+    fn insert(&mut self, id: &str) -> bool {
+        if id.is_empty() {
+            return true;
+        }
+
+        let repeated = self.seen.get(id).is_some_and(|seen| seen.elapsed() < self.window);
+        // Moving a repeated ID to the back is linear in the capacity, fine for the
+        // `DEDUP_MAX_IDS` IDs kept and only done for repeats
+        if self.seen.insert(id.to_string(), Instant::now()).is_some() {
+            self.order.retain(|seen_id| seen_id != id);
+        }
+        self.order.push_back(id.to_string());
+
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        !repeated
+    }
+}
+
+/// Service and target of an alert, read from the segments of its MQTT topic
+struct TopicRoute {
+    service: Option<String>,
+    target: Option<String>,
+}
+
+impl TopicRoute {
+    /// Reads the service and target from a topic with the `topic_routing` pattern
+    ///
+    /// The pattern has one entry per topic segment: `<SERVICE>` and `<TARGET>` take the
+    /// segment's value, `+` matches any segment and anything else must match exactly.
+    ///
+    /// # Arguments
+    /// * `pattern` - Pattern of the topic, ie: "bvrchirp/<SERVICE>/<TARGET>/alert"
+    /// * `topic` - Topic the message was published on
+    ///
+    /// # Returns
+    /// * `None` if the topic doesn't have the pattern's shape
+    fn parse(pattern: &str, topic: &str) -> Option<TopicRoute> {
+        let patterns: Vec<&str> = pattern.split('/').collect();
+        let segments: Vec<&str> = topic.split('/').collect();
+        if patterns.len() != segments.len() {
+            return None;
+        }
+
+        let mut route = TopicRoute { service: None, target: None };
+        for (pattern, segment) in patterns.into_iter().zip(segments) {
+            match pattern {
+                "<SERVICE>" if !segment.is_empty() => route.service = Some(segment.to_owned()),
+                "<TARGET>" if !segment.is_empty() => route.target = Some(segment.to_owned()),
+                "+" => {}
+                literal if literal == segment => {}
+                _ => return None,
+            }
+        }
+        Some(route)
+    }
+}
+
+/// Returns true if the broker refused the connection because of its credentials
+fn is_auth_failure(error: &ConnectionError) -> bool {
+    matches!(error, ConnectionError::ConnectionRefused(ConnectReturnCode::BadUserNamePassword | ConnectReturnCode::NotAuthorized))
+}
+
+/// Classifies a broker connection error, refused credentials are `Auth` and everything else is `Transient`
+fn connection_error(error: &ConnectionError) -> BvrChirpError {
+    if is_auth_failure(error) {
+        BvrChirpError::Auth(error.to_string())
+    } else {
+        BvrChirpError::Transient(error.to_string())
+    }
+}
+
+/// Subscribes to one of the configured topics
+///
+/// # Arguments
+///
+/// * `client` - MQTT client to subscribe with
+/// * `topic` - Topic filter to subscribe to
+/// * `name` - What the topic is for, used in the log
+///
+/// # Returns
+///
+/// * `false` if the topic isn't a valid filter or the subscription couldn't be requested
+fn subscribe(client: &Client, topic: &str, name: &str) -> bool {
+    if !valid_filter(topic) {
+        error!("MQTT: Not a valid topic filter for {}='{}', stopping", name, topic);
+        return false;
+    }
+    if let Err(e) = client.subscribe(topic, QoS::AtMostOnce) {
+        error!("MQTT: Failed to subscribe to {}='{}', stopping: {}", name, topic, e);
+        return false;
+    }
+    info!("MQTT: Successfully subscribed to {}='{}'", name, topic);
+    true
+}
+
+/// Credentials to first connect to the broker with
+///
+/// Short-lived credentials come from the auth server at `credential_url`, the configured
+/// `username` and `password` are the fallback when there's none or it can't be reached.
+fn connect_credentials(config: &MqttConfig) -> (String, String) {
+    if !config.credential_url.is_empty() {
+        match fetch_credentials(&config.credential_url, &config.credential_token) {
+            Ok(fetched) => return fetched,
+            Err(e) => error!("MQTT: Failed to fetch credentials from credential_url, using the configured ones: {}", e),
+        }
+    }
+    (config.username.clone(), config.password.clone())
+}
+
+/// Fetches broker credentials from an auth server
+///
+/// The server answers a GET request with JSON holding the `username` and `password`
+/// to connect with, ie: `{"username": "bvr_chirp", "password": "<short-lived token>"}`.
+///
+/// # Arguments
+/// * `url` - URL of the auth server, `credential_url`
+/// * `token` - Bearer token sent to the auth server, not sent when empty
+///
+/// # Returns
+/// * `Ok((username, password))` to connect with
+/// * `Err` if the request fails or the answer doesn't have the credentials
+fn fetch_credentials(url: &str, token: &str) -> bvr_chirp_error::Result<(String, String)> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(CREDENTIAL_FETCH_TIMEOUT)
+        .build()?;
+    let mut request = client.get(url);
+    if !token.is_empty() {
+        request = request.bearer_auth(token);
+    }
+
+    let json: Value = check_status(request.send()?, BvrChirpError::Auth)?.json()?;
+    match (json["username"].as_str(), json["password"].as_str()) {
+        (Some(username), Some(password)) => Ok((username.to_owned(), password.to_owned())),
+        _ => Err(BvrChirpError::Auth("Credentials answer is missing 'username' or 'password'".to_string())),
+    }
+}
+
+/// Decodes HTML entities like `&amp;` or `&#39;`, keeping the text as is if it isn't valid HTML
+fn decode_html(text: &str) -> String {
+    htmlescape::decode_html(text).unwrap_or_else(|_| text.to_string())
+}
+
+/// Decompresses a gzip payload, which may itself be base64 encoded
+fn gunzip(payload: &[u8]) -> std::io::Result<Vec<u8>> {
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+    let compressed = if payload.starts_with(&GZIP_MAGIC) {
+        payload.to_vec()
+    } else {
+        BASE64_STANDARD.decode(payload.trim_ascii())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+    };
+
+    let mut decompressed = Vec::new();
+    GzDecoder::new(compressed.as_slice()).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+/// Edits the images of an alert and of the other cameras collapsed into it
+///
+/// Adds the motion delta, the text overlay and the QR code when they're enabled. Runs after
+/// burst capture and multi-camera grouping, so burst capture scores the frames as received
+/// and the frames it drops aren't decoded and re-encoded for nothing.
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the image options
+/// * `message` - The alert about to be sent
+fn edit_images(cfg: &BvrChirpConfig, message: &mut BvrChirpMessage) {
+    edit_image(cfg, message);
+    for related in &mut message.related {
+        edit_image(cfg, related);
+    }
+}
+
+/// Edits the image of one camera's alert, see `edit_images`
+fn edit_image(cfg: &BvrChirpConfig, message: &mut BvrChirpMessage) {
+    if !message.has_image() {
+        return;
+    }
+
+    // Before and after side by side, telling a real change from a lighting flicker at a glance
+    if cfg.include_motion_delta {
+        if let Some(delta) = motion_delta::compare(&message.camera_name, &message.image) {
+            message.image = delta;
+        }
+    }
+
+    // Snapshots stay labeled even when they're saved or forwarded without the alert text
+    if cfg.overlay_text {
+        let text = format!("{}  {}", message.camera_name, message.time);
+        message.image = image_processing::add_text_overlay(std::mem::take(&mut message.image), &text, cfg.overlay_position,
+                                                           cfg.overlay_color().unwrap_or(OVERLAY_DEFAULT_COLOR));
+    }
+
+    // Scanning the alert on a shared screen opens the recording on a phone. The QR code is part of
+    // the uploaded image, so it leaves out the credentials
+    if cfg.include_qr {
+        let link = cfg.public_recording_link(&message.db_id, &message.camera_name);
+        message.image = image_processing::add_qr_code(std::mem::take(&mut message.image), &link);
+    }
+}
+
+/// Passes a message to every enabled messaging client once a delay has passed
+///
+/// The wait happens on its own thread so the MQTT connection keeps being serviced.
+/// The alert is counted towards its camera's alerts today, and the daily summary, before it's passed on.
+///
+/// # Arguments
+/// * `message` - The message to send
+/// * `tx_clients` - Channel senders of the messaging clients
+/// * `verbose` - Log each successful hand-off
+/// * `delay` - How long to wait before passing the message on, zero sends right away
+pub fn dispatch_delayed(mut message: BvrChirpMessage, tx_clients: &[TxClient], verbose: bool, delay: Duration) {
+    message.daily_count = Some(daily_counts::record(&message.camera_name));
+    if !message.debug {
+        daily_summary::record(&message);
+    }
+
+    if delay.is_zero() {
+        dispatch(&message, tx_clients, verbose);
+        return;
+    }
+
+    let tx_clients = tx_clients.to_vec();
+    thread::spawn(move || {
+        thread::sleep(delay);
+        dispatch(&message, &tx_clients, verbose);
+    });
+}
+
+/// Returns the camera's confidence threshold and the alert's confidence if it's below it
+///
+/// # Arguments
+/// * `thresholds` - `camera_confidence_thresholds`, percentages per camera
+/// * `message` - The alert with its parsed confidence
+fn below_confidence_threshold(thresholds: &BTreeMap<String, f64>, message: &BvrChirpMessage) -> Option<(f64, f64)> {
+    let threshold = *thresholds.get(&message.camera_name)?;
+    let confidence = message.best_confidence()?;
+    (confidence < threshold).then(|| (threshold, confidence))
+}
+
+/// Returns true if an alert should still be sent while the system is disarmed
+///
+/// # Arguments
+/// * `message` - The alert with its parsed detections
+/// * `disarmed_detections` - Keywords matched case-insensitively anywhere in the detections
+fn allowed_while_disarmed(message: &BvrChirpMessage, disarmed_detections: &[String]) -> bool {
+    disarmed_detections.iter().any(|keyword| {
+        let keyword = keyword.to_lowercase();
+        message.detection_list.iter().any(|detection| detection.to_lowercase().contains(&keyword))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SECRET: &str = "s3cret";
+    const SIGNED_AT: u64 = 1718000000;
+    const TOPIC: &str = "BlueIris/alert";
+
+    /// Alert payload published on `TOPIC`, signed with `SECRET`
+    fn signed_payload() -> Value {
+        json!({
+            "target": "front",
+            "camera": "Driveway",
+            "detections": "person",
+            "db_id": "@123",
+            "time": "2024-06-01 14:03:22",
+            "image": "aGk=",
+            "signed_at": SIGNED_AT,
+            "detections_detail": [{ "label": "person", "confidence": 92.5 }],
+            "signature": "cd34721728f4ca91a19f0b157d7110b7dd0bd375f66f6b34179126bf3121319f",
+        })
+    }
+
+    #[test]
+    fn signed_content_is_length_prefixed_and_ordered() {
+        assert_eq!(signed_content(TOPIC, &signed_payload()),
+                   "14:BlueIris/alert6:camera8:Driveway5:db_id4:@12310:detections6:person\
+                    17:detections_detail38:[{\"confidence\":92.5,\"label\":\"person\"}]\
+                    5:image4:aGk=9:signed_at10:17180000006:target5:front4:time19:2024-06-01 14:03:22");
+    }
+
+    #[test]
+    fn signed_content_keeps_text_in_its_field() {
+        let split = json!({ "camera": "Driveway\nperson", "detections": "" });
+        let joined = json!({ "camera": "Driveway", "detections": "person" });
+        assert_ne!(signed_content(TOPIC, &split), signed_content(TOPIC, &joined));
+    }
+
+    #[test]
+    fn payload_signature_valid() {
+        assert!(verify_payload_signature(SECRET, TOPIC, &signed_payload(), SIGNED_AT + 10));
+    }
+
+    #[test]
+    fn payload_signature_covers_every_field() {
+        let mut payload = signed_payload();
+        payload["image_url"] = json!("https://attacker.example/image.jpg");
+        assert!(!verify_payload_signature(SECRET, TOPIC, &payload, SIGNED_AT));
+
+        let mut payload = signed_payload();
+        payload["detections_detail"][0]["confidence"] = json!(12.5);
+        assert!(!verify_payload_signature(SECRET, TOPIC, &payload, SIGNED_AT));
+    }
+
+    #[test]
+    fn payload_signature_covers_topic() {
+        assert!(!verify_payload_signature(SECRET, "bvrchirp/discord/123456/alert", &signed_payload(), SIGNED_AT));
+    }
+
+    #[test]
+    fn payload_signature_wrong_secret() {
+        assert!(!verify_payload_signature("other", TOPIC, &signed_payload(), SIGNED_AT));
+    }
+
+    #[test]
+    fn payload_signature_stale_or_missing_timestamp() {
+        assert!(!verify_payload_signature(SECRET, TOPIC, &signed_payload(), SIGNED_AT + MAX_SIGNATURE_AGE_SECS + 1));
+
+        let mut payload = signed_payload();
+        payload.as_object_mut().unwrap().remove("signed_at");
+        assert!(!verify_payload_signature(SECRET, TOPIC, &payload, SIGNED_AT));
+    }
+
+    #[test]
+    fn signed_command_must_be_signed_with_a_secret() {
+        assert_eq!(signed_command("", "bvrchirp/maintenance", b"mute Driveway", "command"), Some("mute Driveway".to_string()));
+        assert_eq!(signed_command(SECRET, "bvrchirp/maintenance", b"mute Driveway", "command"), None);
+        assert_eq!(signed_command(SECRET, "bvrchirp/maintenance", br#"{"command": "mute Driveway"}"#, "command"), None);
+        assert_eq!(signed_command(SECRET, "alarm/state", b"disarmed", "state"), None);
+    }
+
+    #[test]
+    fn topic_route_reads_service_and_target() {
+        let route = TopicRoute::parse("bvrchirp/<SERVICE>/<TARGET>/alert", "bvrchirp/discord/123456/alert").unwrap();
+        assert_eq!(route.service.as_deref(), Some("discord"));
+        assert_eq!(route.target.as_deref(), Some("123456"));
+    }
+
+    #[test]
+    fn topic_route_with_wildcard_and_one_placeholder() {
+        let route = TopicRoute::parse("+/<TARGET>", "BlueIris/front").unwrap();
+        assert_eq!(route.service, None);
+        assert_eq!(route.target.as_deref(), Some("front"));
+    }
+
+    #[test]
+    fn topic_route_rejects_other_shapes() {
+        let pattern = "bvrchirp/<SERVICE>/<TARGET>/alert";
+        assert!(TopicRoute::parse(pattern, "bvrchirp/discord/123456").is_none());
+        assert!(TopicRoute::parse(pattern, "bvrchirp/discord/123456/alert/extra").is_none());
+        assert!(TopicRoute::parse(pattern, "other/discord/123456/alert").is_none());
+        assert!(TopicRoute::parse(pattern, "bvrchirp//123456/alert").is_none());
+    }
+
+    #[test]
+    fn recent_ids_detects_repeats_within_the_window() {
+        let mut ids = RecentIds::new(10, Duration::from_secs(60));
+        assert!(ids.insert("A1"));
+        assert!(!ids.insert("A1"));
+        assert!(ids.insert("B2"));
+        assert!(ids.insert(""));
+        assert!(ids.insert(""));
+
+        let mut expired = RecentIds::new(10, Duration::ZERO);
+        assert!(expired.insert("A1"));
+        assert!(expired.insert("A1"), "an ID seen outside the window isn't a repeat");
+    }
+
+    #[test]
+    fn recent_ids_forgets_the_least_recently_seen() {
+        let mut ids = RecentIds::new(2, Duration::from_secs(60));
+        assert!(ids.insert("A1"));
+        assert!(ids.insert("B2"));
+        assert!(ids.insert("C3"));
+        assert_eq!(ids.order, ["B2", "C3"]);
+        assert!(ids.insert("A1"), "the oldest ID was evicted");
+    }
+
+    #[test]
+    fn recent_ids_moves_a_repeat_to_the_back() {
+        let mut ids = RecentIds::new(2, Duration::from_secs(60));
+        assert!(ids.insert("A1"));
+        assert!(ids.insert("B2"));
+        assert!(!ids.insert("A1"));
+        assert!(ids.insert("C3"));
+        assert_eq!(ids.order, ["A1", "C3"]);
+        assert_eq!(ids.seen.len(), 2);
+        assert!(!ids.insert("A1"));
+        assert!(ids.insert("B2"));
+    }
+
+    #[test]
+    fn self_test_topic_fills_in_wildcards() {
+        assert_eq!(self_test_topic("BlueIris/alert"), "BlueIris/alert");
+        assert_eq!(self_test_topic("BlueIris/+/alert"), "BlueIris/bvr_chirp_self_test/alert");
+        assert_eq!(self_test_topic("BlueIris/#"), "BlueIris/bvr_chirp_self_test");
+    }
+
+    #[test]
+    fn alerts_below_the_camera_confidence_threshold_are_skipped() {
+        let thresholds = BTreeMap::from([("Driveway".to_string(), 80.0)]);
+        let mut message = BvrChirpMessage::new(String::new(), "Driveway".to_string(), "person".to_string(),
+                                               "@123".to_string(), String::new(), Vec::new());
+
+        message.confidence = parse_confidence(&json!("1%"));
+        assert_eq!(below_confidence_threshold(&thresholds, &message), Some((80.0, 1.0)));
+
+        message.confidence = parse_confidence(&json!(0.92));
+        assert_eq!(below_confidence_threshold(&thresholds, &message), None);
+
+        message.confidence = None;
+        assert_eq!(below_confidence_threshold(&thresholds, &message), None);
+
+        message.camera_name = "Backyard".to_string();
+        message.confidence = parse_confidence(&json!(12));
+        assert_eq!(below_confidence_threshold(&thresholds, &message), None);
+    }
+
+    #[test]
+    fn self_test_messages_are_recognized() {
+        assert!(is_self_test(br#"{"bvr_chirp_self_test":"0123456789abcdef"}"#));
+        assert!(!is_self_test(br#"{"target":"cam","camera":"Front"}"#));
+        assert!(!is_self_test(b"not json"));
+        assert!(!is_self_test(&[0x1f, 0x8b, 0x08, 0x00]));
+    }
+}
@@ -1,20 +1,28 @@
+use std::borrow::Cow;
+use std::thread;
 use std::time::Duration;
 use reqwest::blocking::{multipart, Client};
-use serde_json::json;
-use tokio::time;
-use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
 use crossbeam_channel::Receiver;
 
-use crate::bvr_chirp_config::SlackConfig;
+use crate::bvr_chirp_config::{BvrChirpConfig, SlackDestination};
+use crate::bvr_chirp_error::{BvrChirpError, Result};
 use crate::bvr_chirp_message::BvrChirpMessage;
-use crate::message_templates::SLACK_TEMPLATE;
+use crate::clients::client_loop::{self, BlockingClient};
+use crate::interaction_server::SLACK_ACK_ACTION_ID;
+use crate::message_templates::{json_escape, plain_ascii, truncate, SLACK_TEMPLATE};
+use log::{error, info};
 
-/// A client for uploading files and sending messages to Slack channel using Slack's Web API.
+/// Seconds an idle connection to Slack is kept open for reuse
+const POOL_IDLE_TIMEOUT_SECS: u64 = 90;
+
+/// A client for uploading files and sending messages to Slack channels using Slack's Web API.
+///
+/// Each destination channel may be in a different workspace with its own token.
 struct SlackClient {
     client: Client,
-    token: String,
-    channel_id: String,
-    alert_endpoint: String,
+    destinations: Vec<SlackDestination>,
+    cfg: BvrChirpConfig,
 }
 
 /// Response from Slack's files.getUploadURLExternal API
@@ -24,36 +32,63 @@ struct UploadUrlResponse {
     file_id: String,
 }
 
+/// An alert message sent to a destination, kept to replace its preview with the full image
+struct SentAlert<'a> {
+    destination: &'a SlackDestination,
+    /// Timestamp (ts) of the message
+    ts: String,
+    /// Camera and uploaded image ID of each other camera of a collapsed alert
+    related_file_ids: Vec<(String, String)>,
+}
+
 impl SlackClient {
     /// Creates a new SlackClient with the specified credentials and configuration
     ///
+    /// The HTTP client is shared by every request, including uploads, so connections
+    /// to Slack are kept alive and reused between alerts.
+    ///
     /// # Arguments
-    /// * `token` - Slack API authentication token
-    /// * `channel_id` - ID of the Slack channel to post messages to
-    /// * `alert_endpoint` - Base URL for alert links (ie: BlueIris server address)
-    fn new(token: String, channel_id: String, alert_endpoint: String) -> Self {
-        Self {
-            client: Client::new(),
-            token,
-            channel_id,
-            alert_endpoint,
+    /// * `cfg` - BvrChirpConfig containing the Slack destinations and timeouts
+    ///
+    /// # Returns
+    /// * `Ok(SlackClient)` if the HTTP client could be built
+    /// * `Err` if the HTTP client configuration is invalid or there's no destination
+    fn new(cfg: &BvrChirpConfig) -> Result<Self> {
+        let config = &cfg.slack_config;
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .timeout(Duration::from_secs(config.request_timeout_secs))
+            .pool_idle_timeout(Duration::from_secs(POOL_IDLE_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| BvrChirpError::Config(format!("Failed to build HTTP client: {}", e)))?;
+
+        let destinations = config.all_destinations();
+        if destinations.is_empty() {
+            return Err(BvrChirpError::Config("No Slack token and channel configured".to_string()));
         }
+
+        Ok(Self {
+            client,
+            destinations,
+            cfg: cfg.clone(),
+        })
     }
 
     /// Retrieves a URL for uploading files to Slack using
     /// the [files.getUploadURLExternal](https://api.slack.com/methods/files.getUploadURLExternal) API
     ///
     /// # Arguments
+    /// * `token` - Token of the workspace to upload to
     /// * `filename` - Name of the file to be uploaded
     /// * `file_length` - Size of the file in bytes
     ///
     /// # Returns
     /// * `Ok(UploadUrlResponse)` containing upload_url and file_id
     /// * `Err` if the API request fails or response is invalid
-    fn get_upload_url(&self, filename: &str, file_length: usize) -> Result<UploadUrlResponse> {
+    fn get_upload_url(&self, token: &str, filename: &str, file_length: usize) -> Result<UploadUrlResponse> {
         let response = self.client
             .post("https://slack.com/api/files.getUploadURLExternal")
-            .bearer_auth(&self.token)
+            .bearer_auth(token)
             .form(&[
                 ("filename", filename),
                 ("length", file_length.to_string().as_str()),
@@ -61,11 +96,13 @@ impl SlackClient {
             .send()?
             .json::<serde_json::Value>()?;
 
+        check_response(&response, BvrChirpError::Upload)?;
+
         Ok(UploadUrlResponse {
             upload_url: response["upload_url"].as_str()
-                .ok_or_else(|| anyhow::anyhow!("Failed to get upload URL"))?.to_string(),
+                .ok_or_else(|| BvrChirpError::Upload("Failed to get upload URL".to_string()))?.to_string(),
             file_id: response["file_id"].as_str()
-                .ok_or_else(|| anyhow::anyhow!("Failed to get file ID"))?.to_string(),
+                .ok_or_else(|| BvrChirpError::Upload("Failed to get file ID".to_string()))?.to_string(),
         })
     }
 
@@ -82,11 +119,12 @@ impl SlackClient {
     ///
     /// # Errors
     /// Will return an error if the upload fails or returns a non-success status code
-    async fn upload_file_data(&self, upload_url: &str, filename: &str, file_data: &[u8]) -> Result<()> {
+    fn upload_file_data(&self, upload_url: &str, filename: &str, file_data: &[u8]) -> Result<()> {
         let form = multipart::Form::new()
             .part("file", multipart::Part::bytes(file_data.to_vec())
                 .file_name(filename.to_string())
-                .mime_str("application/octet-stream")?);
+                .mime_str("application/octet-stream")
+                .map_err(|e| BvrChirpError::Upload(e.to_string()))?);
 
         let response = self.client
             .post(upload_url)
@@ -94,7 +132,7 @@ impl SlackClient {
             .send()?;
 
         if !response.status().is_success() {
-            anyhow::bail!("Upload failed with status: {}", response.status());
+            return Err(BvrChirpError::Upload(format!("Upload failed with status: {}", response.status())));
         }
 
         Ok(())
@@ -104,16 +142,17 @@ impl SlackClient {
     /// [files.completeUploadExternal](https://api.slack.com/methods/files.completeUploadExternal) API
     ///
     /// # Arguments
+    /// * `token` - Token of the workspace the file was uploaded to
     /// * `file_id` - ID of the uploaded file
     /// * `filename` - Name of the uploaded file
     ///
     /// # Returns
     /// * `Ok(())` if completion succeeds
     /// * `Err` if the API request fails
-    fn complete_upload(&self, file_id: &str, filename: &str) -> Result<()> {
-        self.client
+    fn complete_upload(&self, token: &str, file_id: &str, filename: &str) -> Result<()> {
+        let response = self.client
             .post("https://slack.com/api/files.completeUploadExternal")
-            .bearer_auth(&self.token)
+            .bearer_auth(token)
             .header("Content-type", "application/x-www-form-urlencoded")
             .json(&json!({
                 "files": [{
@@ -121,156 +160,542 @@ impl SlackClient {
                     "title": filename
                 }]
             }))
-            .send()?;
+            .send()?
+            .json::<serde_json::Value>()?;
 
-        Ok(())
+        check_response(&response, BvrChirpError::Upload)
     }
 
     /// Performs the complete file upload workflow including getting URL, uploading data,
     /// and completing the upload
     ///
     /// # Arguments
+    /// * `token` - Token of the workspace to upload to
     /// * `img_name` - Name of the image file
     /// * `file_data` - Byte array containing the image data
     ///
     /// # Returns
     /// * `Ok(String)` containing the file_id of the uploaded file
     /// * `Err` if any step of the upload process fails
-    pub async fn upload_file(&self, img_name: String, file_data: &[u8]) -> Result<String> {
+    pub fn upload_file(&self, token: &str, img_name: String, file_data: &[u8]) -> Result<String> {
         let filename = img_name.as_str();
 
-        let upload_info = self.get_upload_url(filename, file_data.len())?;
-        self.upload_file_data(&upload_info.upload_url, filename, file_data).await.expect("TODO: panic message");
-        self.complete_upload(&upload_info.file_id, filename)?;
+        let upload_info = self.get_upload_url(token, filename, file_data.len())?;
+        self.upload_file_data(&upload_info.upload_url, filename, file_data)?;
+        self.complete_upload(token, &upload_info.file_id, filename)?;
 
         // Return the file ID to include with message
         Ok(upload_info.file_id)
     }
 
-    /// Sends a formatted message to the configured Slack channel
+    /// Sends a formatted message to a Slack channel
     ///
     /// # Arguments
+    /// * `destination` - Channel to send to and the token of its workspace
     /// * `blocks` - JSON string containing the formatted Slack message blocks
     ///
     /// # Returns
-    /// * `Ok(())` if message send succeeds
+    /// * `Ok(String)` with the timestamp (ts) of the sent message if message send succeeds
     /// * `Err` if the API request fails
-    fn send_message(&self, blocks: &str) -> Result<()> {
-        self.client
+    fn send_message(&self, destination: &SlackDestination, blocks: &str) -> Result<String> {
+        let response = self.client
             .post("https://slack.com/api/chat.postMessage")
-            .header("Authorization", format!("Bearer {}", self.token))
+            .header("Authorization", format!("Bearer {}", destination.token))
             .header("Content-type", "application/x-www-form-urlencoded")
             .json(&json!({
-                "channel": self.channel_id,
+                "channel": destination.channel_id,
                 "blocks": blocks,
             }))
-            .send()?;
+            .send()?
+            .json::<serde_json::Value>()?;
 
-        Ok(())
+        check_response(&response, BvrChirpError::Send)?;
+        Ok(response["ts"].as_str().unwrap_or_default().to_string())
     }
 
-    /// Processes an alert by uploading an image and sending a formatted message
+    /// Replaces the blocks of a sent message
     ///
     /// # Arguments
-    /// * `bvr_msg` - BvrChirpMessage containing alert details and image
+    /// * `destination` - Channel the message was sent to
+    /// * `ts` - Timestamp of the message to update
+    /// * `blocks` - JSON string containing the new message blocks
     ///
     /// # Returns
-    /// * `Ok(())` if processing succeeds
-    /// * `Err` if image upload or message send fails
-    async fn process_alert(&self, bvr_msg: BvrChirpMessage) -> anyhow::Result<()>{
-        let img_name = format!("{}.jpg", bvr_msg.camera_name);
-
-        let upload_result = self.upload_file(img_name, &bvr_msg.image).await;
-
-        // Upload the alert image
-        let file_id = match &upload_result {
-            Ok(file_id) => file_id,
-            Err(e) => {
-                return Err(anyhow!("Image upload failed: {}", e))
-            },
-        };
+    /// * `Ok(())` if the message was updated
+    /// * `Err` if the API request fails
+    fn update_message(&self, destination: &SlackDestination, ts: &str, blocks: &str) -> Result<()> {
+        let response = self.client
+            .post("https://slack.com/api/chat.update")
+            .header("Authorization", format!("Bearer {}", destination.token))
+            .json(&json!({
+                "channel": destination.channel_id,
+                "ts": ts,
+                "blocks": blocks,
+            }))
+            .send()?
+            .json::<serde_json::Value>()?;
+
+        check_response(&response, BvrChirpError::Send)
+    }
+
+    /// Replies in the thread of a sent message
+    ///
+    /// # Arguments
+    /// * `destination` - Channel the message was sent to
+    /// * `thread_ts` - Timestamp of the message to reply to
+    /// * `text` - mrkdwn text of the reply
+    ///
+    /// # Returns
+    /// * `Ok(())` if the reply was sent
+    /// * `Err` if the API request fails
+    fn send_thread_reply(&self, destination: &SlackDestination, thread_ts: &str, text: &str) -> Result<()> {
+        let response = self.client
+            .post("https://slack.com/api/chat.postMessage")
+            .header("Authorization", format!("Bearer {}", destination.token))
+            .json(&json!({
+                "channel": destination.channel_id,
+                "thread_ts": thread_ts,
+                "text": text,
+            }))
+            .send()?
+            .json::<serde_json::Value>()?;
 
+        check_response(&response, BvrChirpError::Send)
+    }
+
+    /// Sends an alert to one channel, with the detections breakdown as a thread reply
+    ///
+    /// # Arguments
+    /// * `destination` - Channel to send to
+    /// * `file_id` - ID of the image uploaded to the channel's workspace, empty for a text only alert
+    /// * `related_file_ids` - Camera and uploaded image ID of the other cameras of a collapsed alert
+    /// * `bvr_msg` - BvrChirpMessage containing alert details
+    ///
+    /// # Returns
+    /// * `Ok(String)` with the timestamp (ts) of the sent message
+    /// * `Err` if the API request fails
+    fn send_alert(&self, destination: &SlackDestination, file_id: &str, related_file_ids: &[(String, String)],
+                  bvr_msg: &BvrChirpMessage) -> Result<String> {
         // Build Slack message block from a template
-        let msg = build_message(&self.alert_endpoint, file_id.as_str(), &bvr_msg);
+        let msg = build_message(&self.cfg, &destination.bot_name, file_id, related_file_ids, bvr_msg);
+
+        let ts = self.send_message(destination, &msg)?;
+
+        // The detections breakdown goes in the thread, keeping the alert itself short
+        let details = bvr_msg.detail_lines();
+        if !details.is_empty() && !ts.is_empty() && !self.cfg.slack_config.image_only {
+            let mut text = format!("*Details*\n{}", details.join("\n"));
+            if self.cfg.slack_config.plain_ascii {
+                text = plain_ascii(&text);
+            }
+            if let Err(e) = self.send_thread_reply(destination, &ts, &text) {
+                error!("SLACK: Failed to send detections breakdown: {}", e);
+            }
+        }
+
+        Ok(ts)
+    }
+
+    /// Uploads an image to the workspace of every destination
+    ///
+    /// Uploaded files belong to a workspace, so the image is uploaded once per destination.
+    ///
+    /// # Arguments
+    /// * `destinations` - Channels the image will be shown in
+    /// * `image` - Encoded image data, empty for a text only alert
+    /// * `bvr_msg` - BvrChirpMessage the image belongs to
+    ///
+    /// # Returns
+    /// * Each destination with the ID of its uploaded file, empty when there's no image,
+    ///   or the upload failure
+    fn upload_to_all<'a>(&self, destinations: Vec<&'a SlackDestination>, image: &[u8], bvr_msg: &BvrChirpMessage)
+        -> Vec<(&'a SlackDestination, Result<String>)> {
+        let mut uploads = Vec::new();
+        for destination in destinations {
+            let file_id = if image.is_empty() {
+                Ok(String::new())
+            } else {
+                self.upload_file(&destination.token, self.cfg.upload_filename(bvr_msg, image), image)
+            };
+            uploads.push((destination, file_id));
+        }
 
         // The uploaded image is often "not found" until the servers process the image
         // despite a return value indicating it's ready, so we wait a bit to give it
         // a chance to be ready. There must be a better way to do this.
-        time::sleep(Duration::from_millis(3000)).await;
+        if uploads.iter().any(|(_, file_id)| file_id.as_ref().is_ok_and(|file_id| !file_id.is_empty())) {
+            thread::sleep(Duration::from_millis(3000));
+        }
 
-        // Send message
-        if let Err(e) = &self.send_message(&msg) {
-            return Err(anyhow!("Failed to send message: {}", e))
+        uploads
+    }
+
+    /// Uploads the images of the other cameras of a collapsed alert to a destination's workspace
+    ///
+    /// An image that fails to upload is logged and left out.
+    ///
+    /// # Arguments
+    /// * `destination` - Channel the images will be shown in
+    /// * `bvr_msg` - BvrChirpMessage with the alerts of the other cameras
+    ///
+    /// # Returns
+    /// * Camera and uploaded image ID of each other camera, empty if the alert wasn't collapsed
+    fn upload_related(&self, destination: &SlackDestination, bvr_msg: &BvrChirpMessage) -> Vec<(String, String)> {
+        let mut file_ids = Vec::new();
+        for related in bvr_msg.related_images() {
+            let image = self.cfg.upload_image(&related.image);
+            match self.upload_file(&destination.token, self.cfg.upload_filename(related, &image), &image) {
+                Ok(file_id) => file_ids.push((related.camera_name.clone(), file_id)),
+                Err(e) => error!("SLACK: Failed to upload image of {} camera: {}", related.camera_name, e),
+            }
         }
+        file_ids
+    }
 
-        println!("SLACK: Message sent - {}", chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S.%3f"));
-        Ok(())
+    /// Replaces the preview image of sent alerts with the full image
+    ///
+    /// Failures are logged, the alert has already been delivered with its preview.
+    ///
+    /// # Arguments
+    /// * `sent` - The message sent to each destination
+    /// * `bvr_msg` - BvrChirpMessage containing alert details and image
+    fn replace_preview(&self, sent: Vec<SentAlert<'_>>, bvr_msg: &BvrChirpMessage) {
+        let destinations = sent.iter().map(|alert| alert.destination).collect();
+        let uploads = self.upload_to_all(destinations, &self.cfg.upload_image(&bvr_msg.image), bvr_msg);
+
+        for ((destination, file_id), alert) in uploads.into_iter().zip(sent) {
+            let updated = file_id.and_then(|file_id| {
+                let msg = build_message(&self.cfg, &destination.bot_name, &file_id, &alert.related_file_ids, bvr_msg);
+                self.update_message(destination, &alert.ts, &msg)
+            });
+            if let Err(e) = updated {
+                error!("SLACK: Failed to replace preview with full image in channel {}: {}", destination.channel_id, e);
+            }
+        }
+    }
+}
+
+impl BlockingClient for SlackClient {
+    const SERVICE: &'static str = "slack";
+    const LOG_NAME: &'static str = "SLACK";
+
+    /// Sends a plain text message to every destination channel
+    ///
+    /// # Arguments
+    /// * `text` - mrkdwn text of the message
+    ///
+    /// # Returns
+    /// * `Ok(())` if the message was sent to at least one channel
+    /// * `Err` with the last failure if no channel could be sent to
+    fn send_text(&self, text: &str) -> Result<()> {
+        let text = if self.cfg.slack_config.plain_ascii { plain_ascii(text) } else { text.to_string() };
+        let mut last_error = None;
+        let mut sent = false;
+        for destination in &self.destinations {
+            let response = self.client
+                .post("https://slack.com/api/chat.postMessage")
+                .header("Authorization", format!("Bearer {}", destination.token))
+                .json(&json!({
+                    "channel": destination.channel_id,
+                    "text": text,
+                }))
+                .send()
+                .map_err(BvrChirpError::from)
+                .and_then(|response| Ok(response.json::<serde_json::Value>()?));
+
+            match response.and_then(|response| check_response(&response, BvrChirpError::Send)) {
+                Ok(_) => sent = true,
+                Err(err) => last_error = Some(err),
+            }
+        }
+
+        match last_error {
+            Some(err) if !sent => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Processes an alert by uploading an image and sending a formatted message to
+    /// every destination that receives the alert's camera
+    ///
+    /// A destination that fails is logged and skipped so the others still get the alert.
+    ///
+    /// # Arguments
+    /// * `bvr_msg` - BvrChirpMessage containing alert details and image
+    ///
+    /// # Returns
+    /// * `Ok(())` if the alert was sent to at least one destination, or none receives the camera
+    /// * `Err` with the last failure if no destination could be sent to
+    fn process_alert(&self, bvr_msg: &BvrChirpMessage) -> Result<()> {
+        let destinations: Vec<&SlackDestination> = self.destinations.iter()
+            .filter(|destination| destination.accepts(&bvr_msg.camera_name))
+            .collect();
+        if destinations.is_empty() {
+            info!("SLACK: No channel receives alerts from {} camera, skipping", bvr_msg.camera_name);
+            return Ok(());
+        }
+
+        // The other cameras' images of a collapsed alert go first, so the wait for
+        // the main image to be processed covers them too
+        let mut related_file_ids = Vec::new();
+        for destination in &destinations {
+            related_file_ids.push(self.upload_related(destination, bvr_msg));
+        }
+
+        // With fast_preview a small version of the image goes out first and is replaced
+        // once the alert is sent. Alerts whose image couldn't be decoded are sent as text only.
+        let preview = self.cfg.preview_image(bvr_msg);
+        let image = match &preview {
+            Some(preview) => Cow::Borrowed(preview.as_slice()),
+            None => self.cfg.upload_image(&bvr_msg.image),
+        };
+        let uploads = self.upload_to_all(destinations, &image, bvr_msg);
+
+        let mut last_error = None;
+        let mut sent = Vec::new();
+        for ((destination, file_id), related_file_ids) in uploads.into_iter().zip(related_file_ids) {
+            match file_id.and_then(|file_id| self.send_alert(destination, &file_id, &related_file_ids, bvr_msg)) {
+                Ok(ts) => sent.push(SentAlert { destination, ts, related_file_ids }),
+                Err(err) => {
+                    error!("SLACK: Failed to send message to channel {}: {}", destination.channel_id, err);
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        match last_error {
+            Some(err) if sent.is_empty() => Err(err),
+            _ => {
+                info!("SLACK: Message sent - {}", chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S.%3f"));
+                if preview.is_some() {
+                    self.replace_preview(sent, bvr_msg);
+                }
+                Ok(())
+            }
+        }
     }
 }
 
 /// Main entry point for running the Slack client service
 ///
-/// Initializes and starts the Slack client to process messages from the provided channel
+/// Initializes and starts the Slack client to process messages from the provided channel.
+/// Slack's Web API is called with blocking requests, so the client runs on the blocking
+/// thread pool instead of holding up the other clients.
 ///
 /// # Arguments
-/// * `config` - SlackConfig containing token and channel configuration
-/// * `alert_endpoint` - Base URL for alert links (ie: BlueIris server address)
+/// * `cfg` - BvrChirpConfig containing the Slack token and channel configuration
 /// * `rx` - Receiver channel for BvrChirpMessages
 ///
 /// # Returns
-/// * `Ok(())` if client runs successfully
-/// * `Err` if client initialization fails. We print the message, but the returned error
-/// will cause a panic almost immediately after the app start. We want this because it means
-/// the Slack client can't connect and the user needs to fix it.
+/// * `Ok(())` once the message channel closes
+/// * `Err` if client initialization fails
 pub async fn run_slack_client(
-    config: SlackConfig,
-    alert_endpoint: &str,
+    cfg: BvrChirpConfig,
     rx: Receiver<BvrChirpMessage>
 ) -> Result<()> {
-    let slack = SlackClient::new(config.token, config.channel_id, alert_endpoint.to_owned());
+    let slack = match SlackClient::new(&cfg) {
+        Ok(slack) => slack,
+        Err(err) => {
+            error!("SLACK: Error creating Slack client: {}", err);
+            return Err(err);
+        }
+    };
 
-    println!("SLACK: Client ready");
+    client_loop::run_blocking_client(cfg, slack, rx).await;
+    Ok(())
+}
 
-    loop {
-        let bvr_msg = match rx.recv() {
-            Ok(msg) => msg,
-            Err(err) => {
-                println!("SLACK: Failed to receive message: {}", err);
-                continue
-            }
-        };
+/// Checks the `ok` flag of a Slack Web API response
+///
+/// Slack responds with HTTP 200 even when a call fails, so the `error` field is
+/// inspected to tell authentication problems apart from other failures.
+///
+/// # Arguments
+/// * `response` - JSON body returned by the Slack API
+/// * `other` - Error variant used for failures that aren't auth or transient
+///
+/// # Returns
+/// * `Ok(())` if Slack reported success
+/// * `Err` describing the failure otherwise
+fn check_response(response: &serde_json::Value, other: fn(String) -> BvrChirpError) -> Result<()> {
+    if response["ok"].as_bool().unwrap_or(false) {
+        return Ok(());
+    }
 
-        match slack.process_alert(bvr_msg.to_owned()).await {
-            Ok(_) => {}
-            Err(e) => {
-                println!("SLACK: Error processing message: {}", e);
-                continue
-            }
-        }
+    let error = response["error"].as_str().unwrap_or("unknown error").to_string();
+    match error.as_str() {
+        "not_authed" | "invalid_auth" | "account_inactive" | "token_revoked" | "token_expired"
+        | "missing_scope" | "not_in_channel" => Err(BvrChirpError::Auth(error)),
+        "ratelimited" | "service_unavailable" | "internal_error" | "fatal_error"
+        | "request_timeout" => Err(BvrChirpError::Transient(error)),
+        _ => Err(other(error)),
     }
 }
 
 /// Builds a formatted Slack message from a template using the provided data
 ///
 /// # Arguments
-/// * `alert_endpoint` - Base URL for alert links
-/// * `file_id` - ID of the uploaded image file
+/// * `cfg` - BvrChirpConfig with the alert endpoint and optional button settings
+/// * `bot_name` - Name shown in the footer
+/// * `file_id` - ID of the uploaded image file, empty to leave out the image block
+/// * `related_file_ids` - Camera and uploaded image ID of the other cameras of a collapsed alert,
+///   each shown below the main image
 /// * `bvr_msg` - BvrChirpMessage containing alert details
 ///
 /// # Returns
 /// * String containing the formatted message ready to send to Slack
-fn build_message(alert_endpoint: &str, file_id: &str, bvr_msg: &BvrChirpMessage) -> String {
+pub(crate) fn build_message(cfg: &BvrChirpConfig, bot_name: &str, file_id: &str, related_file_ids: &[(String, String)],
+                            bvr_msg: &BvrChirpMessage) -> String {
     let mut msg = SLACK_TEMPLATE.clone();
+    // Long values are cut so an unusual detections string can't get the blocks rejected
+    let limit = cfg.slack_config.max_field_length;
+    // Replaced first so the button text and labels can use the placeholders below
+    msg = msg.replace("<BUTTON_TEXT>", json_escape(&cfg.slack_config.button_text).as_str());
+    msg = msg.replace("<BUTTON_EMOJI>", if cfg.slack_config.button_emoji { "true" } else { "false" });
+    for key in ["detections_label", "time_label", "today_label"] {
+        msg = msg.replace(&format!("<{}>", key.to_uppercase()), json_escape(&cfg.label(key)).as_str());
+    }
+    msg = msg.replace("<DAILY_COUNT_TEXT>", json_escape(&cfg.render_label("daily_count_format", bvr_msg)).as_str());
     msg = msg.replace("<IMG_ID>", file_id);
-    msg = msg.replace("<CAMERA_NAME>", bvr_msg.camera_name.as_str());
-    msg = msg.replace("<ENDPOINT_URL>",
-                      format!("{}/ui3.htm?rec={}&cam={}&m=1",
-                              alert_endpoint,
-                              bvr_msg.db_id,
-                              bvr_msg.camera_name
-                      ).as_str()
-    );
+    msg = msg.replace("<TITLE>", json_escape(&truncate(&cfg.alert_title(bvr_msg), limit)).as_str());
+    let description = truncate(&cfg.alert_description(bvr_msg), limit);
+    if description.is_empty() {
+        msg = msg.replace("<DESCRIPTION>", "");
+    } else {
+        msg = msg.replace("<DESCRIPTION>", format!("\\n{}", json_escape(&description)).as_str());
+    }
+    msg = msg.replace("<CAMERA_NAME>", truncate(&bvr_msg.camera_name, limit).as_str());
+    msg = msg.replace("<ENDPOINT_URL>", &cfg.alert_link(bvr_msg));
     msg = msg.replace("<TIME>", bvr_msg.time.as_str());
-    msg = msg.replace("<DETECTIONS>", bvr_msg.detections.as_str());
+    msg = msg.replace("<DETECTIONS>", truncate(&bvr_msg.detections, limit).as_str());
+    // Detections counted per object type are listed one type per line
+    let count_lines = bvr_msg.count_lines();
+    let count = if count_lines.is_empty() { bvr_msg.count_summary() } else { count_lines.join("\n") };
+    msg = msg.replace("<COUNT>", json_escape(&truncate(&count, limit)).as_str());
+    msg = msg.replace("<DAILY_COUNT>", bvr_msg.daily_count.map(|count| count.to_string()).unwrap_or_default().as_str());
+
+    let mut buttons = Vec::new();
+
+    // Link to the full resolution frame served by the snapshot server
+    if let Some(snapshot_link) = cfg.snapshot_link(&bvr_msg.db_id) {
+        buttons.push(json!({
+            "type": "button",
+            "text": {
+                "type": "plain_text",
+                "text": cfg.label("full_image_label"),
+                "emoji": false
+            },
+            "url": snapshot_link,
+            "action_id": "full-image-action"
+        }));
+    }
+
+    // Link to where the camera physically is
+    if let Some(map_url) = cfg.map_url(&bvr_msg.camera_name) {
+        buttons.push(json!({
+            "type": "button",
+            "text": {
+                "type": "plain_text",
+                "text": cfg.label("map_label"),
+                "emoji": false
+            },
+            "url": map_url,
+            "action_id": "map-action"
+        }));
+    }
+
+    // Link to the camera's live view
+    if let Some(live_url) = cfg.live_url(&bvr_msg.camera_name) {
+        buttons.push(json!({
+            "type": "button",
+            "text": {
+                "type": "plain_text",
+                "text": cfg.label("live_label"),
+                "emoji": false
+            },
+            "url": live_url,
+            "action_id": "live-action"
+        }));
+    }
+
+    // Clicks are sent to the interaction server, which mutes the camera for a while
+    if cfg.slack_acknowledge_enabled() {
+        buttons.push(json!({
+            "type": "button",
+            "text": {
+                "type": "plain_text",
+                "text": cfg.label("acknowledge_label"),
+                "emoji": false
+            },
+            "style": "primary",
+            "value": bvr_msg.camera_name,
+            "action_id": SLACK_ACK_ACTION_ID
+        }));
+    }
+
+    let mut extra_blocks = Vec::new();
+
+    // A section can only hold one accessory, so the extra buttons go in their own actions block
+    if !buttons.is_empty() {
+        extra_blocks.push(json!({
+            "type": "actions",
+            "elements": buttons
+        }));
+    }
+
+    // Footer identifying which bot and instance sent the alert
+    extra_blocks.push(json!({
+        "type": "context",
+        "elements": [{
+            "type": "mrkdwn",
+            "text": cfg.footer_text(bot_name)
+        }]
+    }));
+
+    if let Ok(serde_json::Value::Array(mut blocks)) = serde_json::from_str::<serde_json::Value>(&msg) {
+        // There's no image to show for a text only alert
+        if file_id.is_empty() {
+            blocks.retain(|block| block["type"] != "image");
+        }
+        let related_at = blocks.iter()
+            .position(|block| block["type"] == "image")
+            .map_or(1, |pos| pos + 1)
+            .min(blocks.len());
+        let related_blocks = related_file_ids.iter().map(|(camera, related_file_id)| json!({
+            "type": "image",
+            "slack_file": { "id": related_file_id },
+            "alt_text": format!("{} camera image", camera),
+            "title": { "type": "plain_text", "text": camera }
+        }));
+        blocks.splice(related_at..related_at, related_blocks);
+        if cfg.slack_config.image_only {
+            blocks.retain(|block| block["type"] == "image");
+        } else {
+            blocks.extend(extra_blocks);
+        }
+        // Bridges to SMS or IRC garble emoji and mrkdwn
+        if cfg.slack_config.plain_ascii {
+            blocks.iter_mut().for_each(to_plain_ascii);
+        }
+        msg = serde_json::Value::Array(blocks).to_string();
+    }
+
     msg
-}
\ No newline at end of file
+}
+
+/// Removes emoji and other non-ASCII characters from every text of a block, turning mrkdwn
+/// text into plain text that shows `:emoji:` codes as is
+fn to_plain_ascii(value: &mut Value) {
+    match value {
+        Value::String(text) => *text = plain_ascii(text),
+        Value::Array(values) => values.iter_mut().for_each(to_plain_ascii),
+        Value::Object(values) => {
+            if values.get("type").is_some_and(|kind| kind == "mrkdwn") {
+                values.insert("type".to_string(), json!("plain_text"));
+                values.insert("emoji".to_string(), json!(false));
+            }
+            values.values_mut().for_each(to_plain_ascii);
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use crate::bvr_chirp_message::BvrChirpMessage;
+
+/// A camera's cooldown, started by the last alert that was let through
+struct Cooldown {
+    started: Instant,
+    /// Alert ID of the event that started it, more frames of that event still get through
+    db_id: String,
+    /// Detection types already alerted on during the cooldown, lower case
+    detections: HashSet<String>,
+}
+
+/// Cooldowns by camera
+static COOLDOWNS: Lazy<Mutex<HashMap<String, Cooldown>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Returns true if an alert should be sent, starting the camera's cooldown when it is
+///
+/// After an alert, the camera's further alerts are held back for `cooldown`. Frames of
+/// the same event (same `db_id`) still get through, so the best frame can be picked.
+///
+/// # Arguments
+/// * `bvr_msg` - The alert with its parsed detections
+/// * `cooldown` - How long to hold back a camera's alerts after one is sent, zero disables it
+/// * `break_on_new_type` - Let an alert through during the cooldown if it has a detection
+///   type that wasn't alerted on yet, ie: a car arriving after a person
+pub fn allow(bvr_msg: &BvrChirpMessage, cooldown: Duration, break_on_new_type: bool) -> bool {
+    if cooldown.is_zero() {
+        return true;
+    }
+
+    let mut cooldowns = match COOLDOWNS.lock() {
+        Ok(cooldowns) => cooldowns,
+        Err(_) => return true,
+    };
+
+    let detections: HashSet<String> = bvr_msg.detection_list.iter()
+        .map(|detection| detection.to_lowercase())
+        .collect();
+
+    match cooldowns.get_mut(&bvr_msg.camera_name) {
+        Some(active) if active.started.elapsed() < cooldown => {
+            if active.db_id == bvr_msg.db_id {
+                return true;
+            }
+            if break_on_new_type && !detections.is_subset(&active.detections) {
+                // A new kind of event, alert and cool down again for everything seen so far
+                active.started = Instant::now();
+                active.db_id = bvr_msg.db_id.clone();
+                active.detections.extend(detections);
+                return true;
+            }
+            false
+        }
+        _ => {
+            cooldowns.insert(bvr_msg.camera_name.clone(), Cooldown {
+                started: Instant::now(),
+                db_id: bvr_msg.db_id.clone(),
+                detections,
+            });
+            true
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use chrono::NaiveDate;
+use once_cell::sync::Lazy;
+
+/// Alerts sent per camera on a given day, by camera name
+static COUNTS: Lazy<Mutex<HashMap<String, (NaiveDate, u32)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Counts an alert for a camera, starting over at local midnight
+///
+/// # Arguments
+/// * `camera` - Name of the camera the alert is from
+///
+/// # Returns
+/// * The alert's number among the camera's alerts today, starting at 1
+pub fn record(camera: &str) -> u32 {
+    let today = chrono::offset::Local::now().date_naive();
+    let mut counts = match COUNTS.lock() {
+        Ok(counts) => counts,
+        Err(_) => return 1,
+    };
+
+    let (day, count) = counts.entry(camera.to_string()).or_insert((today, 0));
+    if *day != today {
+        *day = today;
+        *count = 0;
+    }
+    *count += 1;
+    *count
+}
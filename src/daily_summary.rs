@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::thread;
+use chrono::{Local, NaiveDate, NaiveTime, Timelike};
+use once_cell::sync::Lazy;
+use crate::bvr_chirp_message::{capitalize, BvrChirpMessage};
+use crate::clients::mqtt_client::TxClient;
+use log::{info, warn};
+
+/// What was alerted on during one day
+#[derive(Default)]
+struct DayStats {
+    events: u32,
+    /// Events by camera name
+    cameras: BTreeMap<String, u32>,
+    /// Events by detection type, lower case
+    detections: BTreeMap<String, u32>,
+    /// Events by local hour of the day
+    hours: [u32; 24],
+}
+
+/// Stats of the days not summarized yet, by local date
+static DAYS: Lazy<Mutex<BTreeMap<NaiveDate, DayStats>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Counts a dispatched alert towards today's summary
+///
+/// An alert collapsed from several cameras counts as an event for each of them.
+///
+/// # Arguments
+/// * `bvr_msg` - The alert with its parsed detections
+pub fn record(bvr_msg: &BvrChirpMessage) {
+    let now = Local::now();
+    let mut days = match DAYS.lock() {
+        Ok(days) => days,
+        Err(_) => return,
+    };
+
+    let day = days.entry(now.date_naive()).or_default();
+    for alert in std::iter::once(bvr_msg).chain(bvr_msg.related.iter()) {
+        day.events += 1;
+        day.hours[now.hour() as usize] += 1;
+        *day.cameras.entry(alert.camera_name.clone()).or_insert(0) += 1;
+        for detection in &alert.detection_list {
+            *day.detections.entry(detection.to_lowercase()).or_insert(0) += 1;
+        }
+    }
+}
+
+/// Starts a thread that sends a summary of the previous day at a local time every day
+///
+/// # Arguments
+/// * `time` - Local time of day to send the summary at
+/// * `client` - Channel sender of the messaging client to send the summaries through
+pub fn spawn(time: NaiveTime, client: TxClient) {
+    info!("DAILY SUMMARY: Sending yesterday's summary to {} every day at {}", client.name, time.format("%H:%M"));
+
+    thread::spawn(move || loop {
+        let now = Local::now().naive_local();
+        let mut next = now.date().and_time(time);
+        if next <= now {
+            next += chrono::Duration::days(1);
+        }
+        thread::sleep((next - now).to_std().unwrap_or_default());
+
+        let today = Local::now().date_naive();
+        if client.send(BvrChirpMessage::system(summary_text(take_before(today)))).is_err() {
+            warn!("DAILY SUMMARY: {} client stopped, no longer sending summaries", client.name);
+            break;
+        }
+    });
+}
+
+/// Removes and merges the stats of the days before `day`, usually just yesterday's
+fn take_before(day: NaiveDate) -> DayStats {
+    let mut days = match DAYS.lock() {
+        Ok(days) => days,
+        Err(_) => return DayStats::default(),
+    };
+
+    let remaining = days.split_off(&day);
+    let mut merged = DayStats::default();
+    for (_, stats) in std::mem::replace(&mut *days, remaining) {
+        merged.events += stats.events;
+        for (camera, count) in stats.cameras {
+            *merged.cameras.entry(camera).or_insert(0) += count;
+        }
+        for (detection, count) in stats.detections {
+            *merged.detections.entry(detection).or_insert(0) += count;
+        }
+        for (hour, count) in stats.hours.iter().enumerate() {
+            merged.hours[hour] += count;
+        }
+    }
+    merged
+}
+
+/// Text of a summary, ie: "📊 Yesterday: 42 events (Driveway 30, Backyard 12)" followed by
+/// the detection types and the busiest hour
+fn summary_text(stats: DayStats) -> String {
+    if stats.events == 0 {
+        return "📊 Yesterday: no events".to_string();
+    }
+
+    let mut text = format!("📊 Yesterday: {} event{} ({})", stats.events, if stats.events == 1 { "" } else { "s" },
+                           by_count(&stats.cameras, false).join(", "));
+    if !stats.detections.is_empty() {
+        text.push_str(&format!("\nDetections: {}", by_count(&stats.detections, true).join(", ")));
+    }
+
+    // The earliest hour wins a tie
+    let (busiest, count) = stats.hours.iter().enumerate()
+        .rev()
+        .max_by_key(|(_, count)| **count)
+        .unwrap_or((0, &0));
+    let start = NaiveTime::from_hms_opt(busiest as u32, 0, 0).unwrap_or_default();
+    let end = start + chrono::Duration::hours(1);
+    text.push_str(&format!("\nBusiest hour: {}-{} ({} events)", start.format("%H:%M"), end.format("%H:%M"), count));
+    text
+}
+
+/// Entries as "Name count", the most frequent first
+///
+/// # Arguments
+/// * `counts` - Events by name
+/// * `capitalized` - Upper case the first letter of the names, for the lower case detection types
+fn by_count(counts: &BTreeMap<String, u32>, capitalized: bool) -> Vec<String> {
+    let mut entries: Vec<(&String, &u32)> = counts.iter().collect();
+    entries.sort_by(|a, b| b.1.cmp(a.1));
+    entries.iter()
+        .map(|(name, count)| if capitalized {
+            format!("{} {}", capitalize(name), count)
+        } else {
+            format!("{} {}", name, count)
+        })
+        .collect()
+}
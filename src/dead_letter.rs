@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use crate::bvr_chirp_config::{BvrChirpConfig, SERVICES};
+use crate::bvr_chirp_message::BvrChirpMessage;
+use log::{error, warn};
+
+/// Alerts still waiting for a service to report back are forgotten after this long
+const OUTCOME_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Delivery results reported so far for an alert
+struct Outcome {
+    reported: usize,
+    delivered: bool,
+}
+
+/// Identifies an alert by camera, alert ID and when it was received
+type AlertKey = (String, String, Instant);
+
+/// Delivery results by alert
+static OUTCOMES: Lazy<Mutex<HashMap<AlertKey, Outcome>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Records whether a service delivered an alert, dead-lettering it once no service did
+///
+/// Every service an alert is passed to reports it exactly once, that's every enabled
+/// service unless the alert says otherwise in `expected_reports`. The primary services of
+/// a target count as one: a primary service that didn't deliver the alert isn't counted
+/// when the next one gets it. When the last one reports and none delivered the alert,
+/// it's written to `dead_letter_path`.
+/// Reporting also marks the alert as handled by the service, see `ordered_delivery`.
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the dead letter path and enabled services
+/// * `bvr_msg` - The alert the service handled
+/// * `delivered` - Whether the service sent the alert
+pub fn report(cfg: &BvrChirpConfig, bvr_msg: &BvrChirpMessage, delivered: bool) {
+    bvr_msg.mark_handled(delivered);
+
+    if cfg.dead_letter_path.is_empty() {
+        return;
+    }
+
+    let undeliverable = {
+        let mut outcomes = match OUTCOMES.lock() {
+            Ok(outcomes) => outcomes,
+            Err(_) => return,
+        };
+
+        // A service that stopped will never report, don't keep its alerts around forever
+        outcomes.retain(|(_, _, received), _| received.elapsed() < OUTCOME_TIMEOUT);
+
+        // The next primary service reports the final outcome instead
+        if bvr_msg.fallback_follows && !delivered {
+            return;
+        }
+
+        let key = (bvr_msg.camera_name.clone(), bvr_msg.db_id.clone(), bvr_msg.received_at);
+        let outcome = outcomes.entry(key.clone()).or_insert(Outcome { reported: 0, delivered: false });
+        outcome.reported += 1;
+        outcome.delivered |= delivered;
+
+        let expected = bvr_msg.expected_reports.unwrap_or_else(|| enabled_services(cfg));
+        if outcome.reported < expected {
+            false
+        } else {
+            let undeliverable = !outcome.delivered;
+            outcomes.remove(&key);
+            undeliverable
+        }
+    };
+
+    if undeliverable {
+        match write(&cfg.dead_letter_path, bvr_msg) {
+            Ok(file) => warn!("DEAD LETTER: Alert from {} camera couldn't be delivered, saved to {}",
+                                  bvr_msg.camera_name, file),
+            Err(err) => error!("DEAD LETTER: Failed to save undeliverable alert from {} camera: {}",
+                                  bvr_msg.camera_name, err),
+        }
+    }
+}
+
+/// Number of services every alert is passed to
+fn enabled_services(cfg: &BvrChirpConfig) -> usize {
+    SERVICES.iter().filter(|service| cfg.service_enabled(service) == Some(true)).count()
+}
+
+/// Writes an alert as JSON, in the same format as the MQTT payload, to a new file in `dir`
+///
+/// # Returns
+/// * `Ok(path)` of the written file
+/// * `Err` if the directory can't be created or the file can't be written
+fn write(dir: &str, bvr_msg: &BvrChirpMessage) -> std::io::Result<String> {
+    fs::create_dir_all(dir)?;
+
+    let file_name = format!("{}_{}_{}.json",
+                            chrono::offset::Local::now().format("%Y%m%d-%H%M%S%.3f"),
+                            sanitize(&bvr_msg.camera_name),
+                            sanitize(&bvr_msg.db_id));
+    let path = Path::new(dir).join(file_name);
+
+    let json = serde_json::to_string_pretty(&bvr_msg.to_payload_json())?;
+    fs::write(&path, json)?;
+    Ok(path.display().to_string())
+}
+
+/// Keeps only characters that are safe in a file name
+fn sanitize(value: &str) -> String {
+    value.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use once_cell::sync::Lazy;
+
+/// What happened to an alert handed to a service
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    /// The service delivered the alert
+    Sent,
+    /// Sending the alert failed
+    Failed,
+    /// The alert was skipped without trying, ie: stale, circuit breaker open or a full queue
+    Dropped,
+}
+
+/// Alerts handled by one service since the bot started
+#[derive(Clone, Copy, Default, Debug)]
+struct Counts {
+    sent: u64,
+    failed: u64,
+    dropped: u64,
+}
+
+/// Counts per service, by the service's log prefix (ie: "DISCORD")
+static DELIVERY_STATS: Lazy<Mutex<BTreeMap<String, Counts>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Counts what happened to an alert on a service
+///
+/// # Arguments
+/// * `service` - Log prefix of the service, ie: "DISCORD"
+/// * `outcome` - What happened to the alert
+pub fn record(service: &str, outcome: Outcome) {
+    if let Ok(mut stats) = DELIVERY_STATS.lock() {
+        let counts = stats.entry(service.to_string()).or_default();
+        match outcome {
+            Outcome::Sent => counts.sent += 1,
+            Outcome::Failed => counts.failed += 1,
+            Outcome::Dropped => counts.dropped += 1,
+        }
+    }
+}
+
+/// Describes what a service did this session, ie: "142 sent, 2 failed, 0 dropped"
+///
+/// # Arguments
+/// * `service` - Log prefix of the service, ie: "DISCORD"
+pub fn line(service: &str) -> String {
+    let counts = DELIVERY_STATS.lock()
+        .ok()
+        .and_then(|stats| stats.get(service).copied())
+        .unwrap_or_default();
+    format!("{} sent, {} failed, {} dropped", counts.sent, counts.failed, counts.dropped)
+}
+
+/// Describes what every service that handled an alert did this session
+///
+/// # Returns
+/// * ie: "DISCORD: 142 sent, 2 failed, 0 dropped; SLACK: 140 sent, 4 failed, 0 dropped",
+///   or "No alerts handled" before the first alert
+pub fn summary() -> String {
+    let services: Vec<String> = match DELIVERY_STATS.lock() {
+        Ok(stats) => stats.keys().cloned().collect(),
+        Err(_) => Vec::new(),
+    };
+    if services.is_empty() {
+        return "No alerts handled".to_string();
+    }
+
+    services.iter()
+        .map(|service| format!("{}: {}", service, line(service)))
+        .collect::<Vec<String>>()
+        .join("; ")
+}
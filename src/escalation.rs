@@ -0,0 +1,116 @@
+use std::sync::Mutex;
+use once_cell::sync::Lazy;
+use crate::bvr_chirp_config::{BvrChirpConfig, FailureEscalation};
+use crate::bvr_chirp_message::BvrChirpMessage;
+use crate::clients::mqtt_client::TxClient;
+use log::{info, warn};
+
+/// A `failure_escalation` rule with the client its notice goes through
+struct Escalation {
+    rule: FailureEscalation,
+    client: TxClient,
+    /// The notice was sent, no other until the service recovers
+    escalated: bool,
+}
+
+/// Escalation rules, set up once the messaging clients are started
+static ESCALATIONS: Lazy<Mutex<Vec<Escalation>>> = Lazy::new(|| Mutex::new(Vec::new()));
+/// Name of this instance shown in the notices, may be empty
+static INSTANCE_LABEL: Lazy<Mutex<String>> = Lazy::new(|| Mutex::new(String::new()));
+
+/// Sets up the `failure_escalation` rules whose notice service is enabled
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the escalation rules
+/// * `tx_clients` - Channel senders of the enabled messaging clients
+pub fn setup(cfg: &BvrChirpConfig, tx_clients: &[TxClient]) {
+    let mut escalations = match ESCALATIONS.lock() {
+        Ok(escalations) => escalations,
+        Err(_) => return,
+    };
+
+    for rule in &cfg.failure_escalation {
+        match tx_clients.iter().find(|client| client.name.eq_ignore_ascii_case(&rule.notify_service)) {
+            Some(client) => {
+                info!("ESCALATION: Notifying {} after {} consecutive failures of {}", client.name, rule.after_failures, rule.service);
+                escalations.push(Escalation { rule: rule.clone(), client: client.clone(), escalated: false });
+            }
+            None => warn!("ESCALATION: notify_service '{}' is not an enabled service, not escalating failures of {}",
+                          rule.notify_service, rule.service),
+        }
+    }
+    if let Ok(mut instance_label) = INSTANCE_LABEL.lock() {
+        *instance_label = cfg.instance_label.clone();
+    }
+}
+
+/// Sends the escalation notice of a service once it failed enough times in a row
+///
+/// The notice is noted under the lock and sent after releasing it, so a full channel of
+/// the notified service doesn't hold up the failures of other services.
+///
+/// # Arguments
+/// * `service` - Log prefix of the failing service, ie: "MATRIX" or "CHAT WEBHOOK"
+/// * `failures` - Consecutive failures of the service so far
+pub fn record_failure(service: &str, failures: u32) {
+    let notices: Vec<(usize, String, TxClient)> = {
+        let mut escalations = match ESCALATIONS.lock() {
+            Ok(escalations) => escalations,
+            Err(_) => return,
+        };
+
+        escalations.iter_mut()
+            .enumerate()
+            .filter(|(_, escalation)| matches_service(&escalation.rule.service, service))
+            .filter(|(_, escalation)| !escalation.escalated && failures >= escalation.rule.after_failures)
+            .map(|(index, escalation)| {
+                escalation.escalated = true;
+                (index, escalation.rule.service.clone(), escalation.client.clone())
+            })
+            .collect()
+    };
+
+    for (index, rule_service, client) in notices {
+        if client.send(BvrChirpMessage::system(escalation_text(&rule_service, failures))).is_err() {
+            warn!("ESCALATION: {} client stopped, can't report failures of {}", client.name, rule_service);
+            // Not escalated after all, the next failure tries again
+            if let Ok(mut escalations) = ESCALATIONS.lock() {
+                if let Some(escalation) = escalations.get_mut(index) {
+                    escalation.escalated = false;
+                }
+            }
+            continue;
+        }
+        warn!("ESCALATION: {} failed {} times in a row, notified {}", rule_service, failures, client.name);
+    }
+}
+
+/// Re-arms the escalation of a service once it delivers again
+///
+/// # Arguments
+/// * `service` - Log prefix of the service, ie: "MATRIX" or "CHAT WEBHOOK"
+pub fn record_recovery(service: &str) {
+    if let Ok(mut escalations) = ESCALATIONS.lock() {
+        for escalation in escalations.iter_mut().filter(|escalation| matches_service(&escalation.rule.service, service)) {
+            if escalation.escalated {
+                info!("ESCALATION: {} recovered, a new outage will be reported again", escalation.rule.service);
+                escalation.escalated = false;
+            }
+        }
+    }
+}
+
+/// Returns true if a service name from the config, ie: "chat_webhook", is the service with a log prefix, ie: "CHAT WEBHOOK"
+fn matches_service(name: &str, prefix: &str) -> bool {
+    prefix.replace(' ', "_").eq_ignore_ascii_case(name)
+}
+
+/// Text of an escalation notice, ie: "⚠️ BVR Chirp: matrix failed 5 times in a row, alerts are being missed"
+fn escalation_text(service: &str, failures: u32) -> String {
+    let name = match INSTANCE_LABEL.lock() {
+        Ok(instance_label) if !instance_label.is_empty() => format!("BVR Chirp ({})", instance_label),
+        _ => "BVR Chirp".to_string(),
+    };
+
+    format!("⚠️ {}: {} failed {} times in a row, alerts are being missed until it recovers", name, service, failures)
+}
@@ -0,0 +1,97 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Keys that are set until a deadline, ie: muted cameras
+///
+/// Expired keys are removed the next time they're looked up.
+pub struct ExpiringKeys<K> {
+    /// Keys and until when they're set, `None` until they're removed
+    keys: Mutex<HashMap<K, Option<Instant>>>,
+}
+
+impl<K> Default for ExpiringKeys<K> {
+    fn default() -> ExpiringKeys<K> {
+        ExpiringKeys { keys: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl<K: Eq + Hash> ExpiringKeys<K> {
+    /// Sets a key, replacing its previous deadline
+    ///
+    /// # Arguments
+    /// * `key` - The key to set
+    /// * `until` - When the key expires, `None` to keep it until it's removed
+    pub fn insert(&self, key: K, until: Option<Instant>) {
+        if let Ok(mut keys) = self.keys.lock() {
+            keys.insert(key, until);
+        }
+    }
+
+    /// Returns true if the key is set and hasn't expired
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let mut keys = match self.keys.lock() {
+            Ok(keys) => keys,
+            Err(_) => return false,
+        };
+
+        match keys.get(key) {
+            Some(None) => true,
+            Some(Some(until)) if Instant::now() < *until => true,
+            Some(_) => {
+                keys.remove(key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Clears a key
+    ///
+    /// # Returns
+    /// * `true` if the key was set, even if it had expired
+    pub fn remove<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        match self.keys.lock() {
+            Ok(mut keys) => keys.remove(key).is_some(),
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn key_is_set_until_it_expires() {
+        let keys = ExpiringKeys::default();
+        keys.insert("front".to_string(), Some(Instant::now() + Duration::from_secs(60)));
+        keys.insert("back".to_string(), Some(Instant::now()));
+
+        assert!(keys.contains("front"));
+        assert!(!keys.contains("back"));
+        assert!(!keys.remove("back"), "an expired key is dropped when it's looked up");
+    }
+
+    #[test]
+    fn key_without_deadline_is_set_until_removed() {
+        let keys = ExpiringKeys::default();
+        keys.insert("porch".to_string(), None);
+
+        assert!(keys.contains("porch"));
+        assert!(keys.remove("porch"));
+        assert!(!keys.contains("porch"));
+        assert!(!keys.remove("porch"));
+    }
+}
@@ -0,0 +1,31 @@
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use crate::bvr_chirp_message::BvrChirpMessage;
+use crate::expiring_keys::ExpiringKeys;
+
+/// Camera and detections combinations marked as false positives, and until when they're suppressed
+static SUPPRESSED: Lazy<ExpiringKeys<(String, String)>> = Lazy::new(ExpiringKeys::default);
+
+/// Suppresses alerts with the same camera and detections as a false positive alert
+///
+/// # Arguments
+/// * `bvr_msg` - The alert that was marked as a false positive
+/// * `suppress_for` - How long to suppress matching alerts
+pub fn mark(bvr_msg: &BvrChirpMessage, suppress_for: Duration) {
+    SUPPRESSED.insert(key(bvr_msg), Some(Instant::now() + suppress_for));
+}
+
+/// Returns true if the alert's camera and detections were recently marked as a false positive
+pub fn is_suppressed(bvr_msg: &BvrChirpMessage) -> bool {
+    SUPPRESSED.contains(&key(bvr_msg))
+}
+
+/// Camera name and detections normalized so "car, person" and "Person;car" match
+fn key(bvr_msg: &BvrChirpMessage) -> (String, String) {
+    let mut detections: Vec<String> = bvr_msg.detection_list.iter()
+        .map(|detection| detection.to_lowercase())
+        .collect();
+    detections.sort();
+    detections.dedup();
+    (bvr_msg.camera_name.clone(), detections.join(","))
+}
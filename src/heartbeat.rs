@@ -0,0 +1,52 @@
+use std::thread;
+use std::time::Duration;
+use crate::bvr_chirp_message::BvrChirpMessage;
+use crate::clients::mqtt_client::TxClient;
+use crate::recent_alerts;
+use log::{info, warn};
+
+/// Starts a thread that periodically sends a status message saying the bot is running
+///
+/// # Arguments
+/// * `interval` - Time between heartbeats
+/// * `instance_label` - Name of this instance shown in the message, may be empty
+/// * `client` - Channel sender of the messaging client to send the heartbeats through
+pub fn spawn(interval: Duration, instance_label: String, client: TxClient) {
+    info!("HEARTBEAT: Sending a heartbeat to {} every {}s", client.name, interval.as_secs());
+
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+
+        if client.send(BvrChirpMessage::system(heartbeat_text(&instance_label))).is_err() {
+            warn!("HEARTBEAT: {} client stopped, no longer sending heartbeats", client.name);
+            break;
+        }
+    });
+}
+
+/// Text of a heartbeat, ie: "✅ BVR Chirp is running, last alert 3h ago"
+fn heartbeat_text(instance_label: &str) -> String {
+    let name = if instance_label.is_empty() {
+        "BVR Chirp".to_string()
+    } else {
+        format!("BVR Chirp ({})", instance_label)
+    };
+
+    let last_alert = match recent_alerts::latest(1).first() {
+        Some(alert) => format!("last alert {} ago", format_elapsed(alert.recorded_at.elapsed())),
+        None => "no alerts since it started".to_string(),
+    };
+
+    format!("✅ {} is running, {}", name, last_alert)
+}
+
+/// Formats a duration in its largest whole unit, ie: "45s", "12m", "3h" or "2d"
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
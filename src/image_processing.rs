@@ -0,0 +1,435 @@
+use std::borrow::Cow;
+use std::io::Cursor;
+use ab_glyph::{FontRef, PxScale};
+use exif::{In, Reader, Tag};
+use image::codecs::jpeg::JpegEncoder;
+use image::error::{EncodingError, ImageFormatHint};
+use image::{imageops, DynamicImage, GenericImage, ImageError, ImageFormat, ImageResult, Luma, Rgb};
+use imageproc::drawing::{draw_filled_rect_mut, draw_text_mut, text_size};
+use imageproc::rect::Rect;
+use qrcode::QrCode;
+use log::{error, warn};
+use crate::bvr_chirp_config::OverlayPosition;
+
+/// JPEG quality used when an image has to be re-encoded
+const REENCODE_QUALITY: u8 = 90;
+
+/// Rotates and flips an image to upright according to its EXIF orientation tag
+///
+/// The corrected image is re-encoded as JPEG without EXIF data, so clients that
+/// also honour the tag don't rotate it a second time.
+///
+/// # Arguments
+/// * `image` - Encoded image data
+///
+/// # Returns
+/// * The corrected image, or the original data if it's already upright, has no
+///   orientation tag, or can't be decoded
+pub fn fix_orientation(image: Vec<u8>) -> Vec<u8> {
+    let orientation = match read_orientation(&image) {
+        Some(orientation) if (2..=8).contains(&orientation) => orientation,
+        _ => return image,
+    };
+
+    let corrected = image::load_from_memory(&image).map(|img| match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        _ => img.rotate270(),
+    });
+
+    match corrected.and_then(|img| encode_jpeg(&img, REENCODE_QUALITY)) {
+        Ok(corrected) => corrected,
+        Err(err) => {
+            error!("IMAGE: Failed to correct orientation: {}", err);
+            image
+        }
+    }
+}
+
+/// How much the JPEG quality is lowered on each attempt to fit an image under a size cap
+const QUALITY_STEP: u8 = 10;
+
+/// Re-encodes an image at decreasing JPEG quality until it's no larger than `max_bytes`
+///
+/// Starts at the usual re-encode quality and steps down to `min_quality`. If the
+/// image still doesn't fit, the smallest attempt is used so the upload is at least
+/// as close to the limit as the quality floor allows.
+///
+/// # Arguments
+/// * `image` - Encoded image data
+/// * `max_bytes` - Largest acceptable size, 0 leaves the image as is
+/// * `min_quality` - Lowest JPEG quality (1-100) to try
+///
+/// # Returns
+/// * The original data if it already fits or can't be decoded, otherwise the re-encoded image
+pub fn fit_to_size(image: &[u8], max_bytes: usize, min_quality: u8) -> Cow<'_, [u8]> {
+    if max_bytes == 0 || image.len() <= max_bytes {
+        return Cow::Borrowed(image);
+    }
+
+    let decoded = match image::load_from_memory(image) {
+        Ok(decoded) => decoded,
+        Err(err) => {
+            error!("IMAGE: Failed to decode image to reduce its size: {}", err);
+            return Cow::Borrowed(image);
+        }
+    };
+
+    let min_quality = min_quality.clamp(1, REENCODE_QUALITY);
+    let mut quality = REENCODE_QUALITY;
+    let mut smallest: Option<Vec<u8>> = None;
+    loop {
+        match encode_jpeg(&decoded, quality) {
+            Ok(encoded) if encoded.len() <= max_bytes => return Cow::Owned(encoded),
+            Ok(encoded) => {
+                if smallest.as_ref().is_none_or(|smallest| encoded.len() < smallest.len()) {
+                    smallest = Some(encoded);
+                }
+            }
+            Err(err) => {
+                error!("IMAGE: Failed to re-encode image at quality {}: {}", quality, err);
+                break;
+            }
+        }
+
+        if quality <= min_quality {
+            break;
+        }
+        quality = quality.saturating_sub(QUALITY_STEP).max(min_quality);
+    }
+
+    warn!("IMAGE: Image is still over {} bytes at quality {}, sending the smallest version", max_bytes, min_quality);
+    match smallest {
+        Some(smallest) if smallest.len() < image.len() => Cow::Owned(smallest),
+        _ => Cow::Borrowed(image),
+    }
+}
+
+/// Quality (0-100) of lossy WebP images, around the size of a JPEG at `REENCODE_QUALITY`
+const WEBP_QUALITY: f32 = 80.0;
+
+/// Re-encodes an image in another format, ie: WebP for smaller uploads
+///
+/// WebP is encoded lossy at `WEBP_QUALITY`, PNG is always lossless.
+///
+/// # Arguments
+/// * `image` - Encoded image data
+/// * `format` - Format to encode the image in
+///
+/// # Returns
+/// * The original data if it's already in that format, can't be converted or the converted
+///   image isn't smaller, otherwise the re-encoded image
+pub fn convert(image: &[u8], format: ImageFormat) -> Cow<'_, [u8]> {
+    if image::guess_format(image).is_ok_and(|current| current == format) {
+        return Cow::Borrowed(image);
+    }
+
+    let converted = image::load_from_memory(image).and_then(|decoded| {
+        // WebP and PNG have no use for the alpha channel a camera frame doesn't have
+        let rgb = decoded.to_rgb8();
+        if format == ImageFormat::WebP {
+            // The image crate only writes lossless WebP, which is usually larger than the camera's JPEG
+            return webp::Encoder::from_rgb(&rgb, rgb.width(), rgb.height())
+                .encode_simple(false, WEBP_QUALITY)
+                .map(|encoded| encoded.to_vec())
+                .map_err(|err| ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(format), format!("{:?}", err))));
+        }
+
+        let mut encoded = Vec::new();
+        DynamicImage::ImageRgb8(rgb).write_to(&mut Cursor::new(&mut encoded), format)?;
+        Ok(encoded)
+    });
+
+    match converted {
+        Ok(converted) if converted.len() < image.len() => Cow::Owned(converted),
+        Ok(converted) => {
+            warn!("IMAGE: Image is larger as {:?} ({} bytes) than as received ({} bytes), sending it as received",
+                  format, converted.len(), image.len());
+            Cow::Borrowed(image)
+        }
+        Err(err) => {
+            error!("IMAGE: Failed to convert image to {:?}: {}", format, err);
+            Cow::Borrowed(image)
+        }
+    }
+}
+
+/// The QR code's side is the image's shorter side divided by this
+const QR_CODE_SCALE: u32 = 4;
+
+/// Draws a QR code of a link in the bottom right corner of an image
+///
+/// # Arguments
+/// * `image` - Encoded image data
+/// * `link` - Link the QR code opens
+///
+/// # Returns
+/// * The image with the QR code, re-encoded as JPEG, or the original data if it
+///   can't be decoded or the link doesn't fit in a QR code
+pub fn add_qr_code(image: Vec<u8>, link: &str) -> Vec<u8> {
+    let code = match QrCode::new(link.as_bytes()) {
+        Ok(code) => code,
+        Err(err) => {
+            error!("IMAGE: Failed to make a QR code of the alert link: {}", err);
+            return image;
+        }
+    };
+    let mut decoded = match image::load_from_memory(&image) {
+        Ok(decoded) => decoded.to_rgb8(),
+        Err(err) => {
+            error!("IMAGE: Failed to decode image to add a QR code: {}", err);
+            return image;
+        }
+    };
+
+    let size = decoded.width().min(decoded.height()) / QR_CODE_SCALE;
+    let qr = code.render::<Luma<u8>>()
+        .quiet_zone(true)
+        .min_dimensions(size, size)
+        .build();
+    let qr = DynamicImage::ImageLuma8(qr).to_rgb8();
+
+    let x = decoded.width().saturating_sub(qr.width());
+    let y = decoded.height().saturating_sub(qr.height());
+    imageops::overlay(&mut decoded, &qr, x as i64, y as i64);
+
+    match encode_jpeg(&DynamicImage::ImageRgb8(decoded), REENCODE_QUALITY) {
+        Ok(encoded) => encoded,
+        Err(err) => {
+            error!("IMAGE: Failed to encode image with a QR code: {}", err);
+            image
+        }
+    }
+}
+
+/// Font of the text overlay, bundled so snapshots are labeled the same on every system
+static OVERLAY_FONT: &[u8] = include_bytes!("../assets/fonts/DejaVuSansMono-Bold.ttf");
+/// Height of the overlay text as a fraction of the image's height
+const OVERLAY_TEXT_SCALE: f32 = 1.0 / 28.0;
+/// Smallest height of the overlay text in pixels, so it stays readable on small snapshots
+const OVERLAY_MIN_TEXT_HEIGHT: f32 = 12.0;
+
+/// Burns a line of text into a corner of an image on a black box, like a CCTV overlay
+///
+/// # Arguments
+/// * `image` - Encoded image data
+/// * `text` - Text to draw, ie: the camera name and time of the alert
+/// * `position` - Corner the text is drawn in
+/// * `color` - RGB color of the text
+///
+/// # Returns
+/// * The image with the text, re-encoded as JPEG, or the original data if it can't be decoded
+pub fn add_text_overlay(image: Vec<u8>, text: &str, position: OverlayPosition, color: [u8; 3]) -> Vec<u8> {
+    if text.trim().is_empty() {
+        return image;
+    }
+    let font = match FontRef::try_from_slice(OVERLAY_FONT) {
+        Ok(font) => font,
+        Err(err) => {
+            error!("IMAGE: Failed to load the overlay font: {}", err);
+            return image;
+        }
+    };
+    let mut decoded = match image::load_from_memory(&image) {
+        Ok(decoded) => decoded.to_rgb8(),
+        Err(err) => {
+            error!("IMAGE: Failed to decode image to add the text overlay: {}", err);
+            return image;
+        }
+    };
+
+    let scale = PxScale::from((decoded.height() as f32 * OVERLAY_TEXT_SCALE).max(OVERLAY_MIN_TEXT_HEIGHT));
+    let (text_width, text_height) = text_size(scale, &font, text);
+    let padding = (text_height / 2).max(1);
+    let box_width = text_width + padding * 2;
+    let box_height = text_height + padding * 2;
+
+    let x = match position {
+        OverlayPosition::TopLeft | OverlayPosition::BottomLeft => 0,
+        OverlayPosition::TopRight | OverlayPosition::BottomRight => decoded.width().saturating_sub(box_width),
+    };
+    let y = match position {
+        OverlayPosition::TopLeft | OverlayPosition::TopRight => 0,
+        OverlayPosition::BottomLeft | OverlayPosition::BottomRight => decoded.height().saturating_sub(box_height),
+    };
+
+    draw_filled_rect_mut(&mut decoded, Rect::at(x as i32, y as i32).of_size(box_width, box_height), Rgb([0, 0, 0]));
+    draw_text_mut(&mut decoded, Rgb(color), (x + padding) as i32, (y + padding) as i32, scale, &font, text);
+
+    match encode_jpeg(&DynamicImage::ImageRgb8(decoded), REENCODE_QUALITY) {
+        Ok(encoded) => encoded,
+        Err(err) => {
+            error!("IMAGE: Failed to encode image with the text overlay: {}", err);
+            image
+        }
+    }
+}
+
+/// Difference in brightness (0-255) above which a pixel counts as changed in a motion delta
+const MOTION_DELTA_THRESHOLD: u8 = 40;
+/// Highlight color of the changed pixels in a motion delta
+const MOTION_DELTA_HIGHLIGHT: Rgb<u8> = Rgb([255, 0, 0]);
+
+/// Puts two frames of a camera side by side, highlighting in red what changed in the current one
+///
+/// # Arguments
+/// * `previous` - Encoded earlier frame, scaled to the current frame's size if they differ
+/// * `current` - Encoded current frame
+///
+/// # Returns
+/// * `Some(image)` JPEG twice as wide as the current frame, previous on the left
+/// * `None` if either frame can't be decoded or the result can't be encoded
+pub fn motion_delta(previous: &[u8], current: &[u8]) -> Option<Vec<u8>> {
+    let current = image::load_from_memory(current).ok()?.to_rgb8();
+    let (width, height) = current.dimensions();
+    let mut previous = image::load_from_memory(previous).ok()?.to_rgb8();
+    if previous.dimensions() != (width, height) {
+        previous = imageops::resize(&previous, width, height, imageops::FilterType::Triangle);
+    }
+
+    let previous_luma = DynamicImage::ImageRgb8(previous.clone()).to_luma8();
+    let current_luma = DynamicImage::ImageRgb8(current.clone()).to_luma8();
+    let mut highlighted = current;
+    for (x, y, pixel) in highlighted.enumerate_pixels_mut() {
+        if previous_luma.get_pixel(x, y)[0].abs_diff(current_luma.get_pixel(x, y)[0]) > MOTION_DELTA_THRESHOLD {
+            // Blended half way, the changed object stays recognizable
+            for (channel, highlight) in pixel.0.iter_mut().zip(MOTION_DELTA_HIGHLIGHT.0) {
+                *channel = ((*channel as u16 + highlight as u16) / 2) as u8;
+            }
+        }
+    }
+
+    let mut side_by_side = image::RgbImage::new(width * 2, height);
+    side_by_side.copy_from(&previous, 0, 0).ok()?;
+    side_by_side.copy_from(&highlighted, width, 0).ok()?;
+    encode_jpeg(&DynamicImage::ImageRgb8(side_by_side), REENCODE_QUALITY).ok()
+}
+
+/// MIME type of an encoded image, from its header
+///
+/// # Arguments
+/// * `image` - Encoded image data
+///
+/// # Returns
+/// * MIME type like "image/webp", "image/jpeg" when the format isn't recognized
+pub fn mime_type(image: &[u8]) -> &'static str {
+    image::guess_format(image).map_or("image/jpeg", |format| format.to_mime_type())
+}
+
+/// Largest dimension of the thumbnails shown in the web UI and sent as fast previews
+const THUMBNAIL_MAX_DIMENSION: u32 = 320;
+/// JPEG quality of the thumbnails, they're small previews
+const THUMBNAIL_QUALITY: u8 = 70;
+
+/// Scales an image down to a small JPEG preview
+///
+/// # Arguments
+/// * `image` - Encoded image data
+///
+/// # Returns
+/// * `Some(thumbnail)` JPEG no larger than 320 pixels on its longest side
+/// * `None` if the image can't be decoded or encoded
+pub fn thumbnail(image: &[u8]) -> Option<Vec<u8>> {
+    let thumbnail = image::load_from_memory(image).ok()?
+        .thumbnail(THUMBNAIL_MAX_DIMENSION, THUMBNAIL_MAX_DIMENSION);
+    encode_jpeg(&thumbnail, THUMBNAIL_QUALITY).ok()
+}
+
+/// Largest dimension images are scaled to before measuring sharpness, to keep it fast
+const SHARPNESS_MAX_DIMENSION: u32 = 640;
+
+/// Measures how sharp (in focus) an image is
+///
+/// Uses the variance of the Laplacian of the grayscale image: blurry frames have
+/// few strong edges and score low. Scores are only comparable between frames of
+/// the same camera.
+///
+/// # Arguments
+/// * `image` - Encoded image data
+///
+/// # Returns
+/// * `Some(score)` where higher is sharper
+/// * `None` if the image can't be decoded
+pub fn sharpness(image: &[u8]) -> Option<f64> {
+    let gray = image::load_from_memory(image).ok()?
+        .thumbnail(SHARPNESS_MAX_DIMENSION, SHARPNESS_MAX_DIMENSION)
+        .to_luma8();
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return Some(0.0);
+    }
+
+    let pixel = |x: u32, y: u32| gray.get_pixel(x, y)[0] as f64;
+    let mut sum = 0.0;
+    let mut sum_squares = 0.0;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let laplacian = pixel(x - 1, y) + pixel(x + 1, y) + pixel(x, y - 1) + pixel(x, y + 1) - 4.0 * pixel(x, y);
+            sum += laplacian;
+            sum_squares += laplacian * laplacian;
+        }
+    }
+
+    let count = ((width - 2) * (height - 2)) as f64;
+    let mean = sum / count;
+    Some(sum_squares / count - mean * mean)
+}
+
+/// Largest dimension images are scaled to before measuring their brightness
+const BRIGHTNESS_MAX_DIMENSION: u32 = 320;
+
+/// Measures the brightness of an image and how much it varies
+///
+/// # Arguments
+/// * `image` - Encoded image data
+///
+/// # Returns
+/// * `Some((mean, std_dev))` of the grayscale pixel values, both on a 0-255 scale
+/// * `None` if the image can't be decoded
+pub fn brightness(image: &[u8]) -> Option<(f64, f64)> {
+    let gray = image::load_from_memory(image).ok()?
+        .thumbnail(BRIGHTNESS_MAX_DIMENSION, BRIGHTNESS_MAX_DIMENSION)
+        .to_luma8();
+
+    let count = gray.pixels().len() as f64;
+    if count == 0.0 {
+        return None;
+    }
+
+    let (sum, sum_squares) = gray.pixels()
+        .map(|pixel| pixel[0] as f64)
+        .fold((0.0, 0.0), |(sum, sum_squares), value| (sum + value, sum_squares + value * value));
+    let mean = sum / count;
+    Some((mean, (sum_squares / count - mean * mean).max(0.0).sqrt()))
+}
+
+/// Reads the width and height of an image from its header, without decoding it
+///
+/// # Arguments
+/// * `image` - Encoded image data
+///
+/// # Returns
+/// * `Some((width, height))` in pixels
+/// * `None` if the image format isn't recognized
+pub fn dimensions(image: &[u8]) -> Option<(u32, u32)> {
+    image::ImageReader::new(Cursor::new(image))
+        .with_guessed_format().ok()?
+        .into_dimensions().ok()
+}
+
+/// Reads the EXIF orientation tag (1-8) from an encoded image
+fn read_orientation(image: &[u8]) -> Option<u32> {
+    let exif = Reader::new().read_from_container(&mut Cursor::new(image)).ok()?;
+    exif.get_field(Tag::Orientation, In::PRIMARY)?.value.get_uint(0)
+}
+
+/// Encodes an image as JPEG with the given quality (1-100)
+fn encode_jpeg(img: &DynamicImage, quality: u8) -> ImageResult<Vec<u8>> {
+    let mut encoded = Vec::new();
+    JpegEncoder::new_with_quality(&mut encoded, quality).encode_image(&img.to_rgb8())?;
+    Ok(encoded)
+}
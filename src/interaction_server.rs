@@ -0,0 +1,288 @@
+use std::error::Error;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use tiny_http::{Header, Method, Request, Response};
+use crate::{acknowledgements, address, recent_alerts};
+use crate::bvr_chirp_config::BvrChirpConfig;
+use crate::clients::discord_client::RECENT_COMMAND;
+use crate::recent_alerts::{DEFAULT_RECENT_COUNT, RECENT_ALERTS_CAPACITY};
+use log::{error, info, warn};
+
+/// Slack and Discord requests older than this are rejected to prevent replays
+const MAX_REQUEST_AGE_SECS: u64 = 300;
+
+/// Prefix of the Discord button custom ID, followed by the camera name
+pub const DISCORD_ACK_PREFIX: &str = "ack:";
+
+/// Discord limits message content to 2000 characters
+const DISCORD_CONTENT_LIMIT: usize = 2000;
+
+/// Action ID of the Slack "Acknowledge" button, its value is the camera name
+pub const SLACK_ACK_ACTION_ID: &str = "acknowledge-action";
+
+/// Runs an HTTP server receiving button clicks from Slack and Discord
+///
+/// Serves `POST /slack/interactions` (Slack interactivity request URL) and
+/// `POST /discord/interactions` (Discord interactions endpoint URL). Requests are
+/// only acted on if their signature verifies against the configured secrets. An
+/// "Acknowledge" click mutes further alerts for that camera for `ack_mute_secs`.
+/// Blocks for as long as the server is running.
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig containing the port, mute duration and signing secrets
+///
+/// # Returns
+/// * `Err` if the server can't bind to the port
+pub fn run(cfg: BvrChirpConfig) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let server = address::bind_server(cfg.interaction_server_port)?;
+    info!("INTERACTIONS: Server listening on port {}", cfg.interaction_server_port);
+
+    for mut request in server.incoming_requests() {
+        let mut body = String::new();
+        if let Err(err) = request.as_reader().read_to_string(&mut body) {
+            error!("INTERACTIONS: Failed to read request body: {}", err);
+            continue;
+        }
+
+        let response = match (request.method(), request.url()) {
+            (Method::Post, "/slack/interactions") => handle_slack(&cfg, &request, &body),
+            (Method::Post, "/discord/interactions") => handle_discord(&cfg, &request, &body),
+            _ => Response::from_string("Not found").with_status_code(404),
+        };
+
+        if let Err(err) = request.respond(response) {
+            error!("INTERACTIONS: Failed to respond to request: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles a Slack interactive payload, acknowledging the camera on an "Acknowledge" click
+fn handle_slack(cfg: &BvrChirpConfig, request: &Request, body: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    let signature = header_value(request, "X-Slack-Signature").unwrap_or_default();
+    let timestamp = header_value(request, "X-Slack-Request-Timestamp").unwrap_or_default();
+
+    if !verify_slack_signature(&cfg.slack_config.signing_secret, &timestamp, body, &signature, unix_now()) {
+        warn!("INTERACTIONS: Rejected Slack request with an invalid signature");
+        return Response::from_string("Invalid signature").with_status_code(401);
+    }
+
+    // Slack sends the interaction as JSON in the form-encoded `payload` field
+    let payload = url::form_urlencoded::parse(body.as_bytes())
+        .find(|(key, _)| key == "payload")
+        .and_then(|(_, payload)| serde_json::from_str::<Value>(&payload).ok());
+
+    let payload = match payload {
+        Some(payload) => payload,
+        None => return Response::from_string("Invalid payload").with_status_code(400),
+    };
+
+    let actions = payload["actions"].as_array().cloned().unwrap_or_default();
+    for action in actions {
+        if action["action_id"].as_str() == Some(SLACK_ACK_ACTION_ID) {
+            if let Some(camera) = action["value"].as_str() {
+                let user = payload["user"]["username"].as_str()
+                    .or_else(|| payload["user"]["name"].as_str())
+                    .unwrap_or("unknown user");
+                acknowledge(cfg, camera, "Slack", user);
+            }
+        }
+    }
+
+    Response::from_string("")
+}
+
+/// Handles a Discord interaction, answering pings and acknowledging the camera on a button click
+fn handle_discord(cfg: &BvrChirpConfig, request: &Request, body: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    let signature = header_value(request, "X-Signature-Ed25519").unwrap_or_default();
+    let timestamp = header_value(request, "X-Signature-Timestamp").unwrap_or_default();
+
+    // Discord requires invalid signatures to be rejected, it checks this when the URL is configured
+    if !verify_discord_signature(&cfg.discord_config.public_key, &timestamp, body, &signature, unix_now()) {
+        warn!("INTERACTIONS: Rejected Discord request with an invalid signature");
+        return Response::from_string("Invalid signature").with_status_code(401);
+    }
+
+    let payload: Value = match serde_json::from_str(body) {
+        Ok(payload) => payload,
+        Err(_) => return Response::from_string("Invalid payload").with_status_code(400),
+    };
+
+    let reply = match payload["type"].as_u64() {
+        // PING
+        Some(1) => json!({ "type": 1 }),
+        // APPLICATION_COMMAND, slash commands are sent here instead of the gateway once this endpoint is set
+        Some(2) if payload["data"]["name"].as_str() == Some(RECENT_COMMAND) => {
+            let count = payload["data"]["options"].as_array()
+                .and_then(|options| options.iter().find(|option| option["name"] == "count"))
+                .and_then(|option| option["value"].as_u64())
+                .map(|count| count.clamp(1, RECENT_ALERTS_CAPACITY as u64) as usize)
+                .unwrap_or(DEFAULT_RECENT_COUNT);
+
+            json!({
+                "type": 4,
+                "data": {
+                    "content": recent_alerts::summary(cfg, count, DISCORD_CONTENT_LIMIT),
+                    "flags": 64
+                }
+            })
+        }
+        // MESSAGE_COMPONENT
+        Some(3) => {
+            let camera = payload["data"]["custom_id"].as_str()
+                .and_then(|custom_id| custom_id.strip_prefix(DISCORD_ACK_PREFIX));
+
+            match camera {
+                Some(camera) => {
+                    let user = payload["member"]["user"]["username"].as_str()
+                        .or_else(|| payload["user"]["username"].as_str())
+                        .unwrap_or("unknown user");
+                    acknowledge(cfg, camera, "Discord", user);
+
+                    json!({
+                        "type": 4,
+                        "data": {
+                            "content": format!("Acknowledged {} camera, muted for {} minutes", camera, cfg.ack_mute_secs / 60),
+                            "flags": 64
+                        }
+                    })
+                }
+                None => return Response::from_string("Unknown component").with_status_code(400),
+            }
+        }
+        _ => return Response::from_string("Unsupported interaction").with_status_code(400),
+    };
+
+    let content_type = Header::from_bytes("Content-Type", "application/json")
+        .expect("static header is valid");
+    Response::from_string(reply.to_string()).with_header(content_type)
+}
+
+/// Mutes a camera's alerts and logs who acknowledged it
+fn acknowledge(cfg: &BvrChirpConfig, camera: &str, service: &str, user: &str) {
+    acknowledgements::acknowledge(camera, Duration::from_secs(cfg.ack_mute_secs));
+    info!("INTERACTIONS: {} camera acknowledged by {} on {}, muted for {}s",
+             camera, user, service, cfg.ack_mute_secs);
+}
+
+/// Returns the value of a request header, matched case-insensitively
+fn header_value(request: &Request, name: &str) -> Option<String> {
+    request.headers().iter()
+        .find(|header| header.field.as_str().as_str().eq_ignore_ascii_case(name))
+        .map(|header| header.value.as_str().to_string())
+}
+
+/// Seconds since the Unix epoch
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or(0)
+}
+
+/// Returns true if a request timestamp, in seconds since the Unix epoch, is within `MAX_REQUEST_AGE_SECS` of `now`
+fn is_recent(timestamp: &str, now: u64) -> bool {
+    timestamp.parse::<u64>().is_ok_and(|sent| now.abs_diff(sent) <= MAX_REQUEST_AGE_SECS)
+}
+
+/// Verifies a [Slack request signature](https://api.slack.com/authentication/verifying-requests-from-slack)
+///
+/// # Arguments
+/// * `now` - Current time in seconds since the Unix epoch, requests too far from it are rejected
+fn verify_slack_signature(signing_secret: &str, timestamp: &str, body: &str, signature: &str, now: u64) -> bool {
+    if signing_secret.is_empty() || !is_recent(timestamp, now) {
+        return false;
+    }
+
+    let signature = match signature.strip_prefix("v0=").and_then(|hex_sig| hex::decode(hex_sig).ok()) {
+        Some(signature) => signature,
+        None => return false,
+    };
+
+    let mut mac = match Hmac::<Sha256>::new_from_slice(signing_secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(format!("v0:{}:{}", timestamp, body).as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Verifies a [Discord interaction signature](https://discord.com/developers/docs/interactions/overview#setting-up-an-endpoint-validating-security-request-headers)
+///
+/// # Arguments
+/// * `now` - Current time in seconds since the Unix epoch, requests too far from it are rejected
+fn verify_discord_signature(public_key: &str, timestamp: &str, body: &str, signature: &str, now: u64) -> bool {
+    if !is_recent(timestamp, now) {
+        return false;
+    }
+
+    let key = hex::decode(public_key).ok()
+        .and_then(|key| <[u8; 32]>::try_from(key).ok())
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok());
+    let signature = hex::decode(signature).ok()
+        .and_then(|signature| <[u8; 64]>::try_from(signature).ok())
+        .map(|signature| Signature::from_bytes(&signature));
+
+    match (key, signature) {
+        (Some(key), Some(signature)) => key.verify(format!("{}{}", timestamp, body).as_bytes(), &signature).is_ok(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMESTAMP: &str = "1718000000";
+    const NOW: u64 = 1718000060;
+
+    const SLACK_SECRET: &str = "8f742231b10e8888abcd99yyyzzz85a5";
+    const SLACK_BODY: &str = "payload=%7B%22type%22%3A%22block_actions%22%7D";
+    const SLACK_SIGNATURE: &str = "v0=2620956c38d3a70a9f7362e7b92397d340b9518ed3ea43178e8fdb55b2570223";
+
+    // Ed25519 key pair from the seed 00 01 02 .. 1f
+    const DISCORD_PUBLIC_KEY: &str = "03a107bff3ce10be1d70dd18e74bc09967e4d6309ba50d5f1ddc8664125531b8";
+    const DISCORD_BODY: &str = r#"{"type":3,"data":{"custom_id":"ack:Driveway"}}"#;
+    const DISCORD_SIGNATURE: &str = "56f47d8669703f98d83c35a2442946288beef1cc777fc4a3b4ccf2a89fd76c62\
+                                     a7d803fc5a227c2adaaf18bd1cda910c469fc3b13054ec4ef951f7930ae44204";
+
+    #[test]
+    fn slack_signature_valid() {
+        assert!(verify_slack_signature(SLACK_SECRET, TIMESTAMP, SLACK_BODY, SLACK_SIGNATURE, NOW));
+    }
+
+    #[test]
+    fn slack_signature_tampered_body() {
+        let body = SLACK_BODY.replace("block_actions", "view_submission");
+        assert!(!verify_slack_signature(SLACK_SECRET, TIMESTAMP, &body, SLACK_SIGNATURE, NOW));
+    }
+
+    #[test]
+    fn slack_signature_stale_timestamp() {
+        let now = 1718000000 + MAX_REQUEST_AGE_SECS + 1;
+        assert!(!verify_slack_signature(SLACK_SECRET, TIMESTAMP, SLACK_BODY, SLACK_SIGNATURE, now));
+    }
+
+    #[test]
+    fn slack_signature_without_secret() {
+        assert!(!verify_slack_signature("", TIMESTAMP, SLACK_BODY, SLACK_SIGNATURE, NOW));
+    }
+
+    #[test]
+    fn discord_signature_valid() {
+        assert!(verify_discord_signature(DISCORD_PUBLIC_KEY, TIMESTAMP, DISCORD_BODY, DISCORD_SIGNATURE, NOW));
+    }
+
+    #[test]
+    fn discord_signature_tampered_body() {
+        let body = DISCORD_BODY.replace("Driveway", "Backyard");
+        assert!(!verify_discord_signature(DISCORD_PUBLIC_KEY, TIMESTAMP, &body, DISCORD_SIGNATURE, NOW));
+    }
+
+    #[test]
+    fn discord_signature_stale_timestamp() {
+        let now = 1718000000 + MAX_REQUEST_AGE_SECS + 1;
+        assert!(!verify_discord_signature(DISCORD_PUBLIC_KEY, TIMESTAMP, DISCORD_BODY, DISCORD_SIGNATURE, now));
+    }
+}
@@ -0,0 +1,46 @@
+use std::error::Error;
+use std::io::Write;
+use flexi_logger::{Cleanup, Criterion, DeferredNow, Duplicate, FileSpec, Logger, LoggerHandle, Naming};
+use log::Record;
+use crate::bvr_chirp_config::BvrChirpConfig;
+
+/// Log level used when `RUST_LOG` isn't set
+const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Starts logging to the console and, if `log_file` is set, to a rotating log file
+///
+/// Console lines are the bare message, as the service manager usually adds its own
+/// timestamp. Lines in the file start with a timestamp and the level.
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the log file, its maximum size and how many old files to keep
+///
+/// # Returns
+/// * `Ok(LoggerHandle)` which must be kept for as long as the program logs
+/// * `Err` if the log file can't be opened
+pub fn init(cfg: &BvrChirpConfig) -> Result<LoggerHandle, Box<dyn Error>> {
+    let logger = Logger::try_with_env_or_str(DEFAULT_LOG_LEVEL)?;
+
+    let logger = if cfg.log_file.is_empty() {
+        logger.log_to_stdout().format(console_format)
+    } else {
+        logger.log_to_file(FileSpec::try_from(&cfg.log_file)?.suppress_timestamp())
+            .append()
+            .rotate(Criterion::Size(cfg.log_max_size), Naming::NumbersDirect, Cleanup::KeepLogFiles(cfg.log_max_files))
+            .format_for_files(file_format)
+            .duplicate_to_stdout(Duplicate::All)
+            .format_for_stdout(console_format)
+    };
+
+    Ok(logger.start()?)
+}
+
+/// Formats a console line as just the message
+fn console_format(w: &mut dyn Write, _now: &mut DeferredNow, record: &Record) -> std::io::Result<()> {
+    write!(w, "{}", record.args())
+}
+
+/// Formats a log file line, ie: "2024-06-01 14:03:22.123 INFO  MQTT: Connected to broker"
+fn file_format(w: &mut dyn Write, now: &mut DeferredNow, record: &Record) -> std::io::Result<()> {
+    write!(w, "{} {:<5} {}", now.format("%Y-%m-%d %H:%M:%S%.3f"), record.level(), record.args())
+}
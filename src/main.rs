@@ -1,14 +1,45 @@
 use std::{env, thread};
 use std::process::exit;
-use clients::{discord_client, matrix_client, slack_client, mqtt_client};
-use crate::bvr_chirp_config::BvrChirpConfig;
-use crate::bvr_chirp_message::BvrChirpMessage;
+use std::time::Duration;
+use clients::{apprise_client, chat_webhook_client, discord_client, mastodon_client, matrix_client, slack_client, mqtt_client};
+use crate::bvr_chirp_config::{BvrChirpConfig, NoServicesAction, SERVICES};
+use crate::client_runtime::ClientRuntime;
 use crate::clients::mqtt_client::TxClient;
+use log::{error, info, warn};
+use rand::Rng;
 
+mod acknowledgements;
+mod address;
+mod backoff;
+mod blue_iris;
 mod bvr_chirp_message;
 mod bvr_chirp_config;
+mod bvr_chirp_error;
+mod burst_capture;
+mod circuit_breaker;
+mod client_runtime;
 mod clients;
+mod cooldowns;
+mod daily_counts;
+mod daily_summary;
+mod dead_letter;
+mod delivery_stats;
+mod escalation;
+mod expiring_keys;
+mod false_positives;
+mod heartbeat;
+mod image_processing;
+mod interaction_server;
+mod logging;
+mod maintenance;
 mod message_templates;
+mod motion_delta;
+mod multi_cam;
+mod recent_alerts;
+mod render_templates;
+mod replay;
+mod snapshot_server;
+mod web_ui;
 
 /// BVR Chirp - A multiservice messaging bot that supports Discord and Matrix.
 ///
@@ -20,7 +51,11 @@ mod message_templates;
 /// service (discord, matrix, slack, etc)
 ///
 /// # Arguments
-/// * `args[1]` - A string slice that holds the path to the config file.
+/// * `<config>` - A string slice that holds the path to the config file.
+/// * `--render-templates` - Print each service's message for a sample alert and exit without connecting.
+/// * `--validate` - Check the config file and exit, non-zero if problems were found.
+/// * `--replay <path>` - Re-send a saved alert JSON file, or a directory of them, then exit.
+/// * `--test-mqtt` - Check that a message published on the alert topic comes back, then exit.
 ///
 /// # Errors
 /// The program will terminate if:
@@ -30,20 +65,42 @@ mod message_templates;
 /// - One of the enabled messaging services fails to start.
 
 fn main() {
-    // Indicate that the BVR Chirp bot has started
-    println!("BVR Chirp Started");
-
     // Collect command-line arguments
     let args: Vec<String> = env::args().collect();
-
-    // Check if the config file path is provided
-    if args.len() <= 1 || args[1].is_empty() {
-        eprintln!("Error: Config file path is not provided.");
+    let render_templates = args.iter().any(|arg| arg == "--render-templates");
+    let validate = args.iter().any(|arg| arg == "--validate");
+    let test_mqtt = args.iter().any(|arg| arg == "--test-mqtt");
+    let replay_index = args.iter().position(|arg| arg == "--replay");
+    let replay_path = replay_index.and_then(|index| args.get(index + 1)).cloned();
+    if replay_index.is_some() && replay_path.is_none() {
+        eprintln!("Error: --replay requires a file or directory to replay.");
         exit(1);
     }
 
+    // Check if the config file path is provided, it's the first argument that isn't an option or its value
+    let config_path = match args.iter().enumerate().skip(1)
+        .find(|(index, arg)| !arg.starts_with("--") && !arg.is_empty() && Some(index - 1) != replay_index) {
+        Some((_, config_path)) => config_path.to_string(),
+        None => {
+            eprintln!("Error: Config file path is not provided.");
+            exit(1);
+        }
+    };
+
+    // Only check the config, printing nothing but the problems found
+    if validate {
+        let problems = bvr_chirp_config::validate_config(&config_path);
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        exit(if problems.is_empty() { 0 } else { 1 });
+    }
+
+    // Indicate that the BVR Chirp bot has started
+    println!("BVR Chirp Started");
+
     // Attempt to load the configuration file
-    let cfg: BvrChirpConfig = match bvr_chirp_config::load_config(args[1].to_string()) {
+    let mut cfg: BvrChirpConfig = match bvr_chirp_config::load_config(config_path) {
         Ok(config) => config,
         Err(err) => {
             eprintln!("Error: Failed to load config file: {}", err);
@@ -51,57 +108,198 @@ fn main() {
         }
     };
 
-    let mut tx_senders: Vec<TxClient> = Vec::new();
-    // Channel for sending messages between threads
+    // Render the templates for a sample alert without sending anything
+    if render_templates {
+        exit(if render_templates::run(&cfg) { 0 } else { 1 });
+    }
+
+    // Log to the console and the rotating log file, the handle must live until the program exits
+    let _logger = match logging::init(&cfg) {
+        Ok(logger) => logger,
+        Err(err) => {
+            eprintln!("Error: Failed to start logging: {}", err);
+            exit(1);
+        }
+    };
 
-    let alert_endpoint1 = cfg.alert_endpoint.clone();
-    let alert_endpoint2 = cfg.alert_endpoint.clone();
-    let alert_endpoint3 = cfg.alert_endpoint.clone();
+    // Snapshot links are signed, without a configured secret they only work until a restart
+    if cfg.snapshot_server_port != 0 && cfg.snapshot_server_secret.is_empty() {
+        cfg.snapshot_server_secret = format!("{:032x}", rand::thread_rng().gen::<u128>());
+        warn!("SNAPSHOT: snapshot_server_secret is empty, using a random one. Full Image links stop working when the bot restarts");
+    }
+
+    // Check the MQTT round trip without starting any messaging service
+    if test_mqtt {
+        exit(if mqtt_client::self_test(&cfg) { 0 } else { 1 });
+    }
+
+    let mut tx_senders: Vec<TxClient> = Vec::new();
+    // One runtime for every client, or a thread and runtime each
+    let mut client_runtime = ClientRuntime::new(cfg.shared_runtime);
 
     // Spawn messaging service threads
     if cfg.discord_config.enabled {
-        let (tx, rx) = crossbeam_channel::unbounded::<BvrChirpMessage>();
-        tx_senders.push(TxClient {
-            name: "Discord".to_string(),
-            tx
-        });
+        let (tx_client, rx) = TxClient::channel("Discord", &cfg);
+        tx_senders.push(tx_client);
 
-        thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            match rt.block_on(discord_client::run_discord_client(cfg.discord_config.clone(), &alert_endpoint1.as_str(), rx))
-            {
-                Ok(..) => eprintln!("Successfully connected to matrix"),
-                Err(err) => eprintln!("Error connecting to matrix {}", err)
-            };
+        let discord_cfg = cfg.clone();
+        client_runtime.spawn(async move {
+            log_client_exit("DISCORD", discord_client::run_discord_client(discord_cfg, rx).await);
         });
     }
 
     if cfg.matrix_config.enabled {
-        let (tx, rx) = crossbeam_channel::unbounded::<BvrChirpMessage>();
-        tx_senders.push(TxClient {
-            name: "Matrix".to_string(),
-            tx
+        let (tx_client, rx) = TxClient::channel("Matrix", &cfg);
+        tx_senders.push(tx_client);
+
+        let matrix_cfg = cfg.clone();
+        client_runtime.spawn(async move {
+            log_client_exit("MATRIX", matrix_client::run_matrix_client(matrix_cfg, rx).await);
         });
+    }
+
+    if cfg.slack_config.enabled {
+        let (tx_client, rx) = TxClient::channel("Slack", &cfg);
+        tx_senders.push(tx_client);
+
+        let slack_cfg = cfg.clone();
+        client_runtime.spawn(async move {
+            log_client_exit("SLACK", slack_client::run_slack_client(slack_cfg, rx).await);
+        });
+    }
+
+    if cfg.mastodon_config.enabled {
+        let (tx_client, rx) = TxClient::channel("Mastodon", &cfg);
+        tx_senders.push(tx_client);
 
+        let mastodon_cfg = cfg.clone();
+        client_runtime.spawn(async move {
+            log_client_exit("MASTODON", mastodon_client::run_mastodon_client(mastodon_cfg, rx).await);
+        });
+    }
+
+    if cfg.chat_webhook_config.enabled {
+        let (tx_client, rx) = TxClient::channel("Chat_Webhook", &cfg);
+        tx_senders.push(tx_client);
+
+        let chat_webhook_cfg = cfg.clone();
+        client_runtime.spawn(async move {
+            log_client_exit("CHAT WEBHOOK", chat_webhook_client::run_chat_webhook_client(chat_webhook_cfg, rx).await);
+        });
+    }
+
+    if cfg.apprise_config.enabled {
+        let (tx_client, rx) = TxClient::channel("Apprise", &cfg);
+        tx_senders.push(tx_client);
+
+        let apprise_cfg = cfg.clone();
+        client_runtime.spawn(async move {
+            log_client_exit("APPRISE", apprise_client::run_apprise_client(apprise_cfg, rx).await);
+        });
+    }
+
+    // Without a service every alert would be silently dropped
+    if tx_senders.is_empty() {
+        match cfg.no_services_action {
+            NoServicesAction::Exit => {
+                error!("No messaging service is enabled, set enabled=true for at least one of: {}. Exiting.",
+                       SERVICES.join(", "));
+                exit(1);
+            }
+            NoServicesAction::Warn => warn!("No messaging service is enabled, alerts will be received but NOT sent anywhere"),
+        }
+    }
+
+    // Hand alerts to the services in the configured order, optionally waiting for each to finish
+    tx_senders.sort_by_key(|client| cfg.service_rank(&client.name));
+    for client in &mut tx_senders {
+        client.wait_for_delivery = cfg.ordered_delivery;
+    }
+
+    // Re-send saved alerts instead of listening for new ones
+    if let Some(replay_path) = replay_path {
+        let success = replay::run(&replay_path, &cfg.mqtt_config.detection_separators, &tx_senders);
+
+        // Closing the channels lets each client finish sending and stop
+        drop(tx_senders);
+        client_runtime.join();
+        info!("Replay finished. {}", delivery_stats::summary());
+        exit(if success { 0 } else { 1 });
+    }
+
+    // Start the snapshot server for full resolution image links
+    if cfg.snapshot_server_port != 0 {
+        let snapshot_cfg = cfg.clone();
         thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(matrix_client::run_matrix_client(cfg.matrix_config.clone(), &alert_endpoint2.as_str(), rx)).unwrap();
+            if let Err(err) = snapshot_server::run(snapshot_cfg) {
+                error!("SNAPSHOT: Server stopped: {}", err);
+            }
         });
     }
 
-    if cfg.slack_config.enabled {
-        let (tx, rx) = crossbeam_channel::unbounded::<BvrChirpMessage>();
-        tx_senders.push(TxClient {
-            name: "Slack".to_string(),
-            tx
+    // Start the interaction server for "Acknowledge" button clicks
+    if cfg.interaction_server_port != 0 {
+        let interaction_cfg = cfg.clone();
+        thread::spawn(move || {
+            if let Err(err) = interaction_server::run(interaction_cfg) {
+                error!("INTERACTIONS: Server stopped: {}", err);
+            }
         });
+    }
 
+    // Start the web UI listing the recent alerts
+    if cfg.web_ui_port != 0 {
+        recent_alerts::keep_thumbnails();
+        let web_ui_cfg = cfg.clone();
         thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(slack_client::run_slack_client(cfg.slack_config.clone(), &alert_endpoint3.as_str(), rx)).unwrap();
+            if let Err(err) = web_ui::run(web_ui_cfg) {
+                error!("WEB UI: Server stopped: {}", err);
+            }
         });
     }
 
+    // Periodically let the chosen service know the bot is still running
+    if cfg.heartbeat_interval_secs != 0 {
+        match tx_senders.iter().find(|client| client.name.eq_ignore_ascii_case(&cfg.heartbeat_service)) {
+            Some(client) => heartbeat::spawn(Duration::from_secs(cfg.heartbeat_interval_secs),
+                                             cfg.instance_label.clone(), client.clone()),
+            None => warn!("HEARTBEAT: heartbeat_service '{}' is not an enabled service, not sending heartbeats",
+                              cfg.heartbeat_service),
+        }
+    }
+
+    // Tell a fallback contact once when a service keeps failing
+    escalation::setup(&cfg, &tx_senders);
+
+    // Send a summary of the previous day's events to the chosen service every morning
+    if let Some(time) = cfg.daily_summary_local_time() {
+        match tx_senders.iter().find(|client| client.name.eq_ignore_ascii_case(&cfg.daily_summary_service)) {
+            Some(client) => daily_summary::spawn(time, client.clone()),
+            None => warn!("DAILY SUMMARY: daily_summary_service '{}' is not an enabled service, not sending summaries",
+                          cfg.daily_summary_service),
+        }
+    }
+
+    // Report how each service did this session when the bot is stopped with Ctrl+C or SIGTERM
+    if let Err(err) = ctrlc::set_handler(|| {
+        info!("BVR Chirp stopping. {}", delivery_stats::summary());
+        exit(0);
+    }) {
+        warn!("Failed to set up the shutdown handler, delivery stats won't be logged on exit: {}", err);
+    }
+
     // Start the MQTT client
-    mqtt_client::run(cfg.mqtt_config, tx_senders);
-}
\ No newline at end of file
+    mqtt_client::run(cfg, tx_senders);
+}
+
+/// Logs how a messaging client's task ended, a panic on the shared runtime would end it silently
+///
+/// # Arguments
+/// * `name` - Name of the service in logs, ie: "DISCORD"
+/// * `result` - What the client's run function returned
+fn log_client_exit(name: &str, result: bvr_chirp_error::Result<()>) {
+    match result {
+        Ok(()) => info!("{}: Client stopped", name),
+        Err(err) => error!("{}: Client stopped: {}", name, err),
+    }
+}
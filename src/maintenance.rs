@@ -0,0 +1,111 @@
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use crate::bvr_chirp_config::BvrChirpConfig;
+use crate::expiring_keys::ExpiringKeys;
+
+/// Cameras muted over MQTT for maintenance, and until when (`None` until unmuted)
+static MUTED_CAMERAS: Lazy<ExpiringKeys<String>> = Lazy::new(ExpiringKeys::default);
+
+/// Returns true if a camera is being worked on, by a runtime mute or a configured window
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the `maintenance_windows`
+/// * `camera` - Name of the camera, matched case-insensitively
+pub fn is_active(cfg: &BvrChirpConfig, camera: &str) -> bool {
+    let now = chrono::offset::Local::now().naive_local();
+    if cfg.maintenance_windows.iter().any(|window| window.camera.eq_ignore_ascii_case(camera) && window.contains(now)) {
+        return true;
+    }
+
+    MUTED_CAMERAS.contains(&camera.to_lowercase())
+}
+
+/// Applies a command received on the maintenance topic
+///
+/// Commands are `mute <camera>`, `mute <camera> for <minutes>` and `unmute <camera>`.
+/// Camera names may contain spaces, including numbers like `Camera 2`, and are matched
+/// case-insensitively.
+///
+/// # Arguments
+/// * `command` - Payload of the message
+///
+/// # Returns
+/// * `Ok` describing what changed
+/// * `Err` describing why the command isn't understood
+pub fn handle_command(command: &str) -> Result<String, String> {
+    let mut words: Vec<&str> = command.split_whitespace().collect();
+    if words.is_empty() {
+        return Err("Empty maintenance command".to_string());
+    }
+    let action = words.remove(0).to_lowercase();
+
+    // Only `for <minutes>` sets a duration, a trailing number alone is part of the camera name
+    let minutes = match action.as_str() {
+        "mute" if words.len() >= 2 && words[words.len() - 2].eq_ignore_ascii_case("for") => {
+            let minutes = words[words.len() - 1].parse::<u64>()
+                .map_err(|_| format!("Maintenance command '{}' needs a number of minutes after 'for'", command.trim()))?;
+            words.truncate(words.len() - 2);
+            Some(minutes)
+        }
+        _ => None,
+    };
+    let camera = words.join(" ");
+    if camera.is_empty() {
+        return Err(format!("Maintenance command '{}' doesn't name a camera", command.trim()));
+    }
+    let until = match minutes {
+        Some(minutes) => Some(minutes.checked_mul(60)
+            .and_then(|secs| Instant::now().checked_add(Duration::from_secs(secs)))
+            .ok_or_else(|| format!("Maintenance command '{}' mutes for longer than supported", command.trim()))?),
+        None => None,
+    };
+
+    match action.as_str() {
+        "mute" => {
+            MUTED_CAMERAS.insert(camera.to_lowercase(), until);
+            Ok(match minutes {
+                Some(minutes) => format!("Muted {} camera for {} minute(s) of maintenance", camera, minutes),
+                None => format!("Muted {} camera for maintenance until it's unmuted", camera),
+            })
+        }
+        "unmute" if MUTED_CAMERAS.remove(&camera.to_lowercase()) => Ok(format!("Unmuted {} camera", camera)),
+        "unmute" => Ok(format!("{} camera wasn't muted", camera)),
+        _ => Err(format!("Unknown maintenance command '{}', expected mute or unmute", action)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mute_with_minutes() {
+        assert_eq!(handle_command("mute Side Gate for 30"), Ok("Muted Side Gate camera for 30 minute(s) of maintenance".to_string()));
+        assert_eq!(handle_command("unmute side gate"), Ok("Unmuted side gate camera".to_string()));
+    }
+
+    #[test]
+    fn mute_too_long_is_rejected() {
+        assert!(handle_command("mute Porch for 999999999999999999").is_err());
+        assert_eq!(handle_command("unmute Porch"), Ok("Porch camera wasn't muted".to_string()));
+    }
+
+    #[test]
+    fn trailing_number_is_part_of_the_camera_name() {
+        assert_eq!(handle_command("mute Camera 2"), Ok("Muted Camera 2 camera for maintenance until it's unmuted".to_string()));
+        assert_eq!(handle_command("mute Camera 3 for 15"), Ok("Muted Camera 3 camera for 15 minute(s) of maintenance".to_string()));
+        assert_eq!(handle_command("unmute camera 2"), Ok("Unmuted camera 2 camera".to_string()));
+        assert_eq!(handle_command("unmute Camera 3"), Ok("Unmuted Camera 3 camera".to_string()));
+    }
+
+    #[test]
+    fn mute_for_without_minutes_is_rejected() {
+        assert!(handle_command("mute Porch for soon").is_err());
+    }
+
+    #[test]
+    fn command_without_camera_is_rejected() {
+        assert!(handle_command("mute").is_err());
+        assert!(handle_command("").is_err());
+    }
+}
@@ -1,11 +1,48 @@
 use once_cell::sync::Lazy;
 
+/// Escapes text so it can be put between the quotes of a JSON string in a template
+pub(crate) fn json_escape(text: &str) -> String {
+    let quoted = serde_json::to_string(text).unwrap_or_default();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+/// Shortens text to at most `max_chars` characters, ending it with an ellipsis when it's cut
+pub(crate) fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    if max_chars == 0 {
+        return String::new();
+    }
+    let mut truncated: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Removes emoji and other non-ASCII characters from rendered text, for bridges to SMS or IRC
+///
+/// Spaces left doubled or at the start of a line by a removed emoji are tidied up.
+pub(crate) fn plain_ascii(text: &str) -> String {
+    text.split('\n')
+        .map(|line| line.chars()
+            .filter(|c| c.is_ascii())
+            .collect::<String>()
+            .split(' ')
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join(" "))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 pub(crate) const MATRIX_TEMPLATE: Lazy<String> = Lazy::new(||String::from(r#"{
   "msgtype": "m.room.message",
-  "body": "Detection on <CAMERA_NAME> camera\n\nDetections: <DETECTIONS>\nTime <TIME>",
-  "formatted_body": "<strong>Detection on <CAMERA_NAME> camera</strong><br><br><strong>Detections</strong><br><DETECTIONS><br><br><strong>Time</strong><br><TIME>",
+  "body": "<TITLE><DESCRIPTION>\n\n<DETECTIONS_LABEL>: <COUNT>\n<TIME_LABEL>: <TIME>\n<TODAY_LABEL>: <DAILY_COUNT_TEXT><MAP_LINK><LIVE_LINK>",
+  "formatted_body": "<strong><TITLE></strong><DESCRIPTION_HTML><br><br><strong><DETECTIONS_LABEL></strong><br><COUNT_HTML><br><br><strong><TIME_LABEL></strong><br><TIME><br><br><strong><TODAY_LABEL></strong><br><DAILY_COUNT_TEXT><MAP_LINK_HTML><LIVE_LINK_HTML><DETAILS_HTML>",
+  "markdown_body": "**<TITLE>**<DESCRIPTION_MARKDOWN>\n\n**<DETECTIONS_LABEL>:** <COUNT_MARKDOWN>  \n**<TIME_LABEL>:** <TIME>  \n**<TODAY_LABEL>:** <DAILY_COUNT_TEXT><MAP_LINK_MARKDOWN><LIVE_LINK_MARKDOWN>",
   "format": "org.matrix.custom.html",
-  "url": "<IMG_URI>"
+  "url": "<IMG_URI>",
+  "filename": "<FILENAME>"
 }"#));
 
 //"info": {
@@ -30,14 +67,14 @@ pub(crate) static SLACK_TEMPLATE: Lazy<String> = Lazy::new(||String::from("
 		\"type\": \"section\",
 		\"text\": {
 			\"type\": \"mrkdwn\",
-			\"text\": \"Detection on <CAMERA_NAME> camera\"
+			\"text\": \"<TITLE><DESCRIPTION>\"
 		},
 		\"accessory\": {
 			\"type\": \"button\",
 			\"text\": {
 				\"type\": \"plain_text\",
-				\"text\": \"View Alert\",
-				\"emoji\": false
+				\"text\": \"<BUTTON_TEXT>\",
+				\"emoji\": <BUTTON_EMOJI>
 			},
 			\"value\": \"click_me_123\",
 			\"url\": \"<ENDPOINT_URL>\",
@@ -49,7 +86,7 @@ pub(crate) static SLACK_TEMPLATE: Lazy<String> = Lazy::new(||String::from("
 		\"fields\": [
 			{
 				\"type\": \"mrkdwn\",
-				\"text\": \"Time\"
+				\"text\": \"<TIME_LABEL>\"
 			},
 			{
 				\"type\": \"plain_text\",
@@ -58,13 +95,58 @@ pub(crate) static SLACK_TEMPLATE: Lazy<String> = Lazy::new(||String::from("
 			},
 			{
 				\"type\": \"mrkdwn\",
-				\"text\": \"Detections\"
+				\"text\": \"<DETECTIONS_LABEL>\"
 			},
 			{
 				\"type\": \"plain_text\",
-				\"text\": \"<DETECTIONS>\",
+				\"text\": \"<COUNT>\",
+				\"emoji\": false
+			},
+			{
+				\"type\": \"mrkdwn\",
+				\"text\": \"<TODAY_LABEL>\"
+			},
+			{
+				\"type\": \"plain_text\",
+				\"text\": \"<DAILY_COUNT_TEXT>\",
 				\"emoji\": false
 			}
 		]
 	}
-]"));
\ No newline at end of file
+]"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_keeps_short_text() {
+        assert_eq!(truncate("Front Door", 10), "Front Door");
+        assert_eq!(truncate("", 0), "");
+    }
+
+    #[test]
+    fn truncate_cuts_long_text_with_an_ellipsis() {
+        assert_eq!(truncate("Front Door", 6), "Front…");
+        assert_eq!(truncate("Front Door", 1), "…");
+        assert_eq!(truncate("Front Door", 0), "");
+    }
+
+    #[test]
+    fn truncate_counts_characters_not_bytes() {
+        assert_eq!(truncate("Caméra été", 10), "Caméra été");
+        assert_eq!(truncate("🚶🚗🐕🐈", 3), "🚶🚗…");
+    }
+
+    #[test]
+    fn plain_ascii_removes_emoji_and_tidies_spaces() {
+        assert_eq!(plain_ascii("🚶 Person at  Front 🚪 Door"), "Person at Front Door");
+        assert_eq!(plain_ascii("Time: 12:00\n📷 Camera: Café"), "Time: 12:00\nCamera: Caf");
+    }
+
+    #[test]
+    fn plain_ascii_keeps_ascii_text_and_empty_lines() {
+        assert_eq!(plain_ascii("Motion alert\n\nCamera: Gate"), "Motion alert\n\nCamera: Gate");
+        assert_eq!(plain_ascii("🔔"), "");
+    }
+}
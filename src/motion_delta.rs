@@ -0,0 +1,21 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use once_cell::sync::Lazy;
+use crate::image_processing;
+
+/// Last alert image of each camera, compared with its next alert
+static PREVIOUS_FRAMES: Lazy<Mutex<HashMap<String, Vec<u8>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Compares an alert image with the camera's previous one, keeping it for the next alert
+///
+/// # Arguments
+/// * `camera` - Name of the camera the image is from
+/// * `image` - Encoded alert image
+///
+/// # Returns
+/// * `Some(image)` with the previous and current frames side by side, what changed highlighted
+/// * `None` for the camera's first alert, or if either image can't be decoded
+pub fn compare(camera: &str, image: &[u8]) -> Option<Vec<u8>> {
+    let previous = PREVIOUS_FRAMES.lock().ok()?.insert(camera.to_string(), image.to_vec())?;
+    image_processing::motion_delta(&previous, image)
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::bvr_chirp_message::BvrChirpMessage;
+use log::info;
+
+/// Target, service (with `topic_routing`) and debug flag an alert is sent with, only alerts
+/// sharing all three can be sent as one
+type GroupKey = (String, Option<String>, bool);
+
+/// Alerts of one event collected so far, possibly from several cameras
+struct Group {
+    /// When the window for this event closes
+    deadline: Instant,
+    /// First alert of the event, the others are attached to it
+    first: BvrChirpMessage,
+    /// Alerts from the other cameras
+    related: Vec<BvrChirpMessage>,
+}
+
+impl Group {
+    /// Returns true if one of the alerts in the group is from the camera
+    fn has_camera(&self, camera: &str) -> bool {
+        self.first.camera_name == camera || self.related.iter().any(|alert| alert.camera_name == camera)
+    }
+}
+
+/// Key of the group an alert can join
+fn group_key(message: &BvrChirpMessage) -> GroupKey {
+    (message.target.clone(), message.service.clone(), message.debug)
+}
+
+/// Starts a thread that collapses alerts from different cameras arriving together into one
+///
+/// The first alert opens a window of `window`, alerts from other cameras arriving
+/// before it closes are attached to it as `related` and sent as a single alert. An
+/// alert from a camera already in the group starts a new event. Alerts going to
+/// another target or service, or test alerts, are grouped separately, so one is never
+/// sent where the first alert of its group goes instead of its own destination.
+///
+/// # Arguments
+/// * `window` - How long to collect alerts of an event before sending
+/// * `verbose` - Log each collapsed event
+/// * `next` - Where the (possibly combined) alerts go once their window closes
+///
+/// # Returns
+/// * Sender to pass every alert to
+pub fn spawn(window: Duration, verbose: bool, next: Box<dyn Fn(BvrChirpMessage) + Send>) -> Sender<BvrChirpMessage> {
+    let (tx, rx) = mpsc::channel::<BvrChirpMessage>();
+
+    thread::spawn(move || {
+        let mut groups: HashMap<GroupKey, Group> = HashMap::new();
+
+        loop {
+            let timeout = groups.values()
+                .map(|group| group.deadline.saturating_duration_since(Instant::now()))
+                .min()
+                .unwrap_or(window);
+
+            match rx.recv_timeout(timeout) {
+                Ok(message) => {
+                    let key = group_key(&message);
+                    match groups.get_mut(&key) {
+                        Some(open) if !open.has_camera(&message.camera_name) => open.related.push(message),
+                        _ => {
+                            if let Some(done) = groups.remove(&key) {
+                                send_group(done, verbose, &next);
+                            }
+                            groups.insert(key, Group {
+                                deadline: Instant::now() + window,
+                                first: message,
+                                related: Vec::new(),
+                            });
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    for (_, done) in groups.drain() {
+                        send_group(done, verbose, &next);
+                    }
+                    break;
+                }
+            }
+
+            let now = Instant::now();
+            let expired: Vec<GroupKey> = groups.iter()
+                .filter(|(_, group)| group.deadline <= now)
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in expired {
+                if let Some(done) = groups.remove(&key) {
+                    send_group(done, verbose, &next);
+                }
+            }
+        }
+    });
+
+    tx
+}
+
+/// Passes the first alert of a group on, carrying the alerts of the other cameras
+fn send_group(group: Group, verbose: bool, next: &dyn Fn(BvrChirpMessage)) {
+    let mut message = group.first;
+    message.related = group.related;
+    if verbose && !message.related.is_empty() {
+        info!("MULTICAM: Collapsing alerts from {} cameras into one: {}",
+              message.related.len() + 1, message.camera_names().join(", "));
+    }
+    next(message);
+}
@@ -0,0 +1,130 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use once_cell::sync::Lazy;
+use crate::bvr_chirp_config::BvrChirpConfig;
+use crate::bvr_chirp_message::BvrChirpMessage;
+use crate::image_processing;
+
+/// Number of sent alerts kept in memory
+pub const RECENT_ALERTS_CAPACITY: usize = 25;
+
+/// Alerts shown when no count is requested
+pub const DEFAULT_RECENT_COUNT: usize = 5;
+
+/// Summary of an alert that a messaging service delivered
+#[derive(Clone)]
+pub struct RecentAlert {
+    pub camera_name: String,
+    pub detections: String,
+    pub time: String,
+    pub db_id: String,
+    /// Small JPEG preview of the alert image, empty unless thumbnails are enabled
+    pub thumbnail: Vec<u8>,
+    /// When the alert was first delivered
+    pub recorded_at: Instant,
+    /// When the alert was received, tells apart the same alert delivered by several services
+    received_at: Instant,
+}
+
+/// Most recently sent alerts, newest last
+static RECENT_ALERTS: Lazy<Mutex<VecDeque<RecentAlert>>> =
+    Lazy::new(|| Mutex::new(VecDeque::with_capacity(RECENT_ALERTS_CAPACITY)));
+
+/// Whether a thumbnail is kept with each alert, only the web UI shows them
+static KEEP_THUMBNAILS: AtomicBool = AtomicBool::new(false);
+
+/// Keeps a thumbnail of the image with every alert recorded from now on
+pub fn keep_thumbnails() {
+    KEEP_THUMBNAILS.store(true, Ordering::Relaxed);
+}
+
+/// Remembers a delivered alert, dropping the oldest once the buffer is full
+///
+/// Every service reports its deliveries, an alert already delivered by another
+/// service is only kept once. System messages are not alerts and are skipped.
+///
+/// # Arguments
+/// * `bvr_msg` - The alert that was delivered
+pub fn record(bvr_msg: &BvrChirpMessage) {
+    if bvr_msg.system_text.is_some() || is_recorded(bvr_msg) {
+        return;
+    }
+
+    // Decoding the image is the slow part, do it before taking the lock
+    let thumbnail = if KEEP_THUMBNAILS.load(Ordering::Relaxed) {
+        image_processing::thumbnail(&bvr_msg.image).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    if let Ok(mut recent) = RECENT_ALERTS.lock() {
+        // Another service may have delivered the alert while the thumbnail was made
+        if recent.iter().any(|alert| is_same_alert(alert, bvr_msg)) {
+            return;
+        }
+        if recent.len() == RECENT_ALERTS_CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back(RecentAlert {
+            camera_name: bvr_msg.camera_name.clone(),
+            detections: bvr_msg.detections.clone(),
+            time: bvr_msg.time.clone(),
+            db_id: bvr_msg.db_id.clone(),
+            thumbnail,
+            recorded_at: Instant::now(),
+            received_at: bvr_msg.received_at,
+        });
+    }
+}
+
+/// Whether the alert is already in the buffer
+fn is_recorded(bvr_msg: &BvrChirpMessage) -> bool {
+    match RECENT_ALERTS.lock() {
+        Ok(recent) => recent.iter().any(|alert| is_same_alert(alert, bvr_msg)),
+        Err(_) => false,
+    }
+}
+
+/// Whether a recorded alert and a message are the same alert
+fn is_same_alert(alert: &RecentAlert, bvr_msg: &BvrChirpMessage) -> bool {
+    alert.received_at == bvr_msg.received_at
+        && alert.db_id == bvr_msg.db_id
+        && alert.camera_name == bvr_msg.camera_name
+}
+
+/// Returns up to `count` of the most recent alerts, newest first
+pub fn latest(count: usize) -> Vec<RecentAlert> {
+    match RECENT_ALERTS.lock() {
+        Ok(recent) => recent.iter().rev().take(count).cloned().collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Formats the most recent alerts as a markdown list for a chat reply
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig used to build the links to the recordings
+/// * `count` - Maximum number of alerts to list
+/// * `max_len` - Maximum length of the reply, older alerts are left out to fit
+///
+/// # Returns
+/// * One line per alert with its time, camera, detections and link
+pub fn summary(cfg: &BvrChirpConfig, count: usize, max_len: usize) -> String {
+    let alerts = latest(count);
+    if alerts.is_empty() {
+        return "No alerts have been sent yet".to_string();
+    }
+
+    let mut summary = String::new();
+    for alert in alerts {
+        let line = format!("- {} **{}** {} - <{}>\n", alert.time, alert.camera_name, alert.detections,
+                           cfg.recording_link(&alert.db_id, &alert.camera_name));
+        if summary.len() + line.len() > max_len {
+            break;
+        }
+        summary.push_str(&line);
+    }
+    summary.trim_end().to_string()
+}
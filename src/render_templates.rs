@@ -0,0 +1,92 @@
+use serde_json::Value;
+use crate::bvr_chirp_config::BvrChirpConfig;
+use crate::bvr_chirp_message::BvrChirpMessage;
+use crate::clients::{apprise_client, chat_webhook_client, discord_client, mastodon_client, matrix_client, slack_client};
+
+/// Renders every service's message for a sample alert and prints it to stdout
+///
+/// Nothing is sent and no connections are made, so templates can be checked
+/// without credentials. Output that should be JSON is parsed to make sure it's
+/// well-formed.
+///
+/// # Arguments
+/// * `cfg` - Loaded configuration the templates are rendered with
+///
+/// # Returns
+/// * `true` if every template rendered to valid output
+/// * `false` if any rendered JSON is malformed
+pub fn run(cfg: &BvrChirpConfig) -> bool {
+    let bvr_msg = sample_message();
+    let mut valid = true;
+
+    println!("===== Slack blocks =====");
+    let slack_msg = slack_client::build_message(cfg, &cfg.slack_config.bot_name, "<IMG_ID>", &[], &bvr_msg);
+    valid &= print_json("Slack", &slack_msg);
+
+    println!("===== Matrix message =====");
+    let matrix_msg = matrix_client::build_message(cfg, "mxc://example.org/<IMG_ID>", &cfg.upload_filename(&bvr_msg, &bvr_msg.image), &bvr_msg);
+    match serde_json::from_str::<Value>(&matrix_msg) {
+        Ok(json) => {
+            println!("body:\n{}\n", json["body"].as_str().unwrap_or_default());
+            println!("formatted_body:\n{}\n", json["formatted_body"].as_str().unwrap_or_default());
+            println!("markdown_body:\n{}", json["markdown_body"].as_str().unwrap_or_default());
+        }
+        Err(err) => {
+            println!("{}", matrix_msg);
+            eprintln!("Error: Matrix template is not valid JSON: {}", err);
+            valid = false;
+        }
+    }
+
+    println!("===== Discord embed =====");
+    let embed = discord_client::build_embed(cfg, &bvr_msg);
+    match serde_json::to_string_pretty(&embed) {
+        Ok(json) => println!("{}", json),
+        Err(err) => {
+            eprintln!("Error: Failed to serialize Discord embed: {}", err);
+            valid = false;
+        }
+    }
+
+    println!("===== Mastodon status =====");
+    println!("{}", mastodon_client::build_status(cfg, &bvr_msg));
+
+    println!("===== Chat webhook payload =====");
+    let payload = chat_webhook_client::build_payload(cfg, &bvr_msg);
+    valid &= print_json("Chat webhook", &payload.to_string());
+
+    println!("===== Apprise notification =====");
+    let (title, body) = apprise_client::build_notification(cfg, &bvr_msg);
+    println!("title: {}\nbody:\n{}", title, body);
+
+    valid
+}
+
+/// Pretty prints rendered JSON, or the raw output and the parse error if it's malformed
+fn print_json(service: &str, rendered: &str) -> bool {
+    match serde_json::from_str::<Value>(rendered) {
+        Ok(json) => {
+            println!("{}", serde_json::to_string_pretty(&json).unwrap_or_else(|_| rendered.to_string()));
+            true
+        }
+        Err(err) => {
+            println!("{}", rendered);
+            eprintln!("Error: {} template is not valid JSON: {}", service, err);
+            false
+        }
+    }
+}
+
+/// An alert with representative values for every field
+fn sample_message() -> BvrChirpMessage {
+    let mut message = BvrChirpMessage::new(
+        "<target>".to_string(),
+        "FrontDoor".to_string(),
+        "person, car".to_string(),
+        "@195238907624039".to_string(),
+        chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        Vec::new(),
+    );
+    message.daily_count = Some(7);
+    message
+}
@@ -0,0 +1,68 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde_json::Value;
+use crate::bvr_chirp_message::BvrChirpMessage;
+use crate::clients::mqtt_client::{self, TxClient};
+use log::{error, info, warn};
+
+/// Re-sends saved alerts to every enabled messaging client
+///
+/// Reads alerts in the MQTT payload format, such as the files written to
+/// `dead_letter_path`, and passes them on as if they had just arrived. Filters
+/// like acknowledgements and suppressed detections don't apply to replayed alerts.
+///
+/// # Arguments
+/// * `path` - A saved alert JSON file, or a directory of them
+/// * `separators` - Characters that separate individual detections
+/// * `tx_clients` - Channel senders of the messaging clients
+///
+/// # Returns
+/// * `true` if every file was read and passed on
+/// * `false` if any file couldn't be read or parsed
+pub fn run(path: &str, separators: &str, tx_clients: &[TxClient]) -> bool {
+    let files = match alert_files(Path::new(path)) {
+        Ok(files) => files,
+        Err(err) => {
+            error!("REPLAY: Failed to read {}: {}", path, err);
+            return false;
+        }
+    };
+
+    let mut sent = 0;
+    for file in &files {
+        match read_alert(file, separators) {
+            Ok(message) => {
+                info!("REPLAY: Sending alert from {} camera at {} ({})",
+                         message.camera_name, message.time, file.display());
+                mqtt_client::dispatch_delayed(message, tx_clients, false, Duration::ZERO);
+                sent += 1;
+            }
+            Err(err) => warn!("REPLAY: Skipping {}: {}", file.display(), err),
+        }
+    }
+
+    info!("REPLAY: Passed on {} of {} alert(s)", sent, files.len());
+    sent == files.len()
+}
+
+/// Lists the JSON files to replay, sorted by name so they're sent in the order they were saved
+fn alert_files(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files: Vec<PathBuf> = fs::read_dir(path)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| file.is_file() && file.extension().is_some_and(|extension| extension == "json"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Reads a saved alert from a JSON file
+fn read_alert(file: &Path, separators: &str) -> Result<BvrChirpMessage, String> {
+    let contents = fs::read_to_string(file).map_err(|e| e.to_string())?;
+    let payload: Value = serde_json::from_str(&contents).map_err(|e| format!("Failed to parse JSON: {}", e))?;
+    BvrChirpMessage::from_payload_json(&payload, separators)
+}
@@ -0,0 +1,125 @@
+use std::error::Error;
+use std::time::Duration;
+use hmac::{Hmac, Mac};
+use reqwest::blocking::Client;
+use sha2::Sha256;
+use tiny_http::{Header, Method, Request, Response};
+use crate::address;
+use crate::bvr_chirp_config::{is_valid_db_id, BvrChirpConfig};
+use log::{error, info, warn};
+
+/// Runs a small HTTP server that proxies full resolution alert images from Blue Iris
+///
+/// Serves `GET /snapshot/<db_id>?sig=<signature>` by fetching the full size JPEG of the
+/// alert from the Blue Iris server, so a link in the alert message can show the full frame
+/// without opening the NVR app. Only requests signed with `snapshot_server_secret`, like
+/// the links in the alerts, are served. Blocks for as long as the server is running.
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the port, secret and Blue Iris endpoint and credentials
+///
+/// # Returns
+/// * `Err` if the server can't bind to the port
+pub fn run(cfg: BvrChirpConfig) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let server = address::bind_server(cfg.snapshot_server_port)?;
+    let client = Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()?;
+
+    info!("SNAPSHOT: Server listening on port {}", cfg.snapshot_server_port);
+
+    for request in server.incoming_requests() {
+        if let Err(err) = handle_request(&cfg, &client, request) {
+            error!("SNAPSHOT: Failed to respond to request: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles a single request, responding with the image or an error status
+fn handle_request(cfg: &BvrChirpConfig, client: &Client, request: Request) -> std::io::Result<()> {
+    let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+    let db_id = path.strip_prefix("/snapshot/").map(|db_id| db_id.to_string());
+    let db_id = match db_id {
+        Some(db_id) if *request.method() == Method::Get && is_valid_db_id(&db_id) => db_id,
+        _ => return request.respond(Response::from_string("Not found").with_status_code(404)),
+    };
+
+    // Record IDs are easy to guess, only the links sent with an alert open its frame
+    if !has_valid_signature(&cfg.snapshot_server_secret, &db_id, query) {
+        warn!("SNAPSHOT: Refusing request for {} with a missing or invalid signature", db_id);
+        return request.respond(Response::from_string("Forbidden").with_status_code(403));
+    }
+
+    match fetch_snapshot(client, &cfg.full_jpeg_url(&db_id)) {
+        Ok(image) => {
+            let content_type = Header::from_bytes("Content-Type", "image/jpeg")
+                .expect("static header is valid");
+            request.respond(Response::from_data(image).with_header(content_type))
+        }
+        Err(err) => {
+            error!("SNAPSHOT: Failed to fetch image for {}: {}", db_id, describe_error(&err));
+            request.respond(Response::from_string("Failed to fetch snapshot").with_status_code(502))
+        }
+    }
+}
+
+/// Fetches the full size JPEG for an alert from the Blue Iris server
+fn fetch_snapshot(client: &Client, url: &str) -> reqwest::Result<Vec<u8>> {
+    let response = client
+        .get(url)
+        .send()?
+        .error_for_status()?;
+
+    Ok(response.bytes()?.to_vec())
+}
+
+/// Text of a failed request without its URL, which carries the Blue Iris credentials
+fn describe_error(err: &reqwest::Error) -> String {
+    let text = err.to_string();
+    match err.url() {
+        Some(url) => text.replace(url.as_str(), "<alert_endpoint>"),
+        None => text,
+    }
+}
+
+/// Returns true if the query's `sig` parameter is the record ID signed with the secret
+///
+/// # Arguments
+/// * `secret` - `snapshot_server_secret`
+/// * `db_id` - Blue Iris record ID from the path
+/// * `query` - Query string of the request, without the `?`
+fn has_valid_signature(secret: &str, db_id: &str, query: &str) -> bool {
+    let signature = url::form_urlencoded::parse(query.as_bytes())
+        .find(|(name, _)| name == "sig")
+        .and_then(|(_, signature)| hex::decode(signature.as_bytes()).ok());
+    let signature = match signature {
+        Some(signature) => signature,
+        None => return false,
+    };
+
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(db_id.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_from_the_alert_link_is_valid() {
+        let cfg = BvrChirpConfig { snapshot_server_secret: "s3cret".to_string(), ..BvrChirpConfig::default() };
+        let query = format!("sig={}", cfg.snapshot_signature("@123"));
+
+        assert!(has_valid_signature("s3cret", "@123", &query));
+        assert!(!has_valid_signature("s3cret", "@124", &query));
+        assert!(!has_valid_signature("other", "@123", &query));
+        assert!(!has_valid_signature("s3cret", "@123", ""));
+        assert!(!has_valid_signature("s3cret", "@123", "sig=zz"));
+    }
+}
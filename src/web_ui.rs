@@ -0,0 +1,100 @@
+use std::error::Error;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use tiny_http::{Header, Method, Request, Response};
+use crate::address;
+use crate::bvr_chirp_config::BvrChirpConfig;
+use crate::recent_alerts::{self, RecentAlert, RECENT_ALERTS_CAPACITY};
+use log::{error, info};
+
+/// Seconds between automatic reloads of the page
+const REFRESH_SECS: u32 = 30;
+
+/// Runs an HTTP server with a page listing the most recent alerts
+///
+/// Serves `GET /` with the alerts from the same buffer as the Discord `/recent`
+/// command, each with a thumbnail, its camera, detections, time and a link to the
+/// recording. Blocks for as long as the server is running.
+///
+/// # Arguments
+/// * `cfg` - BvrChirpConfig with the port, instance label and Blue Iris endpoint
+///
+/// # Returns
+/// * `Err` if the server can't bind to the port
+pub fn run(cfg: BvrChirpConfig) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let server = address::bind_server(cfg.web_ui_port)?;
+
+    info!("WEB UI: Server listening on port {}", cfg.web_ui_port);
+
+    for request in server.incoming_requests() {
+        if let Err(err) = handle_request(&cfg, request) {
+            error!("WEB UI: Failed to respond to request: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles a single request, responding with the page or a 404
+fn handle_request(cfg: &BvrChirpConfig, request: Request) -> std::io::Result<()> {
+    if *request.method() != Method::Get || request.url() != "/" {
+        return request.respond(Response::from_string("Not found").with_status_code(404));
+    }
+
+    let content_type = Header::from_bytes("Content-Type", "text/html; charset=utf-8")
+        .expect("static header is valid");
+    let page = render_page(cfg, &recent_alerts::latest(RECENT_ALERTS_CAPACITY));
+    request.respond(Response::from_string(page).with_header(content_type))
+}
+
+/// Renders the HTML page listing the alerts, newest first
+fn render_page(cfg: &BvrChirpConfig, alerts: &[RecentAlert]) -> String {
+    let title = if cfg.instance_label.is_empty() {
+        "BVR Chirp".to_string()
+    } else {
+        format!("BVR Chirp - {}", htmlescape::encode_minimal(&cfg.instance_label))
+    };
+
+    let rows = if alerts.is_empty() {
+        "<p>No alerts have been sent yet</p>".to_string()
+    } else {
+        alerts.iter().map(|alert| render_alert(cfg, alert)).collect()
+    };
+
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta http-equiv="refresh" content="{refresh}">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 1em; background: #f4f4f4; }}
+.alert {{ display: flex; gap: 1em; align-items: center; background: #fff; padding: 0.5em; margin-bottom: 0.5em; border-radius: 4px; }}
+.alert img {{ width: 160px; border-radius: 4px; }}
+.thumbnail {{ width: 160px; text-align: center; color: #888; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+{rows}
+</body>
+</html>"#, refresh = REFRESH_SECS, title = title, rows = rows)
+}
+
+/// Renders one alert with its thumbnail, details and link to the recording
+fn render_alert(cfg: &BvrChirpConfig, alert: &RecentAlert) -> String {
+    let thumbnail = if alert.thumbnail.is_empty() {
+        "<div class=\"thumbnail\">No image</div>".to_string()
+    } else {
+        format!("<img src=\"data:image/jpeg;base64,{}\" alt=\"{} camera\">",
+                BASE64_STANDARD.encode(&alert.thumbnail), htmlescape::encode_attribute(&alert.camera_name))
+    };
+
+    format!("<div class=\"alert\">{}<div><strong>{}</strong><br>{}<br>{}<br><a href=\"{}\">View Alert</a></div></div>\n",
+            thumbnail,
+            htmlescape::encode_minimal(&alert.camera_name),
+            htmlescape::encode_minimal(&alert.detections),
+            htmlescape::encode_minimal(&alert.time),
+            htmlescape::encode_attribute(&cfg.public_recording_link(&alert.db_id, &alert.camera_name)))
+}